@@ -0,0 +1,458 @@
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::message::Message;
+use crate::server::{Handler, Request};
+
+/** ACL の規則に一致したクライアントの扱い */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /** 問い合わせに答える */
+    Allow,
+    /** 何も返さずに捨てる */
+    Deny,
+    /** REFUSED を返す */
+    Refuse,
+}
+
+impl FromStr for Action {
+    type Err = ();
+
+    fn from_str(text: &str) -> Result<Self, ()> {
+        match text {
+            "allow" => Ok(Self::Allow),
+            "deny" => Ok(Self::Deny),
+            "refuse" => Ok(Self::Refuse),
+            _ => Err(()),
+        }
+    }
+}
+
+/** アドレスの範囲 (CIDR)。プレフィックス長のないものは 1 つのアドレス */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Network {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+impl Network {
+    /** プレフィックス長がアドレスの長さを超えるなら None。プレフィックスより後ろのビットは 0 にする */
+    pub fn new(address: IpAddr, prefix_len: u8) -> Option<Self> {
+        let address = match address {
+            IpAddr::V4(ipv4) if prefix_len <= 32 => {
+                IpAddr::V4(Ipv4Addr::from(u32::from(ipv4) & ipv4_mask(prefix_len)))
+            }
+            IpAddr::V6(ipv6) if prefix_len <= 128 => {
+                IpAddr::V6(Ipv6Addr::from(u128::from(ipv6) & ipv6_mask(prefix_len)))
+            }
+            _ => return None,
+        };
+        Some(Self {
+            address,
+            prefix_len,
+        })
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.address, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                u32::from(address) & ipv4_mask(self.prefix_len) == u32::from(network)
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                u128::from(address) & ipv6_mask(self.prefix_len) == u128::from(network)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = ();
+
+    fn from_str(text: &str) -> Result<Self, ()> {
+        let (address, prefix_len) = match text.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (text, None),
+        };
+        let address: IpAddr = address.parse().map_err(|_| ())?;
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.parse().map_err(|_| ())?,
+            None if address.is_ipv4() => 32,
+            None => 128,
+        };
+        Self::new(address, prefix_len).ok_or(())
+    }
+}
+
+/** 最も長く一致した規則の動作を選ぶ。どの規則にも一致しなければ default */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Acl {
+    pub rules: Vec<(Network, Action)>,
+    pub default: Action,
+}
+
+impl Acl {
+    pub fn decide(&self, client: IpAddr) -> Action {
+        self.rules
+            .iter()
+            .filter(|(network, _)| network.contains(client))
+            // 同じ長さなら先に書かれた規則
+            .min_by_key(|(network, _)| u8::MAX - network.prefix_len)
+            .map_or(self.default, |(_, action)| *action)
+    }
+}
+
+/** clients に含まれるクライアントには acl を使う */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct View {
+    pub name: String,
+    pub clients: Vec<Network>,
+    pub acl: Acl,
+}
+
+/** クライアントごとに、最初に一致したビューの ACL で問い合わせを受け付けるかを決める */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessControl {
+    pub views: Vec<View>,
+}
+
+impl AccessControl {
+    /** ループバックのクライアントにだけ答え、ほかには REFUSED を返す */
+    pub fn localhost() -> Self {
+        Self {
+            views: vec![View {
+                name: "default".to_string(),
+                clients: vec!["0.0.0.0/0".parse().unwrap(), "::/0".parse().unwrap()],
+                acl: Acl {
+                    rules: vec![
+                        ("127.0.0.0/8".parse().unwrap(), Action::Allow),
+                        ("::1".parse().unwrap(), Action::Allow),
+                    ],
+                    default: Action::Refuse,
+                },
+            }],
+        }
+    }
+
+    /**
+     * 1 行に 1 つずつ、"allow / deny / refuse CIDR..." の規則と、一致しないときの "default 動作" を書く。
+     * "view 名前 CIDR..." 以降の行はそのビューの規則になる。view より前の規則は、どのビューにも一致しない
+     * クライアントに使う。default の既定は refuse。'#' 以降はコメント。解釈できない行があれば None
+     */
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut global = View {
+            name: "default".to_string(),
+            clients: vec!["0.0.0.0/0".parse().unwrap(), "::/0".parse().unwrap()],
+            acl: Acl {
+                rules: Vec::new(),
+                default: Action::Refuse,
+            },
+        };
+        let mut views: Vec<View> = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let parsed = match words.as_slice() {
+                [] => Some(()),
+                ["view", name, clients @ ..] if !clients.is_empty() => {
+                    networks(clients).map(|clients| {
+                        views.push(View {
+                            name: name.to_string(),
+                            clients,
+                            acl: Acl {
+                                rules: Vec::new(),
+                                default: Action::Refuse,
+                            },
+                        })
+                    })
+                }
+                ["default", action] => action.parse().ok().map(|action| {
+                    views.last_mut().unwrap_or(&mut global).acl.default = action;
+                }),
+                [action, clients @ ..] if !clients.is_empty() => {
+                    match (action.parse::<Action>(), networks(clients)) {
+                        (Ok(action), Some(clients)) => {
+                            let view = views.last_mut().unwrap_or(&mut global);
+                            view.acl
+                                .rules
+                                .extend(clients.into_iter().map(|network| (network, action)));
+                            Some(())
+                        }
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            if parsed.is_none() {
+                println!("{} 行目を解釈できません: {:?}", index + 1, line.trim());
+                return None;
+            }
+        }
+
+        views.push(global);
+        Some(Self { views })
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} を解釈できません", path.display()),
+            )
+        })
+    }
+
+    /** client に対する動作と、使ったビューの名前。どのビューにも一致しなければ Refuse */
+    pub fn decide(&self, client: IpAddr) -> (Action, &str) {
+        // デュアルスタックのソケットでは、IPv4 のクライアントが IPv4 射影アドレスで見える
+        let client = client.to_canonical();
+        match self
+            .views
+            .iter()
+            .find(|view| view.clients.iter().any(|network| network.contains(client)))
+        {
+            Some(view) => (view.acl.decide(client), &view.name),
+            None => (Action::Refuse, ""),
+        }
+    }
+}
+
+/** access_control で許されたクライアントの問い合わせだけを handler に答えさせる */
+pub struct Guarded<H> {
+    access_control: AccessControl,
+    handler: H,
+}
+
+impl<H: Handler> Guarded<H> {
+    pub fn new(access_control: AccessControl, handler: H) -> Self {
+        Self {
+            access_control,
+            handler,
+        }
+    }
+}
+
+impl<H: Handler> Handler for Guarded<H> {
+    fn handle(&self, request: &Request) -> Message {
+        match self.access_control.decide(request.client.ip()) {
+            (Action::Allow, _) => self.handler.handle(request),
+            (_, view) => {
+                println!(
+                    "{:?} からの問い合わせを断ります (ビュー: {:?})",
+                    request.client, view
+                );
+                request.response(false, false, 5)
+            }
+        }
+    }
+
+    fn accepts(&self, client: SocketAddr) -> bool {
+        self.access_control.decide(client.ip()).0 != Action::Deny
+    }
+}
+
+/** 上位 prefix_len ビットが 1 のマスク */
+fn ipv4_mask(prefix_len: u8) -> u32 {
+    u32::MAX
+        .checked_shl(32 - u32::from(prefix_len))
+        .unwrap_or(0)
+}
+
+fn ipv6_mask(prefix_len: u8) -> u128 {
+    u128::MAX
+        .checked_shl(128 - u32::from(prefix_len))
+        .unwrap_or(0)
+}
+
+fn networks(texts: &[&str]) -> Option<Vec<Network>> {
+    texts.iter().map(|text| text.parse().ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccessControl, Acl, Action, Guarded, Network};
+    use crate::authoritative::{Authority, Zone};
+    use crate::message::{Header, Message, Question};
+    use crate::server::{respond, spawn};
+    use crate::zone_file;
+    use std::net::{IpAddr, UdpSocket};
+    use std::time::Duration;
+
+    const CONFIG: &str = "# どのビューにも一致しないクライアント
+allow 198.51.100.0/24
+deny 203.0.113.0/24
+
+view internal 10.0.0.0/8 fd00::/8
+default allow
+refuse 10.99.0.0/16   # 来客用のネットワーク
+deny 10.99.0.66
+
+view loopback 127.0.0.1 ::1
+default allow
+";
+
+    fn address(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    fn network(text: &str) -> Network {
+        text.parse().unwrap()
+    }
+
+    fn guarded(access_control: AccessControl) -> Guarded<Authority> {
+        let records = zone_file::parse(
+            "@ 60 IN SOA ns1 hostmaster 1 7200 3600 1209600 60
+www 60 IN A 192.0.2.1
+",
+            "example.com",
+        )
+        .unwrap();
+        let authority = Authority::new(vec![Zone::new("example.com", records).unwrap()]);
+        Guarded::new(access_control, authority)
+    }
+
+    fn query() -> Vec<u8> {
+        Message::new(
+            Header::create(42, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0),
+            Question::new("www.example.com", 1, 1).unwrap(),
+        )
+        .to_bytes()
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        assert!(network("10.0.0.0/8").contains(address("10.255.0.1")));
+        assert!(!network("10.0.0.0/8").contains(address("11.0.0.1")));
+        assert!(!network("10.0.0.0/8").contains(address("::ffff:10.0.0.1")));
+        assert!(network("0.0.0.0/0").contains(address("192.0.2.1")));
+        assert!(network("2001:db8::/32").contains(address("2001:db8:ffff::1")));
+        assert!(!network("2001:db8::1").contains(address("2001:db8::2")));
+        // プレフィックスより後ろのビットは無視する
+        assert_eq!(network("10.1.2.3/8"), network("10.0.0.0/8"));
+        for invalid in ["10.0.0.0/33", "2001:db8::/129", "10.0.0.0/x", "example.com"] {
+            assert!(invalid.parse::<Network>().is_err(), "{}", invalid);
+        }
+
+        let acl = Acl {
+            rules: vec![
+                (network("10.1.0.0/16"), Action::Refuse),
+                (network("10.0.0.0/8"), Action::Allow),
+                (network("10.1.2.3"), Action::Deny),
+                (network("10.1.0.0/16"), Action::Allow),
+            ],
+            default: Action::Deny,
+        };
+        assert_eq!(acl.decide(address("10.2.0.1")), Action::Allow);
+        assert_eq!(acl.decide(address("10.1.0.1")), Action::Refuse);
+        assert_eq!(acl.decide(address("10.1.2.3")), Action::Deny);
+        assert_eq!(acl.decide(address("192.0.2.1")), Action::Deny);
+    }
+
+    #[test]
+    fn views_are_chosen_by_client_address() {
+        let access_control = AccessControl::parse(CONFIG).unwrap();
+        let decide = |client| access_control.decide(address(client));
+        assert_eq!(decide("10.1.2.3"), (Action::Allow, "internal"));
+        assert_eq!(decide("::ffff:10.1.2.3"), (Action::Allow, "internal"));
+        assert_eq!(decide("fd00::1"), (Action::Allow, "internal"));
+        assert_eq!(decide("10.99.0.1"), (Action::Refuse, "internal"));
+        assert_eq!(decide("10.99.0.66"), (Action::Deny, "internal"));
+        assert_eq!(decide("::1"), (Action::Allow, "loopback"));
+        assert_eq!(decide("198.51.100.7"), (Action::Allow, "default"));
+        assert_eq!(decide("203.0.113.7"), (Action::Deny, "default"));
+        // 一致する規則がなければ refuse
+        assert_eq!(decide("192.0.2.1"), (Action::Refuse, "default"));
+        assert_eq!(decide("2001:db8::1"), (Action::Refuse, "default"));
+
+        for invalid in [
+            "permit 10.0.0.0/8",
+            "allow",
+            "allow 10.0.0.0/40",
+            "view internal",
+            "view internal example.com",
+            "default maybe",
+        ] {
+            assert!(AccessControl::parse(invalid).is_none(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn decisions_are_applied_to_queries() {
+        let configured = guarded(AccessControl::parse(CONFIG).unwrap());
+        let respond_to =
+            |client: &str| respond(&configured, &query(), client.parse().unwrap(), false);
+
+        let response = Message::parse(&respond_to("10.0.0.1:5353").unwrap()).unwrap();
+        assert_eq!(response.header.rcode(), 0);
+        assert_eq!(response.header.aa(), 1);
+        assert_eq!(response.answers.len(), 1);
+
+        for client in ["10.99.0.1:5353", "[2001:db8::1]:5353"] {
+            let response = Message::parse(&respond_to(client).unwrap()).unwrap();
+            assert_eq!(response.header.id, 42);
+            assert_eq!(response.header.qr(), 1);
+            assert_eq!(response.header.rcode(), 5);
+            assert_eq!(response.header.aa(), 0);
+            assert_eq!(response.question.qname_dec, "www.example.com");
+            assert!(response.answers.is_empty());
+        }
+
+        // deny のクライアントには、壊れたメッセージにも何も返さない
+        assert!(respond_to("203.0.113.7:5353").is_none());
+        assert!(respond(
+            &configured,
+            &[0; 3],
+            "203.0.113.7:53".parse().unwrap(),
+            false
+        )
+        .is_none());
+
+        // 既定ではループバックのクライアントにだけ答える
+        let localhost = guarded(AccessControl::localhost());
+        for (client, rcode) in [
+            ("127.0.0.1:5353", 0),
+            ("[::1]:5353", 0),
+            ("192.0.2.1:5353", 5),
+        ] {
+            let response = respond(&localhost, &query(), client.parse().unwrap(), false);
+            assert_eq!(
+                Message::parse(&response.unwrap()).unwrap().header.rcode(),
+                rcode
+            );
+        }
+    }
+
+    #[test]
+    fn refused_and_denied_over_udp() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_millis(300)))
+            .unwrap();
+        let mut buf = [0; 512];
+
+        let refusing = spawn(
+            "127.0.0.1:0".parse().unwrap(),
+            guarded(AccessControl::parse("refuse 127.0.0.0/8\n").unwrap()),
+        )
+        .unwrap();
+        socket.send_to(&query(), refusing).unwrap();
+        let (length, _) = socket.recv_from(&mut buf).unwrap();
+        assert_eq!(Message::parse(&buf[..length]).unwrap().header.rcode(), 5);
+
+        let denying = spawn(
+            "127.0.0.1:0".parse().unwrap(),
+            guarded(AccessControl::parse("deny 127.0.0.1\n").unwrap()),
+        )
+        .unwrap();
+        socket.send_to(&query(), denying).unwrap();
+        assert!(socket.recv_from(&mut buf).is_err());
+    }
+}
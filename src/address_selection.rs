@@ -0,0 +1,290 @@
+use std::cmp::Ordering;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, UdpSocket};
+
+/** RFC 6724 Section 2.1 の既定のポリシーテーブル (プレフィックス, プレフィックス長, 優先度, ラベル) */
+const POLICY_TABLE: [(u128, u32, u8, u8); 9] = [
+    (1, 128, 50, 0),            // ::1/128
+    (0, 0, 40, 1),              // ::/0
+    (0xffff << 32, 96, 35, 4),  // ::ffff:0:0/96 (IPv4)
+    (0x2002 << 112, 16, 30, 2), // 2002::/16 (6to4)
+    (0x2001 << 112, 32, 5, 5),  // 2001::/32 (Teredo)
+    (0xfc00 << 112, 7, 3, 13),  // fc00::/7 (ULA)
+    (0, 96, 1, 3),              // ::/96 (IPv4 互換)
+    (0xfec0 << 112, 10, 1, 11), // fec0::/10 (サイトローカル)
+    (0x3ffe << 112, 16, 1, 12), // 3ffe::/16 (6bone)
+];
+
+/** アドレスのスコープ (RFC 4291 のマルチキャストのスコープと同じ値) */
+const SCOPE_LINK_LOCAL: u8 = 0x2;
+const SCOPE_SITE_LOCAL: u8 = 0x5;
+const SCOPE_GLOBAL: u8 = 0xe;
+
+/** 最長一致の比較は、送信元のサブネットのプレフィックス長 (IPv6 では通常 64) までにとどめる */
+const MAX_COMMON_PREFIX_IPV6: u32 = 64;
+
+/**
+ * getaddrinfo と同じく、宛先のアドレスを RFC 6724 の規則で並べ替えたうえで、
+ * Happy Eyeballs (RFC 8305 Section 4) のために IPv6 と IPv4 を交互に並べる
+ */
+pub fn sort(destinations: &[IpAddr]) -> Vec<IpAddr> {
+    interleave(&sort_with(destinations, source_address))
+}
+
+/** RFC 6724 Section 6 の宛先アドレス選択。source は宛先に送るときの送信元アドレス (経路がなければ None) を返す */
+pub fn sort_with(
+    destinations: &[IpAddr],
+    source: impl Fn(IpAddr) -> Option<IpAddr>,
+) -> Vec<IpAddr> {
+    let mut candidates: Vec<Candidate> = Vec::new();
+    for destination in destinations {
+        if candidates.iter().any(|c| c.destination == *destination) {
+            continue;
+        }
+        candidates.push(Candidate {
+            destination: *destination,
+            source: source(*destination),
+        });
+    }
+    // sort_by は安定なので、どの規則でも差がつかなければ元の順序のまま (Rule 10)
+    candidates.sort_by(compare);
+    candidates.into_iter().map(|c| c.destination).collect()
+}
+
+/** 最初のアドレスのファミリから始めて、IPv6 と IPv4 を交互に並べる。同じファミリの中の順序は変えない */
+pub fn interleave(sorted: &[IpAddr]) -> Vec<IpAddr> {
+    let first_is_ipv6 = match sorted.first() {
+        Some(address) => address.is_ipv6(),
+        None => return Vec::new(),
+    };
+    let (first, second): (Vec<IpAddr>, Vec<IpAddr>) = sorted
+        .iter()
+        .partition(|address| address.is_ipv6() == first_is_ipv6);
+    let mut first = first.into_iter();
+    let mut second = second.into_iter();
+    let mut interleaved = Vec::new();
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => return interleaved,
+            (a, b) => interleaved.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/** 宛先に UDP ソケットを connect し、カーネルが選ぶ送信元アドレスを得る (パケットは送らない) */
+pub fn source_address(destination: IpAddr) -> Option<IpAddr> {
+    let local = if destination.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(local).ok()?;
+    socket.connect(SocketAddr::new(destination, 53)).ok()?;
+    Some(socket.local_addr().ok()?.ip())
+}
+
+struct Candidate {
+    destination: IpAddr,
+    source: Option<IpAddr>,
+}
+
+/** RFC 6724 Section 6 の規則を順に適用する。Less のほうを優先する (Rule 3, 4, 7 は判断する材料がないので省く) */
+fn compare(a: &Candidate, b: &Candidate) -> Ordering {
+    let (source_a, source_b) = match (a.source, b.source) {
+        // Rule 1: 使えない (経路のない) 宛先を避ける
+        (Some(_), None) => return Ordering::Less,
+        (None, Some(_)) => return Ordering::Greater,
+        (None, None) => return Ordering::Equal,
+        (Some(source_a), Some(source_b)) => (source_a, source_b),
+    };
+
+    // Rule 2: 送信元とスコープが一致する宛先を選ぶ
+    let scope_a = scope(a.destination);
+    let scope_b = scope(b.destination);
+    let ordering = (scope(source_b) == scope_b).cmp(&(scope(source_a) == scope_a));
+    if ordering != Ordering::Equal {
+        return ordering;
+    }
+
+    // Rule 5: 送信元とラベルが一致する宛先を選ぶ
+    let (precedence_a, label_a) = policy(a.destination);
+    let (precedence_b, label_b) = policy(b.destination);
+    let ordering = (policy(source_b).1 == label_b).cmp(&(policy(source_a).1 == label_a));
+    if ordering != Ordering::Equal {
+        return ordering;
+    }
+
+    // Rule 6: 優先度の高い宛先を選ぶ
+    let ordering = precedence_b.cmp(&precedence_a);
+    if ordering != Ordering::Equal {
+        return ordering;
+    }
+
+    // Rule 8: スコープの狭い宛先を選ぶ
+    let ordering = scope_a.cmp(&scope_b);
+    if ordering != Ordering::Equal {
+        return ordering;
+    }
+
+    // Rule 9: 同じファミリなら、送信元との共通のプレフィックスが長い宛先を選ぶ
+    if a.destination.is_ipv4() == b.destination.is_ipv4() {
+        return common_prefix_len(source_b, b.destination)
+            .cmp(&common_prefix_len(source_a, a.destination));
+    }
+    Ordering::Equal
+}
+
+/** IPv4 のアドレスは IPv4 射影アドレスとして扱う */
+fn to_u128(address: IpAddr) -> u128 {
+    match address {
+        IpAddr::V4(ipv4) => u128::from(ipv4.to_ipv6_mapped()),
+        IpAddr::V6(ipv6) => u128::from(ipv6),
+    }
+}
+
+/** ポリシーテーブルの最長一致のエントリの (優先度, ラベル) */
+fn policy(address: IpAddr) -> (u8, u8) {
+    let address = to_u128(address);
+    POLICY_TABLE
+        .iter()
+        .filter(|(prefix, length, _, _)| {
+            *length == 0 || address >> (128 - length) == prefix >> (128 - length)
+        })
+        .max_by_key(|(_, length, _, _)| *length)
+        .map(|(_, _, precedence, label)| (*precedence, *label))
+        .unwrap()
+}
+
+/** RFC 6724 Section 3.2: IPv4 はループバックとリンクローカルだけをリンクローカルのスコープとする */
+fn scope(address: IpAddr) -> u8 {
+    match address {
+        IpAddr::V4(ipv4) if ipv4.is_loopback() || ipv4.is_link_local() => SCOPE_LINK_LOCAL,
+        IpAddr::V4(_) => SCOPE_GLOBAL,
+        IpAddr::V6(ipv6) => ipv6_scope(ipv6),
+    }
+}
+
+fn ipv6_scope(address: Ipv6Addr) -> u8 {
+    let segments = address.segments();
+    if address.is_multicast() {
+        return (segments[0] & 0x000f) as u8;
+    }
+    if address.is_loopback() || segments[0] & 0xffc0 == 0xfe80 {
+        return SCOPE_LINK_LOCAL;
+    }
+    if segments[0] & 0xffc0 == 0xfec0 {
+        return SCOPE_SITE_LOCAL;
+    }
+    SCOPE_GLOBAL
+}
+
+fn common_prefix_len(source: IpAddr, destination: IpAddr) -> u32 {
+    match (source, destination) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+            (u32::from(source) ^ u32::from(destination)).leading_zeros()
+        }
+        (IpAddr::V6(source), IpAddr::V6(destination)) => (u128::from(source)
+            ^ u128::from(destination))
+        .leading_zeros()
+        .min(MAX_COMMON_PREFIX_IPV6),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{interleave, sort_with};
+    use std::net::IpAddr;
+
+    fn addresses(addresses: &[&str]) -> Vec<IpAddr> {
+        addresses.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    /** 送信元のアドレスの一覧から、宛先と同じファミリのものを選ぶ */
+    fn sources(sources: &'static [&'static str]) -> impl Fn(IpAddr) -> Option<IpAddr> {
+        move |destination| {
+            addresses(sources)
+                .into_iter()
+                .find(|source| source.is_ipv4() == destination.is_ipv4())
+        }
+    }
+
+    #[test]
+    fn rfc6724_examples() {
+        // RFC 6724 Section 10.2 の例
+        let dual_stack = sources(&["2001:db8:1::2", "169.254.13.78"]);
+        assert_eq!(
+            sort_with(
+                &addresses(&["198.51.100.121", "2001:db8:1::1"]),
+                &dual_stack
+            ),
+            addresses(&["2001:db8:1::1", "198.51.100.121"])
+        );
+        assert_eq!(
+            sort_with(
+                &addresses(&["2001:db8:1::1", "198.51.100.121"]),
+                sources(&["fe80::1", "198.51.100.117"])
+            ),
+            addresses(&["198.51.100.121", "2001:db8:1::1"])
+        );
+        assert_eq!(
+            sort_with(
+                &addresses(&["10.1.2.3", "2001:db8:1::1"]),
+                sources(&["2001:db8:1::2", "10.1.2.4"])
+            ),
+            addresses(&["2001:db8:1::1", "10.1.2.3"])
+        );
+        // Rule 5: 6to4 の宛先には 6to4 の送信元のほうが合う
+        assert_eq!(
+            sort_with(
+                &addresses(&["2001:db8:1::1", "2002:c633:6401::1"]),
+                sources(&["2002:c633:6401::2"])
+            ),
+            addresses(&["2002:c633:6401::1", "2001:db8:1::1"])
+        );
+        // Rule 9: 共通のプレフィックスが長い宛先から
+        assert_eq!(
+            sort_with(
+                &addresses(&["2001:db8:1::1", "2001:db8:3ffe::1"]),
+                sources(&["2001:db8:3f44::2"])
+            ),
+            addresses(&["2001:db8:3ffe::1", "2001:db8:1::1"])
+        );
+    }
+
+    #[test]
+    fn unreachable_destinations_go_last() {
+        let ipv4_only = sources(&["192.0.2.10"]);
+        assert_eq!(
+            sort_with(
+                &addresses(&["2001:db8::1", "198.51.100.1", "2001:db8::1"]),
+                ipv4_only
+            ),
+            addresses(&["198.51.100.1", "2001:db8::1"])
+        );
+    }
+
+    #[test]
+    fn interleave_families() {
+        assert_eq!(
+            interleave(&addresses(&[
+                "2001:db8::1",
+                "2001:db8::2",
+                "2001:db8::3",
+                "192.0.2.1",
+                "192.0.2.2"
+            ])),
+            addresses(&[
+                "2001:db8::1",
+                "192.0.2.1",
+                "2001:db8::2",
+                "192.0.2.2",
+                "2001:db8::3"
+            ])
+        );
+        assert_eq!(
+            interleave(&addresses(&["192.0.2.1", "2001:db8::1"])),
+            addresses(&["192.0.2.1", "2001:db8::1"])
+        );
+        assert!(interleave(&[]).is_empty());
+    }
+}
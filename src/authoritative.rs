@@ -0,0 +1,537 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use crate::message::{is_subdomain, Message, Resource};
+use crate::server::{Handler, Request};
+use crate::zone_file;
+
+/** 1 つの問い合わせでたどる CNAME の数の上限 */
+const MAX_CNAME_CHAIN: usize = 8;
+
+/** 権威を持つ 1 つのゾーンのデータ */
+pub struct Zone {
+    /** ゾーンの頂点 (小文字で、末尾のドットは付けない) */
+    pub origin: String,
+    pub class: u16,
+    soa: Resource,
+    /** 小文字にした名前ごとのレコード */
+    nodes: HashMap<String, Vec<Resource>>,
+}
+
+/** ゾーンから引いた結果。ヘッダの AA と RCODE、各セクションになる */
+#[derive(Debug, Default)]
+pub struct Lookup {
+    pub authoritative: bool,
+    pub rcode: u8,
+    pub answers: Vec<Resource>,
+    pub authorities: Vec<Resource>,
+    pub additionals: Vec<Resource>,
+}
+
+/** ある名前のデータを探した結果 */
+enum Found {
+    Records(Vec<Resource>),
+    Alias(Box<Resource>),
+    NoData,
+    NxDomain,
+}
+
+impl Zone {
+    /** 頂点に SOA がちょうど 1 つあり、すべてのレコードがゾーンの中にあること */
+    pub fn new(origin: &str, records: Vec<Resource>) -> Option<Self> {
+        let origin = origin.trim_end_matches('.').to_ascii_lowercase();
+        let mut nodes: HashMap<String, Vec<Resource>> = HashMap::new();
+        for record in records {
+            let name = record.name.to_ascii_lowercase();
+            if !is_subdomain(&name, &origin) {
+                return None;
+            }
+            nodes.entry(name).or_default().push(record);
+        }
+        let soa = match nodes
+            .get(&origin)?
+            .iter()
+            .filter(|record| record.rr_type == 6)
+            .collect::<Vec<_>>()
+            .as_slice()
+        {
+            [soa] => (*soa).clone(),
+            _ => return None,
+        };
+        Some(Self {
+            origin,
+            class: soa.data_class,
+            soa,
+            nodes,
+        })
+    }
+
+    /** マスターファイルからゾーンを読む */
+    pub fn load(path: &Path, origin: &str) -> io::Result<Self> {
+        let records = zone_file::load(path, origin)?;
+        Self::new(origin, records).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} の頂点に SOA がないか、ゾーンの外のレコードがあります",
+                    path.display()
+                ),
+            )
+        })
+    }
+
+    /**
+     * RFC 1034 Section 4.3.2 のアルゴリズムで答えを探す。qname はこのゾーンの中の名前。
+     * 委任されたサブゾーンの名前には NS とグルーで委任先を示し、CNAME はゾーンの中にある限りたどる
+     */
+    pub fn lookup(&self, qname: &str, qtype: u16) -> Lookup {
+        let mut lookup = Lookup {
+            authoritative: true,
+            ..Default::default()
+        };
+        let mut name = qname.to_string();
+        for _ in 0..=MAX_CNAME_CHAIN {
+            if let Some(ns) = self.delegation(&name, qtype) {
+                // 委任先はこのゾーンの権威の外。CNAME をたどってきたなら、その CNAME には権威がある
+                lookup.authoritative = !lookup.answers.is_empty();
+                lookup.additionals = self.addresses(ns.iter().map(|r| r.nsdname.as_str()));
+                lookup.authorities.extend(ns);
+                return lookup;
+            }
+            match self.find(&name, qtype) {
+                Found::Records(records) => {
+                    lookup.additionals = self.addresses(records.iter().filter_map(target));
+                    lookup.answers.extend(records);
+                    return lookup;
+                }
+                Found::Alias(cname) => {
+                    name = cname.cname.clone();
+                    lookup.answers.push(*cname);
+                    // ゾーンの外を指す CNAME は、クライアントにたどってもらう
+                    if !is_subdomain(&name.to_ascii_lowercase(), &self.origin) {
+                        return lookup;
+                    }
+                }
+                Found::NoData => {
+                    lookup.authorities.push(self.negative_soa());
+                    return lookup;
+                }
+                Found::NxDomain => {
+                    // RCODE は最後にたどった名前についてのもの (RFC 6604)
+                    lookup.rcode = 3;
+                    lookup.authorities.push(self.negative_soa());
+                    return lookup;
+                }
+            }
+        }
+        println!("CNAME の連鎖が長すぎるか、ループしています: {:?}", qname);
+        lookup.rcode = 2;
+        lookup
+    }
+
+    /** name かその祖先がこのゾーンから委任されていれば、最も上の委任の NS */
+    fn delegation(&self, name: &str, qtype: u16) -> Option<Vec<Resource>> {
+        let name = name.to_ascii_lowercase();
+        self.ancestors(&name)
+            .iter()
+            .rev()
+            // DS は委任する側のゾーンにある (RFC 4035 Section 3.1.4.1)
+            .filter(|cut| !(**cut == name && qtype == 43))
+            .find_map(|cut| {
+                let ns: Vec<Resource> = self
+                    .nodes
+                    .get(cut)?
+                    .iter()
+                    .filter(|record| record.rr_type == 2)
+                    .cloned()
+                    .collect();
+                (!ns.is_empty()).then_some(ns)
+            })
+    }
+
+    fn find(&self, name: &str, qtype: u16) -> Found {
+        let key = name.to_ascii_lowercase();
+        if let Some(records) = self.nodes.get(&key) {
+            return select(records, qtype);
+        }
+        // 子孫にレコードがある名前 (空の非終端) は存在する
+        if self.nodes.keys().any(|node| is_subdomain(node, &key)) {
+            return Found::NoData;
+        }
+
+        // RFC 4592: 存在する最も近い祖先 (closest encloser) の直下のワイルドカードから合成する
+        let encloser = self
+            .ancestors(&key)
+            .into_iter()
+            .skip(1)
+            .find(|ancestor| self.exists(ancestor))
+            .unwrap_or_else(|| self.origin.clone());
+        let wildcard = if encloser.is_empty() {
+            "*".to_string()
+        } else {
+            ["*.", encloser.as_str()].concat()
+        };
+        let rename = |record: Resource| Resource {
+            name: name.to_string(),
+            ..record
+        };
+        match self.nodes.get(&wildcard) {
+            Some(records) => match select(records, qtype) {
+                Found::Records(records) => {
+                    Found::Records(records.into_iter().map(rename).collect())
+                }
+                Found::Alias(cname) => Found::Alias(Box::new(rename(*cname))),
+                found => found,
+            },
+            None => Found::NxDomain,
+        }
+    }
+
+    fn exists(&self, name: &str) -> bool {
+        self.nodes.contains_key(name) || self.nodes.keys().any(|node| is_subdomain(node, name))
+    }
+
+    /** name (小文字) からゾーンの頂点の直下までの名前。name に近いほうから並べる */
+    fn ancestors(&self, name: &str) -> Vec<String> {
+        let mut ancestors = Vec::new();
+        let mut name = name;
+        while name != self.origin && is_subdomain(name, &self.origin) {
+            ancestors.push(name.to_string());
+            name = name.split_once('.').map_or("", |(_, parent)| parent);
+        }
+        ancestors
+    }
+
+    /** 付加情報部に載せる、ゾーンの中の names の A と AAAA (委任先のグルーを含む) */
+    fn addresses<'a>(&self, names: impl Iterator<Item = &'a str>) -> Vec<Resource> {
+        let mut addresses: Vec<Resource> = Vec::new();
+        for name in names {
+            let name = name.to_ascii_lowercase();
+            if addresses
+                .iter()
+                .any(|address| address.name.eq_ignore_ascii_case(&name))
+            {
+                continue;
+            }
+            if let Some(records) = self.nodes.get(&name) {
+                addresses.extend(
+                    records
+                        .iter()
+                        .filter(|record| record.rr_type == 1 || record.rr_type == 28)
+                        .cloned(),
+                );
+            }
+        }
+        addresses
+    }
+
+    /** 否定応答の権威部の SOA。TTL は SOA の TTL と MINIMUM の小さいほう (RFC 2308 Section 3) */
+    fn negative_soa(&self) -> Resource {
+        Resource {
+            ttl: self.soa.ttl.min(self.soa.minimum),
+            ..self.soa.clone()
+        }
+    }
+}
+
+/** 複数のゾーンに権威を持つサーバ。再帰問い合わせは行わない */
+pub struct Authority {
+    zones: Vec<Zone>,
+}
+
+impl Authority {
+    pub fn new(zones: Vec<Zone>) -> Self {
+        Self { zones }
+    }
+
+    /** qname を含む最も深いゾーン */
+    fn zone_for(&self, qname: &str) -> Option<&Zone> {
+        let qname = qname.trim_end_matches('.').to_ascii_lowercase();
+        self.zones
+            .iter()
+            .filter(|zone| is_subdomain(&qname, &zone.origin))
+            .max_by_key(|zone| zone.origin.len())
+    }
+}
+
+impl Handler for Authority {
+    fn handle(&self, request: &Request) -> Message {
+        let question = &request.question;
+        let zone = match self.zone_for(&question.qname_dec) {
+            Some(zone) if question.qclass == zone.class || question.qclass == 255 => zone,
+            // 権威を持たない名前の問い合わせは断る
+            _ => return request.response(false, false, 5),
+        };
+        // ゾーン転送 (IXFR, AXFR) には対応しない
+        if question.qtype == 251 || question.qtype == 252 {
+            return request.response(false, false, 4);
+        }
+
+        let lookup = zone.lookup(&question.qname_dec, question.qtype);
+        let mut response = request.response(lookup.authoritative, false, lookup.rcode);
+        response.answers = lookup.answers;
+        response.authorities = lookup.authorities;
+        response.additionals = lookup.additionals;
+        response
+    }
+}
+
+/** ある名前のレコードから qtype (255 は ANY) のものを選ぶ。なければ CNAME をたどる */
+fn select(records: &[Resource], qtype: u16) -> Found {
+    let matching: Vec<Resource> = records
+        .iter()
+        .filter(|record| qtype == 255 || record.rr_type == qtype)
+        .cloned()
+        .collect();
+    if !matching.is_empty() {
+        return Found::Records(matching);
+    }
+    match records.iter().find(|record| record.rr_type == 5) {
+        Some(cname) => Found::Alias(Box::new(cname.clone())),
+        None => Found::NoData,
+    }
+}
+
+/** 付加情報部にアドレスを載せる名前 (NS, MX, SRV) */
+fn target(record: &Resource) -> Option<&str> {
+    match record.rr_type {
+        2 => Some(&record.nsdname),
+        15 => Some(&record.exchange),
+        33 => record.srv.as_ref().map(|srv| srv.target.as_str()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Authority, Lookup, Zone};
+    use crate::message::Resource;
+    use crate::server::testing::request;
+    use crate::server::Handler;
+    use crate::zone_file;
+
+    const ZONE: &str = "$ORIGIN example.com.
+$TTL 3600
+@         IN SOA   ns1 hostmaster 2024010101 7200 3600 1209600 300
+          IN NS    ns1
+          IN MX    10 mail
+ns1       IN A     192.0.2.1
+mail      IN A     192.0.2.2
+          IN AAAA  2001:db8::2
+web       IN A     192.0.2.3
+www       IN CNAME web
+alias     IN CNAME www
+outside   IN CNAME www.example.net.
+dangling  IN CNAME nothing
+loop1     IN CNAME loop2
+loop2     IN CNAME loop1
+*.wild    IN A     192.0.2.4
+          IN TXT   \"wild\"
+*.star    IN CNAME web
+a.b.c     IN A     192.0.2.5
+sub       IN NS    ns1.sub
+          IN NS    ns.example.net.
+          IN DS    12345 13 2 0123456789ABCDEF0123456789ABCDEF0123456789ABCDEF0123456789ABCDEF
+ns1.sub   IN A     192.0.2.53
+";
+
+    fn zone() -> Zone {
+        Zone::new(
+            "example.com.",
+            zone_file::parse(ZONE, "example.com").unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn text(records: &[Resource]) -> Vec<String> {
+        records.iter().map(ToString::to_string).collect()
+    }
+
+    const NEGATIVE_SOA: &str = "example.com. 300 IN SOA ns1.example.com. hostmaster.example.com. 2024010101 7200 3600 1209600 300";
+
+    fn assert_negative(lookup: &Lookup, rcode: u8) {
+        assert!(lookup.authoritative);
+        assert_eq!(lookup.rcode, rcode);
+        assert_eq!(text(&lookup.authorities), vec![NEGATIVE_SOA]);
+    }
+
+    #[test]
+    fn authoritative_answers() {
+        let zone = zone();
+        let lookup = zone.lookup("Example.COM", 15);
+        assert!(lookup.authoritative);
+        assert_eq!(lookup.rcode, 0);
+        assert_eq!(
+            text(&lookup.answers),
+            vec!["example.com. 3600 IN MX 10 mail.example.com."]
+        );
+        assert!(lookup.authorities.is_empty());
+        assert_eq!(
+            text(&lookup.additionals),
+            vec![
+                "mail.example.com. 3600 IN A 192.0.2.2",
+                "mail.example.com. 3600 IN AAAA 2001:db8::2"
+            ]
+        );
+
+        assert_eq!(zone.lookup("mail.example.com", 255).answers.len(), 2);
+    }
+
+    #[test]
+    fn cname_chains_are_followed_inside_the_zone() {
+        let zone = zone();
+        let lookup = zone.lookup("alias.example.com", 1);
+        assert!(lookup.authoritative);
+        assert_eq!(
+            text(&lookup.answers),
+            vec![
+                "alias.example.com. 3600 IN CNAME www.example.com.",
+                "www.example.com. 3600 IN CNAME web.example.com.",
+                "web.example.com. 3600 IN A 192.0.2.3"
+            ]
+        );
+
+        // CNAME そのものを問われたらたどらない
+        assert_eq!(zone.lookup("www.example.com", 5).answers.len(), 1);
+
+        let lookup = zone.lookup("outside.example.com", 1);
+        assert_eq!(
+            text(&lookup.answers),
+            vec!["outside.example.com. 3600 IN CNAME www.example.net."]
+        );
+        assert!(lookup.authorities.is_empty());
+
+        // 指す先がなければ NXDOMAIN だが、CNAME は答えに含める
+        let lookup = zone.lookup("dangling.example.com", 1);
+        assert_negative(&lookup, 3);
+        assert_eq!(lookup.answers.len(), 1);
+
+        assert_eq!(zone.lookup("loop1.example.com", 1).rcode, 2);
+    }
+
+    #[test]
+    fn negative_answers_carry_the_soa() {
+        let zone = zone();
+        assert_negative(&zone.lookup("nothing.example.com", 1), 3);
+        // 名前はあるがタイプがない (NODATA)
+        assert_negative(&zone.lookup("web.example.com", 28), 0);
+        // 空の非終端も NODATA
+        assert_negative(&zone.lookup("c.example.com", 1), 0);
+        assert_negative(&zone.lookup("b.c.example.com", 1), 0);
+        assert!(zone.lookup("c.example.com", 1).answers.is_empty());
+    }
+
+    #[test]
+    fn wildcards_are_synthesised() {
+        let zone = zone();
+        assert_eq!(
+            text(&zone.lookup("host.wild.example.com", 1).answers),
+            vec!["host.wild.example.com. 3600 IN A 192.0.2.4"]
+        );
+        assert_eq!(
+            text(&zone.lookup("a.host.wild.example.com", 16).answers),
+            vec!["a.host.wild.example.com. 3600 IN TXT \"wild\""]
+        );
+        assert_negative(&zone.lookup("host.wild.example.com", 15), 0);
+        // ワイルドカードの親は空の非終端で、ワイルドカードには一致しない
+        assert_negative(&zone.lookup("wild.example.com", 1), 0);
+        // 最も近い祖先 (b.c) の直下にワイルドカードはない
+        assert_negative(&zone.lookup("x.b.c.example.com", 1), 3);
+
+        assert_eq!(
+            text(&zone.lookup("host.star.example.com", 1).answers),
+            vec![
+                "host.star.example.com. 3600 IN CNAME web.example.com.",
+                "web.example.com. 3600 IN A 192.0.2.3"
+            ]
+        );
+    }
+
+    #[test]
+    fn delegations_are_referred_with_glue() {
+        let zone = zone();
+        for (qname, qtype) in [("www.sub.example.com", 1), ("sub.example.com", 2)] {
+            let lookup = zone.lookup(qname, qtype);
+            assert!(!lookup.authoritative);
+            assert_eq!(lookup.rcode, 0);
+            assert!(lookup.answers.is_empty());
+            assert_eq!(
+                text(&lookup.authorities),
+                vec![
+                    "sub.example.com. 3600 IN NS ns1.sub.example.com.",
+                    "sub.example.com. 3600 IN NS ns.example.net."
+                ]
+            );
+            assert_eq!(
+                text(&lookup.additionals),
+                vec!["ns1.sub.example.com. 3600 IN A 192.0.2.53"]
+            );
+        }
+
+        // 委任の DS には親のゾーンが権威を持つ
+        let lookup = zone.lookup("sub.example.com", 43);
+        assert!(lookup.authoritative);
+        assert_eq!(lookup.answers.len(), 1);
+        // 委任より下のワイルドカードや NXDOMAIN は返さない
+        assert!(!zone.lookup("nothing.sub.example.com", 1).authoritative);
+    }
+
+    #[test]
+    fn server_picks_the_deepest_zone() {
+        let child = Zone::new(
+            "sub.example.com",
+            zone_file::parse(
+                "@ 60 IN SOA ns1 hostmaster 1 7200 3600 1209600 60
+@ 60 IN NS ns1
+www 60 IN A 198.51.100.1
+",
+                "sub.example.com",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let authority = Authority::new(vec![zone(), child]);
+
+        let response = authority.handle(&request("WWW.sub.example.com", 1));
+        assert_eq!(response.header.id, 7);
+        assert_eq!(response.header.qr(), 1);
+        assert_eq!(response.header.aa(), 1);
+        assert_eq!(response.header.rd(), 1);
+        assert_eq!(response.header.ra(), 0);
+        assert_eq!(response.question.qname_dec, "WWW.sub.example.com");
+        assert_eq!(
+            text(&response.answers),
+            vec!["www.sub.example.com. 60 IN A 198.51.100.1"]
+        );
+
+        let response = authority.handle(&request("nothing.example.com", 1));
+        assert_eq!((response.header.aa(), response.header.rcode()), (1, 3));
+
+        // 権威を持たない名前やクラスは断る
+        for (qname, qclass) in [("www.example.net", 1), ("www.example.com", 3)] {
+            let mut query = request(qname, 1);
+            query.question.qclass = qclass;
+            let response = authority.handle(&query);
+            assert_eq!((response.header.aa(), response.header.rcode()), (0, 5));
+        }
+        let response = authority.handle(&request("example.com", 252));
+        assert_eq!(response.header.rcode(), 4);
+    }
+
+    #[test]
+    fn zones_need_an_soa_and_in_zone_data() {
+        let records = |text| zone_file::parse(text, "example.com").unwrap();
+        assert!(Zone::new("example.com", records("@ 60 IN NS ns1\n")).is_none());
+        assert!(Zone::new(
+            "example.com",
+            records("@ 60 IN SOA ns1 hostmaster 1 2 3 4 5\nwww.example.net. 60 IN A 192.0.2.1\n")
+        )
+        .is_none());
+        assert!(Zone::new(
+            "example.com",
+            records("@ 60 IN SOA ns1 hostmaster 1 2 3 4 5\n@ 60 IN SOA ns2 hostmaster 1 2 3 4 5\n")
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+
+use crate::message::Resource;
+use crate::validator::Security;
+
+/** 保持する応答の数の上限。超えたら期限の近いものから捨てる */
+const MAX_ENTRIES: usize = 10000;
+
+/** TTL がこれより長くても、この時間 (1 日) までしか保持しない */
+const MAX_TTL: u32 = 86400;
+
+/** 問い合わせへの最終的な答え */
+#[derive(Debug, Clone)]
+pub struct Answer {
+    pub rcode: u8,
+    pub answers: Vec<Resource>,
+    pub authorities: Vec<Resource>,
+    pub security: Security,
+}
+
+/** QNAME (小文字・末尾のドットなし) と QTYPE */
+type Key = (String, u16);
+
+/** (QNAME, QTYPE) ごとに、最終的な答えをその TTL の間だけ保持する (RFC 1035 Section 7.4, RFC 2308) */
+pub struct Cache {
+    entries: Mutex<Entries>,
+}
+
+struct Entries {
+    answers: HashMap<Key, Entry>,
+    /** 有効期限の早い順に並べたキー。期限切れのものは get で見つけたときに捨てる */
+    expiry: BTreeSet<(u32, Key)>,
+}
+
+struct Entry {
+    answer: Answer,
+    /** 保持した時刻と有効期限 (validator::now と同じ UNIX 時刻) */
+    stored: u32,
+    expires: u32,
+}
+
+impl Entries {
+    fn remove(&mut self, key: &Key) -> Option<Entry> {
+        let entry = self.answers.remove(key)?;
+        self.expiry.remove(&(entry.expires, key.clone()));
+        Some(entry)
+    }
+}
+
+impl Cache {
+    pub fn new() -> Self {
+        Self {
+            entries: Mutex::new(Entries {
+                answers: HashMap::new(),
+                expiry: BTreeSet::new(),
+            }),
+        }
+    }
+
+    /**
+     * 答えを保持する。Answer があればその最小の TTL、否定応答 (NXDOMAIN, NODATA) なら権威部の SOA の
+     * TTL と MINIMUM の小さいほうだけ保持する。SOA のない否定応答、エラー、検証に失敗した答えは保持しない
+     */
+    pub fn insert(&self, qname: &str, qtype: u16, answer: &Answer, now: u32) {
+        if answer.security == Security::Bogus {
+            return;
+        }
+        let ttl = match answer.rcode {
+            0 if !answer.answers.is_empty() => answer.answers.iter().map(|r| r.ttl).min(),
+            0 | 3 => answer
+                .authorities
+                .iter()
+                .find(|r| r.rr_type == 6)
+                .map(|soa| soa.ttl.min(soa.minimum)),
+            _ => None,
+        };
+        let ttl = match ttl {
+            Some(ttl) if ttl > 0 => ttl.min(MAX_TTL),
+            _ => return,
+        };
+
+        let key = (qname.trim_end_matches('.').to_ascii_lowercase(), qtype);
+        let entry = Entry {
+            answer: answer.clone(),
+            stored: now,
+            expires: now.saturating_add(ttl),
+        };
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(&key);
+        // 期限切れのものを含め、期限の最も近いものから捨てる
+        if entries.answers.len() >= MAX_ENTRIES {
+            if let Some((_, nearest)) = entries.expiry.pop_first() {
+                entries.answers.remove(&nearest);
+            }
+        }
+        entries.expiry.insert((entry.expires, key.clone()));
+        entries.answers.insert(key, entry);
+    }
+
+    /** 保持している答え。TTL は保持してから経った時間だけ減らし、残りの有効期間より長くはしない */
+    pub fn get(&self, qname: &str, qtype: u16, now: u32) -> Option<Answer> {
+        let key = (qname.trim_end_matches('.').to_ascii_lowercase(), qtype);
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.answers.get(&key)?;
+        if entry.expires <= now {
+            entries.remove(&key);
+            return None;
+        }
+
+        let elapsed = now.saturating_sub(entry.stored);
+        let remaining = entry.expires - now;
+        let age = |resources: &[Resource]| -> Vec<Resource> {
+            resources
+                .iter()
+                .map(|r| Resource {
+                    ttl: r.ttl.saturating_sub(elapsed).min(remaining),
+                    ..r.clone()
+                })
+                .collect()
+        };
+        Some(Answer {
+            answers: age(&entry.answer.answers),
+            authorities: age(&entry.answer.authorities),
+            ..entry.answer.clone()
+        })
+    }
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Answer, Cache, MAX_ENTRIES};
+    use crate::validator::Security;
+    use crate::zone_file::parse_record;
+
+    fn answer(rcode: u8, answers: &[&str], authorities: &[&str], security: Security) -> Answer {
+        Answer {
+            rcode,
+            answers: answers.iter().map(|r| parse_record(r).unwrap()).collect(),
+            authorities: authorities
+                .iter()
+                .map(|r| parse_record(r).unwrap())
+                .collect(),
+            security,
+        }
+    }
+
+    const SOA: &str =
+        "example.com. 3600 IN SOA ns1.example.com. hostmaster.example.com. 1 7200 3600 1209600 300";
+
+    #[test]
+    fn positive_answers_expire_with_the_smallest_ttl() {
+        let cache = Cache::new();
+        let positive = answer(
+            0,
+            &[
+                "www.example.com. 600 IN CNAME web.example.com.",
+                "web.example.com. 60 IN A 192.0.2.1",
+                "web.example.com. 120 IN A 192.0.2.2",
+            ],
+            &[],
+            Security::Secure,
+        );
+        cache.insert("WWW.example.com.", 1, &positive, 1000);
+
+        let cached = cache.get("www.example.com", 1, 1030).unwrap();
+        assert_eq!(cached.rcode, 0);
+        assert_eq!(
+            cached.answers.iter().map(|r| r.ttl).collect::<Vec<_>>(),
+            vec![30, 30, 30]
+        );
+        assert_eq!(cached.answers[0].cname, "web.example.com");
+        assert!(cached.authorities.is_empty());
+        assert_eq!(cached.security, Security::Secure);
+
+        assert!(cache.get("www.example.com", 28, 1030).is_none());
+        assert!(cache.get("www.example.com", 1, 1060).is_none());
+    }
+
+    #[test]
+    fn full_cache_evicts_the_nearest_expiry() {
+        let cache = Cache::new();
+        let record = |ttl: u32| {
+            answer(
+                0,
+                &[&format!("www.example.com. {} IN A 192.0.2.1", ttl)],
+                &[],
+                Security::Insecure,
+            )
+        };
+        cache.insert("short.example.com", 1, &record(10), 1000);
+        for index in 0..MAX_ENTRIES - 1 {
+            cache.insert(&format!("{}.example.com", index), 1, &record(600), 1000);
+        }
+        // 同じキーを入れ直しても数は増えない
+        cache.insert("0.example.com", 1, &record(300), 1000);
+        assert!(cache.get("short.example.com", 1, 1000).is_some());
+
+        cache.insert("new.example.com", 1, &record(600), 1000);
+        assert!(cache.get("short.example.com", 1, 1000).is_none());
+        assert!(cache.get("0.example.com", 1, 1000).is_some());
+        assert!(cache.get("new.example.com", 1, 1000).is_some());
+        assert_eq!(cache.entries.lock().unwrap().expiry.len(), MAX_ENTRIES);
+    }
+
+    #[test]
+    fn negative_answers_use_the_soa_minimum() {
+        let cache = Cache::new();
+        let nxdomain = answer(3, &[], &[SOA], Security::Insecure);
+        cache.insert("www.example.com", 1, &nxdomain, 1000);
+        let cached = cache.get("www.example.com", 1, 1100).unwrap();
+        assert_eq!(cached.rcode, 3);
+        assert!(cached.answers.is_empty());
+        assert_eq!(cached.authorities[0].ttl, 200);
+        assert!(cache.get("www.example.com", 1, 1300).is_none());
+
+        // SOA のない否定応答、エラー、検証に失敗した答えは保持しない
+        let uncacheable = [
+            ("a.example.com", answer(0, &[], &[], Security::Insecure)),
+            ("b.example.com", answer(2, &[], &[SOA], Security::Insecure)),
+            (
+                "c.example.com",
+                answer(
+                    0,
+                    &["c.example.com. 60 IN A 192.0.2.1"],
+                    &[],
+                    Security::Bogus,
+                ),
+            ),
+        ];
+        for (qname, answer) in &uncacheable {
+            cache.insert(qname, 1, answer, 1000);
+            assert!(cache.get(qname, 1, 1000).is_none());
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::hosts::{Hosts, HOSTS_PATH};
+use crate::resolv_conf::{ResolvConf, RESOLV_CONF_PATH};
+use crate::root_hints::RootHints;
+use crate::trust_anchor::TrustAnchor;
+
+/** 名前解決の方法 */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /** ルートサーバから順に反復問い合わせを行う */
+    Iterative,
+    /** 上位のフルサービスリゾルバに RD=1 で再帰問い合わせを転送する (先頭から順にフェイルオーバー) */
+    Forward(Vec<SocketAddr>),
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolverConfig {
+    pub mode: Mode,
+    /** ゾーンごとに mode を上書きする。最も長く一致したゾーンが使われる */
+    pub zones: HashMap<String, Mode>,
+    /** 相対名に付加するドメイン (resolv.conf の search / domain) */
+    pub search: Vec<String>,
+    pub ndots: usize,
+    /** 1 回の問い合わせで応答を待つ時間 */
+    pub timeout: Duration,
+    /** Forward で上位リゾルバの一覧を試す回数 */
+    pub attempts: usize,
+    /** Forward で問い合わせごとに最初に試す上位リゾルバをずらす */
+    pub rotate: bool,
+    /** 問い合わせに EDNS0 の OPT レコードを付ける */
+    pub edns0: bool,
+    /** ネットワークに問い合わせる前に参照する */
+    pub hosts: Hosts,
+    /** Iterative でプライミングに使うルートサーバ。既定は IANA の named.root */
+    pub root_hints: RootHints,
+    /** Iterative で QNAME 最小化 (RFC 9156) を行う */
+    pub qname_minimisation: bool,
+    /** QNAME の大文字・小文字をランダムにして、応答の質問部と照合する (DNS 0x20) */
+    pub case_randomisation: bool,
+    /** Iterative で DO ビットを立てて問い合わせ、trust_anchor から DNSSEC の検証を行う */
+    pub dnssec: bool,
+    pub trust_anchor: TrustAnchor,
+    /** dnssec が有効なとき、検証済みの NSEC / NSEC3 が示す範囲の否定応答を問い合わせずに合成する (RFC 8198) */
+    pub aggressive_nsec: bool,
+    /** RFC 5011 で更新したトラストアンカーを保存するファイル。あれば trust_anchor の代わりに読み込む */
+    pub trust_anchor_file: Option<PathBuf>,
+}
+
+impl ResolverConfig {
+    pub fn new() -> Self {
+        Self::from_resolv_conf(&ResolvConf::default(), Hosts::default())
+    }
+
+    /** resolv.conf の nameserver を上位リゾルバとする Forward の設定を作る */
+    pub fn from_resolv_conf(conf: &ResolvConf, hosts: Hosts) -> Self {
+        let mode = if conf.nameservers.is_empty() {
+            Mode::Iterative
+        } else {
+            Mode::Forward(
+                conf.nameservers
+                    .iter()
+                    .map(|address| SocketAddr::new(*address, 53))
+                    .collect(),
+            )
+        };
+        Self {
+            mode,
+            zones: HashMap::new(),
+            search: conf.search.clone(),
+            ndots: conf.ndots,
+            timeout: conf.timeout,
+            attempts: conf.attempts,
+            rotate: conf.rotate,
+            edns0: conf.edns0,
+            hosts,
+            root_hints: RootHints::default(),
+            qname_minimisation: false,
+            case_randomisation: false,
+            dnssec: false,
+            trust_anchor: TrustAnchor::default(),
+            aggressive_nsec: true,
+            trust_anchor_file: None,
+        }
+    }
+
+    /** 指定されたパスの resolv.conf と hosts を読み込む。存在しないファイルは空とみなす */
+    pub fn load(resolv_conf: &Path, hosts: &Path) -> io::Result<Self> {
+        let conf = ResolvConf::parse(&read_optional(resolv_conf)?);
+        let hosts = Hosts::parse(&read_optional(hosts)?);
+        let mut config = Self::from_resolv_conf(&conf, hosts);
+        // nameserver の記述がなければローカルホストのリゾルバを使う
+        if conf.nameservers.is_empty() {
+            config.mode = Mode::Forward(vec![SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 53)]);
+        }
+        Ok(config)
+    }
+
+    /** /etc/resolv.conf と /etc/hosts を読み込む */
+    pub fn system() -> io::Result<Self> {
+        Self::load(Path::new(RESOLV_CONF_PATH), Path::new(HOSTS_PATH))
+    }
+
+    pub fn forward(upstreams: &[SocketAddr]) -> Self {
+        Self {
+            mode: Mode::Forward(upstreams.to_vec()),
+            ..Self::new()
+        }
+    }
+
+    /** fqdn の解決に使う mode を返す */
+    pub fn mode_for(&self, fqdn: &str) -> &Mode {
+        let fqdn = fqdn.trim_end_matches('.').to_ascii_lowercase();
+        self.zones
+            .iter()
+            .filter(|(zone, _)| {
+                let zone = zone.trim_end_matches('.').to_ascii_lowercase();
+                zone.is_empty() || fqdn == zone || fqdn.ends_with(&[".", zone.as_str()].concat())
+            })
+            .max_by_key(|(zone, _)| zone.trim_end_matches('.').len())
+            .map(|(_, mode)| mode)
+            .unwrap_or(&self.mode)
+    }
+
+    /** 相対名を search と ndots に従って展開し、問い合わせる順に並べる */
+    pub fn search_candidates(&self, name: &str) -> Vec<String> {
+        // 末尾がドットなら絶対名なので展開しない
+        if let Some(absolute) = name.strip_suffix('.') {
+            return vec![absolute.to_string()];
+        }
+
+        let expanded = self
+            .search
+            .iter()
+            .filter(|domain| !domain.is_empty())
+            .map(|domain| [name, ".", domain.as_str()].concat());
+        if name.matches('.').count() >= self.ndots {
+            std::iter::once(name.to_string()).chain(expanded).collect()
+        } else {
+            expanded.chain(std::iter::once(name.to_string())).collect()
+        }
+    }
+}
+
+fn read_optional(path: &Path) -> io::Result<String> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(text),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e),
+    }
+}
+
+impl Default for ResolverConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mode, ResolverConfig};
+    use std::fs;
+    use std::path::Path;
+    use std::time::Duration;
+
+    #[test]
+    fn load_system_files() {
+        let dir = std::env::temp_dir().join(format!("resolver-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("resolv.conf"),
+            "nameserver 192.168.12.1
+search corp.example
+options timeout:2 rotate
+",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("hosts"),
+            "192.0.2.1 db.corp.example
+",
+        )
+        .unwrap();
+
+        let config = ResolverConfig::load(&dir.join("resolv.conf"), &dir.join("hosts")).unwrap();
+        assert_eq!(
+            config.mode,
+            Mode::Forward(vec!["192.168.12.1:53".parse().unwrap()])
+        );
+        assert_eq!(config.search, vec!["corp.example"]);
+        assert_eq!(config.timeout, Duration::from_secs(2));
+        assert!(config.rotate);
+        assert_eq!(config.hosts.lookup("db.corp.example", 1).len(), 1);
+
+        let missing = ResolverConfig::load(
+            Path::new("/nonexistent/resolv.conf"),
+            Path::new("/nonexistent/hosts"),
+        )
+        .unwrap();
+        assert_eq!(
+            missing.mode,
+            Mode::Forward(vec!["127.0.0.1:53".parse().unwrap()])
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn search_candidates() {
+        let mut config = ResolverConfig::new();
+        config.search = vec!["corp.example".to_string(), "example.com".to_string()];
+        config.ndots = 2;
+
+        assert_eq!(
+            config.search_candidates("db"),
+            vec!["db.corp.example", "db.example.com", "db"]
+        );
+        assert_eq!(
+            config.search_candidates("db.eu"),
+            vec!["db.eu.corp.example", "db.eu.example.com", "db.eu"]
+        );
+        assert_eq!(
+            config.search_candidates("www.nyamikan.net"),
+            vec![
+                "www.nyamikan.net",
+                "www.nyamikan.net.corp.example",
+                "www.nyamikan.net.example.com"
+            ]
+        );
+        assert_eq!(config.search_candidates("db."), vec!["db"]);
+
+        config.search.clear();
+        assert_eq!(config.search_candidates("db"), vec!["db"]);
+    }
+
+    #[test]
+    fn mode_for_zone() {
+        let mut config = ResolverConfig::new();
+        config.zones.insert(
+            "corp.example".to_string(),
+            Mode::Forward(vec!["192.168.12.1:53".parse().unwrap()]),
+        );
+        config
+            .zones
+            .insert("public.corp.example.".to_string(), Mode::Iterative);
+
+        assert_eq!(config.mode_for("nyamikan.net"), &Mode::Iterative);
+        assert_eq!(
+            config.mode_for("www.CORP.example"),
+            &Mode::Forward(vec!["192.168.12.1:53".parse().unwrap()])
+        );
+        assert_eq!(
+            config.mode_for("corp.example."),
+            &Mode::Forward(vec!["192.168.12.1:53".parse().unwrap()])
+        );
+        assert_eq!(config.mode_for("www.public.corp.example"), &Mode::Iterative);
+        assert_eq!(config.mode_for("notcorp.example"), &Mode::Iterative);
+    }
+}
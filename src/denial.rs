@@ -0,0 +1,476 @@
+use data_encoding::BASE32HEX_NOPAD;
+use ring::digest;
+use std::cmp::Ordering;
+
+use crate::dnssec::{Nsec, Nsec3};
+use crate::message::{
+    encode_checked_name, is_subdomain, label_count, last_labels, name_labels, Resource,
+};
+use crate::validator::Security;
+
+/** これより多い繰り返し回数の NSEC3 は計算せず、署名されていないものとして扱う (RFC 9276 Section 3.2) */
+pub const MAX_NSEC3_ITERATIONS: u16 = 150;
+
+/**
+ * 否定応答の権威部にある NSEC と NSEC3 による不在の証明 (RFC 4035 Section 5.4, RFC 5155 Section 8)。
+ * 署名は検証済みであること。証明の結果は、証明できれば Secure、
+ * Opt-Out などで署名されていない委任の可能性が残れば Insecure、証明できなければ Bogus とする
+ */
+pub struct Denial<'a> {
+    /** 小文字・末尾のドットなし */
+    zone: String,
+    /** 所有者名 (小文字) と NSEC */
+    nsecs: Vec<(String, &'a Nsec)>,
+    /** 所有者名のハッシュと NSEC3 */
+    nsec3s: Vec<(Vec<u8>, &'a Nsec3)>,
+}
+
+impl<'a> Denial<'a> {
+    /** ゾーン zone の NSEC と NSEC3 を resources から集める */
+    pub fn new(zone: &str, resources: &'a [Resource]) -> Self {
+        let zone = zone.to_ascii_lowercase();
+        let nsecs = resources
+            .iter()
+            .filter_map(|r| Some((r.name.to_ascii_lowercase(), r.nsec.as_ref()?)))
+            .filter(|(owner, _)| is_subdomain(owner, &zone))
+            .collect();
+        // NSEC3 の所有者名は、ゾーンの頂点の直下にあるハッシュのラベル
+        let nsec3s = resources
+            .iter()
+            .filter_map(|r| {
+                let (hash, parent) = r.name.split_once('.').unwrap_or((&r.name, ""));
+                if !parent.eq_ignore_ascii_case(&zone) {
+                    return None;
+                }
+                let hash = BASE32HEX_NOPAD
+                    .decode(hash.to_ascii_uppercase().as_bytes())
+                    .ok()?;
+                Some((hash, r.nsec3.as_ref()?))
+            })
+            .filter(|(_, nsec3)| nsec3.hash_algorithm == 1)
+            .collect();
+        Self {
+            zone,
+            nsecs,
+            nsec3s,
+        }
+    }
+
+    /** qname が存在せず、それに一致するワイルドカードもないこと */
+    pub fn nxdomain(&self, qname: &str) -> Security {
+        let qname = qname.to_ascii_lowercase();
+        if let Some(security) = self.nsec3_limit() {
+            return security;
+        }
+
+        if let Some((owner, nsec)) = self
+            .nsecs
+            .iter()
+            .find(|(owner, nsec)| nsec_covers(owner, nsec, &qname))
+        {
+            let wildcard = wildcard(&closest_encloser(&qname, owner, nsec));
+            if self
+                .nsecs
+                .iter()
+                .any(|(owner, nsec)| nsec_covers(owner, nsec, &wildcard))
+            {
+                return Security::Secure;
+            }
+        }
+
+        if let Some((closest_encloser, next_closer)) = self.nsec3_closest_encloser(&qname) {
+            if self.nsec3_covering(&wildcard(&closest_encloser)).is_some() {
+                // Opt-Out の範囲なら、署名されていない委任が隠れているかもしれない
+                return if next_closer.is_opt_out() {
+                    Security::Insecure
+                } else {
+                    Security::Secure
+                };
+            }
+        }
+
+        Security::Bogus
+    }
+
+    /** qname は存在するが qtype のレコードがないこと (ワイルドカードによるものを含む) */
+    pub fn nodata(&self, qname: &str, qtype: u16) -> Security {
+        let qname = qname.to_ascii_lowercase();
+        if let Some(security) = self.nsec3_limit() {
+            return security;
+        }
+
+        for (owner, nsec) in &self.nsecs {
+            if *owner == qname && proves_nodata(&nsec.types, qtype) {
+                return Security::Secure;
+            }
+            if nsec_covers(owner, nsec, &qname) {
+                // 次の名前が qname の下にあれば、qname は空の非終端
+                if is_subdomain(&nsec.next_domain_name.to_ascii_lowercase(), &qname) {
+                    return Security::Secure;
+                }
+                let wildcard = wildcard(&closest_encloser(&qname, owner, nsec));
+                if self
+                    .nsecs
+                    .iter()
+                    .any(|(owner, nsec)| *owner == wildcard && proves_nodata(&nsec.types, qtype))
+                {
+                    return Security::Secure;
+                }
+            }
+        }
+
+        if let Some(nsec3) = self.nsec3_matching(&qname) {
+            return if proves_nodata(&nsec3.types, qtype) {
+                Security::Secure
+            } else {
+                Security::Bogus
+            };
+        }
+        if let Some((closest_encloser, next_closer)) = self.nsec3_closest_encloser(&qname) {
+            // DS がないことは、Opt-Out の範囲に含まれることでも示される (RFC 5155 Section 8.6)
+            if qtype == 43 && next_closer.is_opt_out() {
+                return Security::Insecure;
+            }
+            if let Some(nsec3) = self.nsec3_matching(&wildcard(&closest_encloser)) {
+                if proves_nodata(&nsec3.types, qtype) {
+                    return Security::Secure;
+                }
+            }
+        }
+
+        Security::Bogus
+    }
+
+    /** labels 個のラベルのワイルドカードから合成された qname の応答で、qname そのものは存在しないこと */
+    pub fn wildcard_answer(&self, qname: &str, labels: u8) -> Security {
+        let qname = qname.to_ascii_lowercase();
+        if let Some(security) = self.nsec3_limit() {
+            return security;
+        }
+
+        if self
+            .nsecs
+            .iter()
+            .any(|(owner, nsec)| nsec_covers(owner, nsec, &qname))
+        {
+            return Security::Secure;
+        }
+        // 最も近い祖先はワイルドカードの親なので、その 1 つ下の名前がないことを示せばよい
+        let next_closer = last_labels(&qname, usize::from(labels) + 1);
+        if self.nsec3_covering(&next_closer).is_some() {
+            return Security::Secure;
+        }
+
+        Security::Bogus
+    }
+
+    /** 繰り返しが多すぎる NSEC3 は検証しない */
+    fn nsec3_limit(&self) -> Option<Security> {
+        if self
+            .nsec3s
+            .iter()
+            .any(|(_, nsec3)| nsec3.iterations > MAX_NSEC3_ITERATIONS)
+        {
+            Some(Security::Insecure)
+        } else {
+            None
+        }
+    }
+
+    /** RFC 5155 Section 8.3: 一致する NSEC3 がある最も近い祖先と、その 1 つ下の名前を覆う NSEC3 */
+    fn nsec3_closest_encloser(&self, qname: &str) -> Option<(String, &Nsec3)> {
+        let total = label_count(qname);
+        for count in (label_count(&self.zone)..total).rev() {
+            let candidate = last_labels(qname, count);
+            let nsec3 = match self.nsec3_matching(&candidate) {
+                Some(nsec3) => nsec3,
+                None => continue,
+            };
+            // 委任点 (NS があり SOA がない) より下のことは親ゾーンからは証明できない
+            if nsec3.types.contains(&2) && !nsec3.types.contains(&6) {
+                return None;
+            }
+            let next_closer = last_labels(qname, count + 1);
+            return self
+                .nsec3_covering(&next_closer)
+                .map(|nsec3| (candidate, nsec3));
+        }
+        None
+    }
+
+    fn nsec3_matching(&self, name: &str) -> Option<&Nsec3> {
+        self.nsec3s
+            .iter()
+            .find(|(hash, nsec3)| *hash == nsec3_hash(name, &nsec3.salt, nsec3.iterations))
+            .map(|(_, nsec3)| *nsec3)
+    }
+
+    fn nsec3_covering(&self, name: &str) -> Option<&Nsec3> {
+        self.nsec3s
+            .iter()
+            .find(|(owner, nsec3)| {
+                let hash = nsec3_hash(name, &nsec3.salt, nsec3.iterations);
+                let next = &nsec3.next_hashed_owner;
+                if owner < next {
+                    *owner < hash && hash < *next
+                } else {
+                    // ハッシュの順で最後の NSEC3 は先頭に戻る
+                    *owner < hash || hash < *next
+                }
+            })
+            .map(|(_, nsec3)| *nsec3)
+    }
+}
+
+/** RFC 5155 Section 5 のハッシュ (SHA-1 を iterations 回繰り返す) */
+pub fn nsec3_hash(name: &str, salt: &[u8], iterations: u16) -> Vec<u8> {
+    let mut data = encode_checked_name(&name.to_ascii_lowercase());
+    data.extend(salt);
+    let mut hash = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, &data);
+    for _ in 0..iterations {
+        let mut data = hash.as_ref().to_vec();
+        data.extend(salt);
+        hash = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, &data);
+    }
+    hash.as_ref().to_vec()
+}
+
+/** 名前の正規の順序 (RFC 4034 Section 6.1)。右のラベルから、小文字にしたバイト列で比べる */
+pub fn canonical_cmp(a: &str, b: &str) -> Ordering {
+    let labels = |name: &str| -> Vec<Vec<u8>> {
+        name_labels(name)
+            .into_iter()
+            .rev()
+            .map(|label| label.to_ascii_lowercase())
+            .collect()
+    };
+    labels(a).cmp(&labels(b))
+}
+
+/** owner から next までの NSEC が name を覆っているか (owner と next は含まない) */
+pub fn covers(owner: &str, next: &str, name: &str) -> bool {
+    let after_owner = canonical_cmp(owner, name) == Ordering::Less;
+    let before_next = canonical_cmp(name, next) == Ordering::Less;
+    if canonical_cmp(owner, next) == Ordering::Less {
+        after_owner && before_next
+    } else {
+        // ゾーンで最後の NSEC の次の名前は頂点に戻る
+        after_owner || before_next
+    }
+}
+
+/** qname を覆う NSEC (owner) から、qname の最も近い祖先 (closest encloser) を求める */
+fn closest_encloser(qname: &str, owner: &str, nsec: &Nsec) -> String {
+    let common = common_labels(qname, owner).max(common_labels(qname, &nsec.next_domain_name));
+    last_labels(qname, common)
+}
+
+/**
+ * NSEC が name を覆い、name がないことを示すか。
+ * name の祖先の委任点 (NS があり SOA がない) と DNAME の NSEC は、その下の名前について何も示さない (RFC 4035 Section 5.4)
+ */
+fn nsec_covers(owner: &str, nsec: &Nsec, name: &str) -> bool {
+    let cut = (nsec.types.contains(&2) && !nsec.types.contains(&6)) || nsec.types.contains(&39);
+    covers(owner, &nsec.next_domain_name, name) && !(cut && is_subdomain(name, owner))
+}
+
+/** closest_encloser の直下のワイルドカード */
+fn wildcard(closest_encloser: &str) -> String {
+    if closest_encloser.is_empty() {
+        "*".to_string()
+    } else {
+        ["*", closest_encloser].join(".")
+    }
+}
+
+/** タイプビットマップに qtype も CNAME もない。委任点のものは親ゾーン側の DS についてしか使えない */
+fn proves_nodata(types: &[u16], qtype: u16) -> bool {
+    let delegation = types.contains(&2) && !types.contains(&6);
+    (qtype == 43 || !delegation) && !types.contains(&qtype) && !types.contains(&5)
+}
+
+/** 末尾から数えて共通しているラベルの数 */
+fn common_labels(a: &str, b: &str) -> usize {
+    name_labels(a)
+        .iter()
+        .rev()
+        .zip(name_labels(b).iter().rev())
+        .take_while(|(a, b)| a.eq_ignore_ascii_case(b))
+        .count()
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use data_encoding::BASE32HEX_NOPAD;
+
+    use super::nsec3_hash;
+    use crate::dnssec::Nsec3;
+    use crate::message::Resource;
+    use crate::validator::testing::resource;
+
+    /** names のハッシュで作った NSEC3 の連鎖 */
+    pub fn nsec3_chain(zone: &str, names: &[(&str, Vec<u16>)], flags: u8) -> Vec<Resource> {
+        let mut hashed: Vec<(Vec<u8>, Vec<u16>)> = names
+            .iter()
+            .map(|(name, types)| (nsec3_hash(name, &[0xAB], 2), types.clone()))
+            .collect();
+        hashed.sort();
+        (0..hashed.len())
+            .map(|i| {
+                let nsec3 = Nsec3 {
+                    hash_algorithm: 1,
+                    flags,
+                    iterations: 2,
+                    salt: vec![0xAB],
+                    next_hashed_owner: hashed[(i + 1) % hashed.len()].0.clone(),
+                    types: hashed[i].1.clone(),
+                };
+                let owner = [BASE32HEX_NOPAD.encode(&hashed[i].0).as_str(), zone].join(".");
+                resource(&owner, 50, &nsec3.to_rdata())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::nsec3_chain;
+    use super::{canonical_cmp, covers, nsec3_hash, Denial};
+    use crate::dnssec::Nsec;
+    use crate::message::Resource;
+    use crate::validator::testing::resource;
+    use crate::validator::Security;
+    use data_encoding::{BASE32HEX_NOPAD, HEXUPPER};
+    use std::cmp::Ordering;
+
+    #[test]
+    fn nsec3_hash_vectors() {
+        // RFC 5155 Appendix A
+        let salt = HEXUPPER.decode(b"AABBCCDD").unwrap();
+        for (name, hash) in [
+            ("example", "0P9MHAVEQVM6T7VBL5LOP2U3T2RP3TOM"),
+            ("a.example", "35MTHGPGCU1QG68FAB165KLNSNK3DPVL"),
+            ("w.example", "K8UDEMVP1J2F7EG6JEBPS17VP3N8I58H"),
+            ("X.W.Example.", "B4UM86EGHHDS6NEA196SMVMLO4ORS995"),
+        ] {
+            assert_eq!(BASE32HEX_NOPAD.encode(&nsec3_hash(name, &salt, 12)), hash);
+        }
+    }
+
+    #[test]
+    fn canonical_order() {
+        // RFC 4034 Section 6.1 の例
+        let names = [
+            "example",
+            "a.example",
+            "yljkjljk.a.example",
+            "Z.a.example",
+            "zABC.a.EXAMPLE",
+            "z.example",
+            "\u{1}.z.example",
+            "*.z.example",
+            "\u{80}.z.example",
+        ];
+        for pair in names.windows(2) {
+            assert_eq!(canonical_cmp(pair[0], pair[1]), Ordering::Less);
+        }
+        // エスケープされたバイトとドットは、デコードしたラベルで比べる
+        for pair in [
+            ["\\001.z.example", "*.z.example"],
+            ["*.z.example", "\\200.z.example"],
+            ["a\\.b.example", "b.example"],
+        ] {
+            assert_eq!(canonical_cmp(pair[0], pair[1]), Ordering::Less);
+        }
+        assert!(covers("a.example", "z.example", "b.example"));
+        assert!(!covers("a.example", "z.example", "z.example"));
+        assert!(covers("z.example", "example", "zz.example"));
+    }
+
+    /** names を所有者名とする NSEC の連鎖 (最後の NSEC の次は頂点) */
+    fn nsec_chain(names: &[(&str, Vec<u16>)]) -> Vec<Resource> {
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, (owner, types))| {
+                let nsec = Nsec {
+                    next_domain_name: names[(i + 1) % names.len()].0.to_string(),
+                    types: types.clone(),
+                };
+                resource(owner, 47, &nsec.to_rdata())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nsec_proofs() {
+        let zone = nsec_chain(&[
+            ("example", vec![2, 6, 46, 47, 48]),
+            ("a.example", vec![1, 46, 47]),
+            ("deep.sub.example", vec![1, 46, 47]),
+            ("unsigned.example", vec![2, 46, 47]),
+            ("*.w.example", vec![16, 46, 47]),
+            ("x.w.example", vec![1, 46, 47]),
+        ]);
+        let denial = Denial::new("example", &zone);
+
+        assert_eq!(denial.nxdomain("b.example"), Security::Secure);
+        // ワイルドカード *.w.example に一致するので、存在しないとは言えない
+        assert_eq!(denial.nxdomain("y.w.example"), Security::Bogus);
+        assert_eq!(denial.nxdomain("a.example"), Security::Bogus);
+        // 委任点の NSEC は、委任先のゾーンの名前がないことを示さない
+        assert_eq!(denial.nxdomain("www.unsigned.example"), Security::Bogus);
+
+        assert_eq!(denial.nodata("a.example", 28), Security::Secure);
+        assert_eq!(denial.nodata("a.example", 1), Security::Bogus);
+        assert_eq!(denial.nodata("sub.example", 1), Security::Secure);
+        assert_eq!(denial.nodata("y.w.example", 1), Security::Secure);
+        assert_eq!(denial.nodata("y.w.example", 16), Security::Bogus);
+        assert_eq!(denial.nodata("unsigned.example", 43), Security::Secure);
+        assert_eq!(denial.nodata("unsigned.example", 1), Security::Bogus);
+
+        assert_eq!(denial.wildcard_answer("y.w.example", 2), Security::Secure);
+        assert_eq!(denial.wildcard_answer("x.w.example", 2), Security::Bogus);
+
+        // 一部の NSEC だけでは証明にならない
+        let partial = Denial::new("example", &zone[1..2]);
+        assert_eq!(partial.nxdomain("b.example"), Security::Bogus);
+    }
+
+    #[test]
+    fn nsec3_proofs() {
+        let names = [
+            ("example", vec![2, 6, 46, 48, 51]),
+            ("a.example", vec![1, 46]),
+            ("w.example", vec![]),
+            ("*.w.example", vec![16, 46]),
+            ("x.w.example", vec![1, 46]),
+        ];
+        let zone = nsec3_chain("example", &names, 0);
+        let denial = Denial::new("example", &zone);
+
+        assert_eq!(denial.nxdomain("b.example"), Security::Secure);
+        assert_eq!(denial.nxdomain("y.w.example"), Security::Bogus);
+        assert_eq!(denial.nodata("a.example", 28), Security::Secure);
+        assert_eq!(denial.nodata("a.example", 1), Security::Bogus);
+        assert_eq!(denial.nodata("y.w.example", 1), Security::Secure);
+        assert_eq!(denial.wildcard_answer("y.w.example", 2), Security::Secure);
+        assert_eq!(denial.wildcard_answer("x.w.example", 2), Security::Bogus);
+
+        // Opt-Out の範囲にある委任は、DS がないことが示されても署名されていない
+        let opt_out = nsec3_chain("example", &names, 1);
+        let denial = Denial::new("example", &opt_out);
+        assert_eq!(denial.nodata("unsigned.example", 43), Security::Insecure);
+        assert_eq!(denial.nxdomain("b.example"), Security::Insecure);
+
+        // 繰り返しが多すぎる NSEC3 は計算しない
+        let mut expensive = zone.clone();
+        let mut nsec3 = expensive[0].nsec3.clone().unwrap();
+        nsec3.iterations = 500;
+        expensive[0] = resource(&expensive[0].name.clone(), 50, &nsec3.to_rdata());
+        assert_eq!(
+            Denial::new("example", &expensive).nxdomain("b.example"),
+            Security::Insecure
+        );
+    }
+}
@@ -0,0 +1,619 @@
+use data_encoding::{BASE32HEX_NOPAD, BASE64, HEXUPPER};
+use std::fmt;
+
+use crate::message::{encode_checked_name, escape_label, presentation_name, read_u16, type_name};
+
+/** DNSKEY (RFC 4034 Section 2) */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dnskey {
+    pub flags: u16,
+    pub protocol: u8,
+    pub algorithm: u8,
+    pub public_key: Vec<u8>,
+}
+
+impl Dnskey {
+    pub fn parse(rdata: &[u8]) -> Option<Self> {
+        Some(Self {
+            flags: read_u16(rdata, 0)?,
+            protocol: *rdata.get(2)?,
+            algorithm: *rdata.get(3)?,
+            public_key: rdata.get(4..)?.to_vec(),
+        })
+    }
+
+    pub fn to_rdata(&self) -> Vec<u8> {
+        let mut rdata = Vec::new();
+        rdata.extend(self.flags.to_be_bytes());
+        rdata.push(self.protocol);
+        rdata.push(self.algorithm);
+        rdata.extend(&self.public_key);
+        rdata
+    }
+
+    /** RFC 4034 Appendix B のキータグ */
+    pub fn key_tag(&self) -> u16 {
+        let mut ac: u32 = 0;
+        for (i, byte) in self.to_rdata().iter().enumerate() {
+            ac += if i & 1 == 1 {
+                u32::from(*byte)
+            } else {
+                u32::from(*byte) << 8
+            };
+        }
+        ac += (ac >> 16) & 0xFFFF;
+        (ac & 0xFFFF) as u16
+    }
+
+    /** Zone Key フラグ (ビット 7) */
+    pub fn is_zone_key(&self) -> bool {
+        self.flags & 0x0100 != 0
+    }
+
+    /** REVOKE フラグ (ビット 8, RFC 5011) */
+    pub fn is_revoked(&self) -> bool {
+        self.flags & 0x0080 != 0
+    }
+
+    /** Secure Entry Point フラグ (ビット 15) */
+    pub fn is_sep(&self) -> bool {
+        self.flags & 0x0001 != 0
+    }
+}
+
+impl fmt::Display for Dnskey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.flags,
+            self.protocol,
+            self.algorithm,
+            BASE64.encode(&self.public_key)
+        )
+    }
+}
+
+/** DS (RFC 4034 Section 5) */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ds {
+    pub key_tag: u16,
+    pub algorithm: u8,
+    pub digest_type: u8,
+    pub digest: Vec<u8>,
+}
+
+impl Ds {
+    pub fn parse(rdata: &[u8]) -> Option<Self> {
+        Some(Self {
+            key_tag: read_u16(rdata, 0)?,
+            algorithm: *rdata.get(2)?,
+            digest_type: *rdata.get(3)?,
+            digest: rdata.get(4..)?.to_vec(),
+        })
+    }
+
+    pub fn to_rdata(&self) -> Vec<u8> {
+        let mut rdata = Vec::new();
+        rdata.extend(self.key_tag.to_be_bytes());
+        rdata.push(self.algorithm);
+        rdata.push(self.digest_type);
+        rdata.extend(&self.digest);
+        rdata
+    }
+}
+
+impl fmt::Display for Ds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.key_tag,
+            self.algorithm,
+            self.digest_type,
+            HEXUPPER.encode(&self.digest)
+        )
+    }
+}
+
+/** RRSIG (RFC 4034 Section 3) */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rrsig {
+    pub type_covered: u16,
+    pub algorithm: u8,
+    pub labels: u8,
+    pub original_ttl: u32,
+    pub expiration: u32,
+    pub inception: u32,
+    pub key_tag: u16,
+    pub signer_name: String,
+    pub signature: Vec<u8>,
+}
+
+impl Rrsig {
+    pub fn parse(rdata: &[u8]) -> Option<Self> {
+        let (signer_name, position) = read_name(rdata, 18)?;
+        Some(Self {
+            type_covered: read_u16(rdata, 0)?,
+            algorithm: *rdata.get(2)?,
+            labels: *rdata.get(3)?,
+            original_ttl: read_u32(rdata, 4)?,
+            expiration: read_u32(rdata, 8)?,
+            inception: read_u32(rdata, 12)?,
+            key_tag: read_u16(rdata, 16)?,
+            signer_name,
+            signature: rdata.get(position..)?.to_vec(),
+        })
+    }
+
+    /** 署名の対象となる、署名フィールドを除いた正規形 (署名者名が小文字) の RDATA */
+    pub fn to_rdata_without_signature(&self) -> Vec<u8> {
+        self.fields(&self.signer_name.to_ascii_lowercase())
+    }
+
+    pub fn to_rdata(&self) -> Vec<u8> {
+        let mut rdata = self.fields(&self.signer_name);
+        rdata.extend(&self.signature);
+        rdata
+    }
+
+    fn fields(&self, signer_name: &str) -> Vec<u8> {
+        let mut rdata = Vec::new();
+        rdata.extend(self.type_covered.to_be_bytes());
+        rdata.push(self.algorithm);
+        rdata.push(self.labels);
+        rdata.extend(self.original_ttl.to_be_bytes());
+        rdata.extend(self.expiration.to_be_bytes());
+        rdata.extend(self.inception.to_be_bytes());
+        rdata.extend(self.key_tag.to_be_bytes());
+        rdata.extend(encode_checked_name(signer_name));
+        rdata
+    }
+}
+
+impl fmt::Display for Rrsig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {} {} {} {} {}",
+            type_name(self.type_covered),
+            self.algorithm,
+            self.labels,
+            self.original_ttl,
+            format_time(self.expiration),
+            format_time(self.inception),
+            self.key_tag,
+            presentation_name(&self.signer_name),
+            BASE64.encode(&self.signature)
+        )
+    }
+}
+
+/** NSEC (RFC 4034 Section 4) */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nsec {
+    pub next_domain_name: String,
+    pub types: Vec<u16>,
+}
+
+impl Nsec {
+    pub fn parse(rdata: &[u8]) -> Option<Self> {
+        let (next_domain_name, position) = read_name(rdata, 0)?;
+        Some(Self {
+            next_domain_name,
+            types: parse_type_bitmaps(rdata.get(position..)?)?,
+        })
+    }
+
+    /** 次の名前は RFC 6840 Section 5.1 により正規形でも小文字にしない */
+    pub fn to_rdata(&self) -> Vec<u8> {
+        let mut rdata = encode_checked_name(&self.next_domain_name);
+        rdata.extend(encode_type_bitmaps(&self.types));
+        rdata
+    }
+}
+
+impl fmt::Display for Nsec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", presentation_name(&self.next_domain_name))?;
+        for rr_type in &self.types {
+            write!(f, " {}", type_name(*rr_type))?;
+        }
+        Ok(())
+    }
+}
+
+/** NSEC3 (RFC 5155 Section 3) */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nsec3 {
+    pub hash_algorithm: u8,
+    pub flags: u8,
+    pub iterations: u16,
+    pub salt: Vec<u8>,
+    pub next_hashed_owner: Vec<u8>,
+    pub types: Vec<u16>,
+}
+
+impl Nsec3 {
+    pub fn parse(rdata: &[u8]) -> Option<Self> {
+        let salt_length = usize::from(*rdata.get(4)?);
+        let salt_end = 5 + salt_length;
+        let hash_length = usize::from(*rdata.get(salt_end)?);
+        let hash_end = salt_end + 1 + hash_length;
+        Some(Self {
+            hash_algorithm: *rdata.first()?,
+            flags: *rdata.get(1)?,
+            iterations: read_u16(rdata, 2)?,
+            salt: rdata.get(5..salt_end)?.to_vec(),
+            next_hashed_owner: rdata.get(salt_end + 1..hash_end)?.to_vec(),
+            types: parse_type_bitmaps(rdata.get(hash_end..)?)?,
+        })
+    }
+
+    pub fn to_rdata(&self) -> Vec<u8> {
+        let mut rdata = vec![self.hash_algorithm, self.flags];
+        rdata.extend(self.iterations.to_be_bytes());
+        rdata.push(self.salt.len() as u8);
+        rdata.extend(&self.salt);
+        rdata.push(self.next_hashed_owner.len() as u8);
+        rdata.extend(&self.next_hashed_owner);
+        rdata.extend(encode_type_bitmaps(&self.types));
+        rdata
+    }
+
+    /** Opt-Out フラグ */
+    pub fn is_opt_out(&self) -> bool {
+        self.flags & 0x01 != 0
+    }
+}
+
+impl fmt::Display for Nsec3 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {}",
+            self.hash_algorithm,
+            self.flags,
+            self.iterations,
+            presentation_salt(&self.salt),
+            BASE32HEX_NOPAD.encode(&self.next_hashed_owner)
+        )?;
+        for rr_type in &self.types {
+            write!(f, " {}", type_name(*rr_type))?;
+        }
+        Ok(())
+    }
+}
+
+/** NSEC3PARAM (RFC 5155 Section 4) */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nsec3param {
+    pub hash_algorithm: u8,
+    pub flags: u8,
+    pub iterations: u16,
+    pub salt: Vec<u8>,
+}
+
+impl Nsec3param {
+    pub fn parse(rdata: &[u8]) -> Option<Self> {
+        let salt_length = usize::from(*rdata.get(4)?);
+        Some(Self {
+            hash_algorithm: *rdata.first()?,
+            flags: *rdata.get(1)?,
+            iterations: read_u16(rdata, 2)?,
+            salt: rdata.get(5..5 + salt_length)?.to_vec(),
+        })
+    }
+
+    pub fn to_rdata(&self) -> Vec<u8> {
+        let mut rdata = vec![self.hash_algorithm, self.flags];
+        rdata.extend(self.iterations.to_be_bytes());
+        rdata.push(self.salt.len() as u8);
+        rdata.extend(&self.salt);
+        rdata
+    }
+}
+
+impl fmt::Display for Nsec3param {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.hash_algorithm,
+            self.flags,
+            self.iterations,
+            presentation_salt(&self.salt)
+        )
+    }
+}
+
+/** NSEC と NSEC3 のタイプビットマップ (RFC 4034 Section 4.1.2) を解析する */
+pub fn parse_type_bitmaps(bytes: &[u8]) -> Option<Vec<u16>> {
+    let mut types = Vec::new();
+    let mut position = 0;
+    while position < bytes.len() {
+        let window = u16::from(*bytes.get(position)?);
+        let length = usize::from(*bytes.get(position + 1)?);
+        if length == 0 || length > 32 {
+            return None;
+        }
+        let bitmap = bytes.get(position + 2..position + 2 + length)?;
+        for (i, byte) in bitmap.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    types.push(window * 256 + (i * 8 + bit) as u16);
+                }
+            }
+        }
+        position += 2 + length;
+    }
+    Some(types)
+}
+
+/** タイプの一覧をタイプビットマップにする */
+pub fn encode_type_bitmaps(types: &[u16]) -> Vec<u8> {
+    let mut types = types.to_vec();
+    types.sort_unstable();
+    types.dedup();
+
+    let mut bytes = Vec::new();
+    let mut index = 0;
+    while index < types.len() {
+        let window = types[index] / 256;
+        let in_window: Vec<u16> = types[index..]
+            .iter()
+            .take_while(|rr_type| **rr_type / 256 == window)
+            .map(|rr_type| rr_type % 256)
+            .collect();
+        index += in_window.len();
+
+        let length = usize::from(*in_window.last().unwrap() / 8) + 1;
+        let mut bitmap = vec![0u8; length];
+        for rr_type in in_window {
+            bitmap[usize::from(rr_type / 8)] |= 0x80 >> (rr_type % 8);
+        }
+        bytes.push(window as u8);
+        bytes.push(length as u8);
+        bytes.extend(bitmap);
+    }
+    bytes
+}
+
+/** RRSIG の時刻 (1970 年からの秒) を YYYYMMDDHHmmSS 形式にする */
+pub fn format_time(time: u32) -> String {
+    let days = i64::from(time / 86400);
+    let seconds = time % 86400;
+
+    // days_from_civil の逆変換 (http://howardhinnant.github.io/date_algorithms.html)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/** RRSIG の時刻の表記 (YYYYMMDDHHmmSS か 10 進数の秒) を 1970 年からの秒にする (RFC 4034 Section 3.2) */
+pub fn parse_time(text: &str) -> Option<u32> {
+    if !text.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    if text.len() != 14 {
+        return text.parse().ok();
+    }
+    let field = |range: std::ops::Range<usize>| text[range].parse::<i64>().unwrap();
+    let (year, month, day) = (field(0..4), field(4..6), field(6..8));
+    if !(1..=12).contains(&month) {
+        return None;
+    }
+
+    // days_from_civil (http://howardhinnant.github.io/date_algorithms.html)
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let seconds = days * 86400 + field(8..10) * 3600 + field(10..12) * 60 + field(12..14);
+    let time = u32::try_from(seconds).ok()?;
+    // 2 月 30 日や 25 時のような存在しない時刻は、書き戻すと一致しない
+    if format_time(time) != text {
+        return None;
+    }
+    Some(time)
+}
+
+fn presentation_salt(salt: &[u8]) -> String {
+    if salt.is_empty() {
+        "-".to_string()
+    } else {
+        HEXUPPER.encode(salt)
+    }
+}
+
+fn read_u32(bytes: &[u8], position: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        bytes.get(position..position + 4)?.try_into().ok()?,
+    ))
+}
+
+/** 圧縮されていない NAME を読む (DNSSEC のレコードでは圧縮が禁止されている) */
+fn read_name(bytes: &[u8], offset: usize) -> Option<(String, usize)> {
+    let mut position = offset;
+    let mut labels = Vec::new();
+    loop {
+        let length = usize::from(*bytes.get(position)?);
+        position += 1;
+        if length == 0 {
+            break;
+        }
+        if length & 0b11000000 != 0 {
+            return None;
+        }
+        let label = bytes.get(position..position + length)?;
+        labels.push(escape_label(label));
+        position += length;
+    }
+    // 名前はワイヤ形式で 255 バイトまで (RFC 1035 Section 2.3.4)
+    if position - offset > 255 {
+        return None;
+    }
+    Some((labels.join("."), position))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_time, parse_time, Dnskey, Ds, Nsec, Nsec3, Nsec3param, Rrsig};
+    use data_encoding::{BASE32HEX_NOPAD, BASE64, HEXUPPER};
+
+    /** RFC 4034 Section 5.4 の例 */
+    fn rfc4034_dnskey() -> Dnskey {
+        Dnskey {
+            flags: 256,
+            protocol: 3,
+            algorithm: 5,
+            public_key: BASE64
+                .decode(
+                    b"AQOeiiR0GOMYkDshWoSKz9XzfwJr1AYtsmx3TGkJaNXVbfi/2pHm822aJ5iI9BMzNXxeYCmZDRD99WYwYqUSdjMmmAphXdvxegXd/M5+X7OrzKBaMbCVdFLUUh6DhweJBjEVv5f2wwjM9XzcnOf+EPbtG9DMBmADjFDc2w/rljwvFw==",
+                )
+                .unwrap(),
+        }
+    }
+
+    #[test]
+    fn dnskey_roundtrip() {
+        let dnskey = rfc4034_dnskey();
+        assert_eq!(dnskey.key_tag(), 60485);
+        assert!(dnskey.is_zone_key());
+        assert!(!dnskey.is_sep());
+        assert!(!dnskey.is_revoked());
+        assert_eq!(Dnskey::parse(&dnskey.to_rdata()), Some(dnskey.clone()));
+        assert!(dnskey.to_string().starts_with("256 3 5 AQOeiiR0GOMYkDsh"));
+    }
+
+    #[test]
+    fn ds_roundtrip() {
+        let ds = Ds {
+            key_tag: 60485,
+            algorithm: 5,
+            digest_type: 1,
+            digest: HEXUPPER
+                .decode(b"2BB183AF5F22588179A53B0A98631FAD1A292118")
+                .unwrap(),
+        };
+        assert_eq!(Ds::parse(&ds.to_rdata()), Some(ds.clone()));
+        assert_eq!(
+            ds.to_string(),
+            "60485 5 1 2BB183AF5F22588179A53B0A98631FAD1A292118"
+        );
+    }
+
+    #[test]
+    fn rrsig_roundtrip() {
+        let rrsig = Rrsig {
+            type_covered: 1,
+            algorithm: 5,
+            labels: 3,
+            original_ttl: 86400,
+            expiration: 1081539377,
+            inception: 1078861057,
+            key_tag: 2642,
+            signer_name: "Example.com".to_string(),
+            signature: vec![1, 2, 3, 4],
+        };
+        let rdata = rrsig.to_rdata();
+        assert_eq!(Rrsig::parse(&rdata), Some(rrsig.clone()));
+        // 正規形では署名者名を小文字にする
+        assert_eq!(
+            &rrsig.to_rdata_without_signature()[18..],
+            b"\x07example\x03com\x00"
+        );
+        assert_eq!(
+            rrsig.to_string(),
+            "A 5 3 86400 20040409193617 20040309193737 2642 Example.com. AQIDBA=="
+        );
+        assert!(Rrsig::parse(&rdata[..20]).is_none());
+    }
+
+    #[test]
+    fn nsec_type_bitmaps() {
+        // RFC 4034 Section 4.3 の例
+        let nsec = Nsec {
+            next_domain_name: "host.example.com".to_string(),
+            types: vec![1, 15, 46, 47, 1234],
+        };
+        let rdata = nsec.to_rdata();
+        let mut expect = b"\x04host\x07example\x03com\x00".to_vec();
+        expect.extend([0x00, 0x06, 0x40, 0x01, 0x00, 0x00, 0x00, 0x03]);
+        expect.extend([0x04, 0x1b]);
+        expect.extend([0x00; 26]);
+        expect.push(0x20);
+        assert_eq!(rdata, expect);
+        assert_eq!(Nsec::parse(&rdata), Some(nsec.clone()));
+        assert_eq!(
+            nsec.to_string(),
+            "host.example.com. A MX RRSIG NSEC TYPE1234"
+        );
+    }
+
+    #[test]
+    fn nsec3_roundtrip() {
+        // RFC 5155 Appendix A の例
+        let nsec3 = Nsec3 {
+            hash_algorithm: 1,
+            flags: 1,
+            iterations: 12,
+            salt: vec![0xaa, 0xbb, 0xcc, 0xdd],
+            next_hashed_owner: BASE32HEX_NOPAD
+                .decode(b"2T7B4G4VSA5SMI47K61MV5BV1A22BOJR")
+                .unwrap(),
+            types: vec![2, 6, 15, 46, 48, 51],
+        };
+        assert!(nsec3.is_opt_out());
+        assert_eq!(Nsec3::parse(&nsec3.to_rdata()), Some(nsec3.clone()));
+        assert_eq!(
+            nsec3.to_string(),
+            "1 1 12 AABBCCDD 2T7B4G4VSA5SMI47K61MV5BV1A22BOJR NS SOA MX RRSIG DNSKEY NSEC3PARAM"
+        );
+
+        let nsec3param = Nsec3param {
+            hash_algorithm: 1,
+            flags: 0,
+            iterations: 0,
+            salt: Vec::new(),
+        };
+        assert_eq!(
+            Nsec3param::parse(&nsec3param.to_rdata()),
+            Some(nsec3param.clone())
+        );
+        assert_eq!(nsec3param.to_string(), "1 0 0 -");
+    }
+
+    #[test]
+    fn rrsig_time() {
+        assert_eq!(format_time(0), "19700101000000");
+        assert_eq!(format_time(951782400), "20000229000000");
+        assert_eq!(format_time(u32::MAX), "21060207062815");
+
+        assert_eq!(parse_time("20000229000000"), Some(951782400));
+        assert_eq!(parse_time("21060207062815"), Some(u32::MAX));
+        assert_eq!(parse_time("1700000000"), Some(1700000000));
+        assert_eq!(parse_time("20230230000000"), None);
+        assert_eq!(parse_time("21060207062816"), None);
+        assert_eq!(parse_time("2023-01-01"), None);
+    }
+}
@@ -1,149 +1,2576 @@
-use core::time;
+use rand::seq::SliceRandom;
 use rand::Rng;
-use std::net::UdpSocket;
-use std::thread::sleep;
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
+use crate::address_selection;
+use crate::config::{Mode, ResolverConfig};
+use crate::denial::Denial;
+use crate::dnssec::{Dnskey, Ds};
+use crate::inflight::Inflight;
 use crate::message;
+use crate::nsec_cache::NsecCache;
+use crate::rtt::RttTable;
+use crate::trust_anchor::TrustAnchor;
+use crate::validator::{self, Security};
 
-const ROOT_NAME_SERVERS: [&str; 13] = [
-    // See: root-servers.org
-    "198.41.0.4",
-    "199.9.14.201",
-    "192.33.4.12",
-    "199.7.91.13",
-    "192.203.230.10",
-    "192.5.5.241",
-    "192.112.36.4",
-    "198.97.190.53",
-    "192.36.148.17",
-    "192.58.128.30",
-    "193.0.14.129",
-    "199.7.83.42",
-    "202.12.27.33",
-];
-
-pub fn resolve_iterative(fqdn: &str, qtype: u16, nameserver: &str) -> Option<String> {
-    let mut rng = rand::thread_rng();
-    let id: u16 = rng.gen();
+/** EDNS0 で広告する UDP ペイロードサイズ (DNS Flag Day 2020 の推奨値) */
+const EDNS_UDP_PAYLOAD_SIZE: u16 = 1232;
 
-    // 以下の条件に達するまでクエリを投げ続ける
-    // - Answer が得られる
-    // - RCODE が 0 以外で何らかのエラーが生じている
-    let message = message::Message {
-        header: message::Header::create(
-            id, 0b0, 0b0000, 0b0, 0b0, 0b0, 0b0, 0b000, 0b0000, 0x0001, 0x0000, 0x0000, 0x0000,
-        ),
-        question: message::Question::new(fqdn, qtype, 0x0001),
-    };
+/** プライミングに失敗したとき、ルートヒントを使い続けてから再び試すまでの時間 */
+const PRIMING_RETRY: Duration = Duration::from_secs(60);
 
-    println!("{:?} に問い合わせます...", nameserver);
-    sleep(time::Duration::from_millis(2000));
+/** プライミングを試すルートヒントのサーバ数 */
+const PRIMING_ATTEMPTS: usize = 3;
 
-    let socket = UdpSocket::bind("0.0.0.0:12345").expect("Couldn't bind to address");
-    let buffer = message.to_bytes();
-    let buffer_slice = buffer.as_slice();
-    socket
-        .send_to(&buffer_slice, [nameserver, ":53"].concat())
-        .expect("Couldn't send");
+/** ルートの NS の TTL がこれより短くても、この間は再びプライミングしない */
+const PRIMING_MIN_INTERVAL: Duration = Duration::from_secs(300);
 
-    // Receive
-    let mut buf = [0; 512];
-    let (number_of_bytes, src_addr) = socket.recv_from(&mut buf).expect("Didn't receive data");
-    println!(
-        "number_of_bytes: {}, src_addr: {:?}",
-        number_of_bytes, src_addr
-    );
-    println!("{:?}", buf);
+/** 1 つのゾーンについて、応答がない場合に試すネームサーバの数 */
+const MAX_SERVER_ATTEMPTS: usize = 3;
 
-    drop(socket);
+/** QNAME 最小化で、ラベルを 1 つずつ明かす問い合わせの回数 (RFC 9156 の MINIMISE_ONE_LAB) */
+const MINIMISE_ONE_LAB: usize = 4;
 
-    // Response
-    let mut ret_header: [u8; 12] = Default::default();
-    ret_header.copy_from_slice(&buf[0..12]);
-    let ret_header = message::Header::parse(&ret_header);
+/** QNAME 最小化で送る問い合わせの最大数 (RFC 9156 の MAX_MINIMISE_COUNT) */
+const MAX_MINIMISE_COUNT: usize = 10;
 
-    let ret_body = &buf[12..512];
-    let questions = message::Question::parse(&ret_body, ret_header.qd_count.into());
-    println!("{:?}", questions.0);
+/** SVCB / HTTPS の AliasMode をたどる回数の上限 (RFC 9460 Section 2.4.2) */
+const MAX_ALIAS_CHAIN: usize = 8;
 
-    let ret_body = &buf[(questions.1 + 12)..512];
-    let resources = message::Resource::parse(
-        &buf,
-        ret_body,
-        (ret_header.an_count + ret_header.ns_count + ret_header.ar_count).into(),
-    );
+/** CNAME をたどる回数の上限 */
+const MAX_CNAME_CHAIN: usize = 8;
 
-    println!("{:?}", resources);
+/** 1 つの反復問い合わせでたどる委任の数の上限 */
+const MAX_REFERRALS: usize = 30;
 
-    // 判定
-    if ret_header.an_count > 0 {
-        println!("結果が得られました。終了します");
-        let end = ret_header.an_count as usize;
-        let an_records = &resources[0..end];
+/** グルーのない委任で、ネームサーバの名前の解決を入れ子にできる深さ */
+const MAX_GLUELESS_DEPTH: usize = 4;
+
+thread_local! {
+    /** このスレッドで解決中の、グルーのない委任のネームサーバの数 */
+    static GLUELESS_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
 
-        println!("Answer records: {:?}", an_records);
+/** 問い合わせ先, QNAME (小文字), QTYPE, RD の組で同一の問い合わせを判定する */
+type QueryKey = (SocketAddr, String, u16, bool);
 
-        match &an_records[0].address {
-            message::IpAddr::V4(ipv4) => return Some(ipv4.to_string()),
-            message::IpAddr::V6(_) => return None,
+/** 複数のスレッドから共有して使うリゾルバ */
+pub struct Resolver {
+    config: ResolverConfig,
+    inflight: Inflight<QueryKey, Option<Arc<message::Message>>>,
+    /** rotate で最初に試す上位リゾルバを決めるための通し番号 */
+    rotation: AtomicUsize,
+    roots: RwLock<Roots>,
+    /** プライミングを同時に 1 つだけ行うためのロック */
+    priming: Mutex<()>,
+    rtt: RttTable,
+    /** 0x20 で変えた大文字・小文字をそのまま返さないサーバ */
+    case_insensitive_servers: Mutex<HashSet<SocketAddr>>,
+    /** RFC 5011 で更新していくトラストアンカー */
+    trust_anchor: RwLock<TrustAnchor>,
+    /** 検証済みの否定応答の NSEC / NSEC3 */
+    nsec_cache: NsecCache,
+}
+
+/** 反復問い合わせでたどったゾーン。DNSSEC の検証で、ここから DNSKEY を取得する */
+struct ZoneCut {
+    /** 小文字・末尾のドットなし */
+    zone: String,
+    nameservers: Vec<SocketAddr>,
+    /** 親ゾーンのサーバからの委任の応答 (DS を含む)。出発点のゾーンでは None */
+    referral: Option<Arc<message::Message>>,
+}
+
+/** プライミングで得たルートサーバのアドレスと、その有効期限 */
+struct Roots {
+    addresses: Vec<SocketAddr>,
+    /** まだプライミングしていなければ None */
+    expires: Option<Instant>,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self::with_config(ResolverConfig::new())
+    }
+
+    pub fn with_config(config: ResolverConfig) -> Self {
+        // 保存したトラストアンカーがあれば、設定よりそちらを使う
+        let trust_anchor = config
+            .trust_anchor_file
+            .as_deref()
+            .and_then(|path| TrustAnchor::load(path).ok())
+            .unwrap_or_else(|| config.trust_anchor.clone());
+        Self {
+            config,
+            inflight: Inflight::new(),
+            rotation: AtomicUsize::new(0),
+            roots: RwLock::new(Roots {
+                addresses: Vec::new(),
+                expires: None,
+            }),
+            priming: Mutex::new(()),
+            rtt: RttTable::new(),
+            case_insensitive_servers: Mutex::new(HashSet::new()),
+            trust_anchor: RwLock::new(trust_anchor),
+            nsec_cache: NsecCache::new(),
         }
     }
-    if ret_header.rcode() > 0 {
+
+    /**
+     * ルートヒントのサーバに ". NS" を問い合わせ、ルートサーバの一覧を更新する (RFC 8109)。
+     * 起動時に呼んでおく。呼ばなければ最初の反復問い合わせのときに行う
+     */
+    pub fn prime(&self) -> bool {
+        // 片方のファミリに経路がなくても試せるよう、IPv6 と IPv4 を交互に試す
+        let mut addresses = self.config.root_hints.addresses();
+        addresses.shuffle(&mut rand::thread_rng());
+        let hints: Vec<SocketAddr> = address_selection::interleave(&addresses)
+            .into_iter()
+            .map(|address| SocketAddr::new(address, 53))
+            .collect();
+
+        for hint in hints.iter().take(PRIMING_ATTEMPTS) {
+            if self.prime_from(*hint) {
+                return true;
+            }
+        }
+
+        println!("プライミングに失敗しました。しばらくルートヒントをそのまま使います");
+        *self.roots.write().unwrap() = Roots {
+            addresses: hints,
+            expires: Some(Instant::now() + PRIMING_RETRY),
+        };
+        false
+    }
+
+    fn prime_from(&self, nameserver: SocketAddr) -> bool {
+        println!("{:?} にプライミングクエリを送ります", nameserver);
+
+        // 13 台分のグルーが 512 バイトに収まらないので、EDNS0 を必ず使う
+        let config = ResolverConfig {
+            edns0: true,
+            ..self.config.clone()
+        };
+        let response = match send_query(&config, nameserver, "", 2, false) {
+            Some(response) => response,
+            None => return false,
+        };
+        let ns_records: Vec<&message::Resource> = response
+            .answers
+            .iter()
+            .filter(|r| r.rr_type == 2 && r.name.is_empty())
+            .collect();
+        if response.header.rcode() > 0 || ns_records.is_empty() {
+            println!("プライミングの応答にルートの NS がありません");
+            return false;
+        }
+
+        let addresses: Vec<SocketAddr> = response
+            .additionals
+            .iter()
+            .filter(|r| {
+                (r.rr_type == 1 || r.rr_type == 28)
+                    && ns_records
+                        .iter()
+                        .any(|ns| ns.nsdname.eq_ignore_ascii_case(&r.name))
+            })
+            .filter_map(|r| r.address)
+            .map(|address| SocketAddr::new(address, 53))
+            .collect();
+        if addresses.is_empty() {
+            println!("プライミングの応答にルートサーバのアドレスがありません");
+            return false;
+        }
+
+        let ttl = ns_records.iter().map(|r| r.ttl).min().unwrap_or(0);
         println!(
-            "エラーが返されました (RCODE: {:?}) 。終了します",
-            ret_header.rcode()
+            "ルートサーバの一覧を更新しました: {:?} (TTL: {})",
+            addresses, ttl
         );
-        return None;
+        *self.roots.write().unwrap() = Roots {
+            addresses,
+            expires: Some(
+                Instant::now() + Duration::from_secs(ttl.into()).max(PRIMING_MIN_INTERVAL),
+            ),
+        };
+        true
+    }
+
+    /** 有効なルートサーバの一覧を返す。まだ取得していないか期限切れならプライミングする */
+    fn root_servers(&self) -> Vec<SocketAddr> {
+        if let Some(addresses) = self.current_roots() {
+            return addresses;
+        }
+
+        let _priming = self.priming.lock().unwrap();
+        // ロックを待つ間に、他のスレッドがプライミングを終えているかもしれない
+        if let Some(addresses) = self.current_roots() {
+            return addresses;
+        }
+        self.prime();
+        self.roots.read().unwrap().addresses.clone()
     }
 
-    println!("ここに答えはありませんでした。次の問い合わせ先を探します");
+    fn current_roots(&self) -> Option<Vec<SocketAddr>> {
+        let roots = self.roots.read().unwrap();
+        match roots.expires {
+            Some(expires) if expires > Instant::now() => Some(roots.addresses.clone()),
+            _ => None,
+        }
+    }
 
-    // 次の問い合わせ先を探す
-    let begin = ret_header.an_count as usize;
-    let end = begin + ret_header.ns_count as usize;
-    let ns_records = &resources[begin..end];
+    /** nameservers のうち速いものから問い合わせ、最終的な応答 (Answer を含むか、RCODE が 0 以外のもの) を返す */
+    pub fn resolve_iterative(
+        &self,
+        fqdn: &str,
+        qtype: u16,
+        nameservers: &[SocketAddr],
+    ) -> Option<Arc<message::Message>> {
+        self.resolve_iterative_validated(fqdn, qtype, nameservers)
+            .map(|(response, _)| response)
+    }
 
-    println!(
-        "{:?} について、 {:?} などが知っているようです。問い合わせてみましょう",
-        ns_records[0].name, ns_records[0].nsdname
-    );
+    /** resolve_iterative と同じく問い合わせ、dnssec が有効なら応答を検証した結果も返す。nameservers はルートのサーバとみなす */
+    pub fn resolve_iterative_validated(
+        &self,
+        fqdn: &str,
+        qtype: u16,
+        nameservers: &[SocketAddr],
+    ) -> Option<(Arc<message::Message>, Security)> {
+        if !is_valid_name(fqdn) {
+            return None;
+        }
+        if self.config.dnssec && self.config.aggressive_nsec {
+            if let Some(response) = self.nsec_cache.synthesise(fqdn, qtype, validator::now()) {
+                println!(
+                    "{:?} の否定応答を、検証済みの NSEC / NSEC3 から合成しました (RCODE: {:?})",
+                    fqdn,
+                    response.header.rcode()
+                );
+                return Some((Arc::new(response), Security::Secure));
+            }
+        }
+
+        let minimisation = if self.config.qname_minimisation {
+            Some(Minimisation::default())
+        } else {
+            None
+        };
+        let mut path = vec![ZoneCut {
+            zone: "".to_string(),
+            nameservers: nameservers.to_vec(),
+            referral: None,
+        }];
+        let response = self.iterate(fqdn, qtype, nameservers, minimisation, &mut path)?;
+
+        let security = if self.config.dnssec {
+            self.validate(&path, &response)
+        } else {
+            Security::Indeterminate
+        };
+        Some((response, security))
+    }
+
+    fn iterate(
+        &self,
+        fqdn: &str,
+        qtype: u16,
+        nameservers: &[SocketAddr],
+        minimisation: Option<Minimisation>,
+        path: &mut Vec<ZoneCut>,
+    ) -> Option<Arc<message::Message>> {
+        // QNAME 最小化: 完全な QNAME を明かすまでは、ラベルを少しずつ増やして問い合わせる
+        if let Some(minimisation) = minimisation {
+            let next = minimisation.next(message::label_count(fqdn));
+            if next.labels < message::label_count(fqdn) {
+                return self.iterate_minimised(fqdn, qtype, nameservers, next, path);
+            }
+        }
+
+        // 以下の条件に達するまでクエリを投げ続ける
+        // - Answer が得られる
+        // - RCODE が 0 以外で何らかのエラーが生じている
+        let response = self.query_fastest(nameservers, fqdn, qtype)?;
+        let ret_header = &response.header;
+
+        // 判定
+        if !response.answers.is_empty() {
+            println!("結果が得られました。終了します");
+            for answer in &response.answers {
+                println!("{}", answer);
+            }
+            return Some(response);
+        }
+        if ret_header.rcode() > 0 {
+            println!(
+                "エラーが返されました (RCODE: {:?}) 。終了します",
+                ret_header.rcode()
+            );
+            return Some(response);
+        }
+
+        println!("ここに答えはありませんでした。次の問い合わせ先を探します");
+
+        if !response.authorities.iter().any(|r| r.rr_type == 2) {
+            println!("委任先が見つかりませんでした。終了します");
+            return Some(response);
+        }
 
-    if ret_header.ar_count == 0 {
+        // 完全な QNAME はもう明かしたので、以降は最小化しない
+        let nameservers = self.follow_referral(response, path)?;
+        self.iterate(fqdn, qtype, &nameservers, None, path)
+    }
+
+    /** QNAME 最小化 (RFC 9156) で、fqdn の末尾 minimisation.labels 個のラベルだけを問い合わせる */
+    fn iterate_minimised(
+        &self,
+        fqdn: &str,
+        qtype: u16,
+        nameservers: &[SocketAddr],
+        minimisation: Minimisation,
+        path: &mut Vec<ZoneCut>,
+    ) -> Option<Arc<message::Message>> {
+        let qname = message::last_labels(fqdn, minimisation.labels);
+        // 元の QTYPE も隠すため、RFC 9156 の推奨どおり A で問い合わせる
         println!(
-            "付加情報部がないので、まず問い合わせ先 {:?} の IP アドレスを調べます。",
+            "QNAME 最小化: {:?} の代わりに {:?} (type 1) を問い合わせます",
+            fqdn, qname
+        );
+
+        let response = match self.query_fastest(nameservers, &qname, 1) {
+            Some(response) if response.header.rcode() == 0 => response,
+            response => {
+                // 空の非終端で NXDOMAIN などを返す壊れたサーバがあるので、完全な QNAME で問い合わせ直す
+                println!(
+                    "最小化した問い合わせが失敗しました (RCODE: {:?}) 。完全な QNAME で問い合わせ直します",
+                    response.map(|r| r.header.rcode())
+                );
+                return self.iterate(fqdn, qtype, nameservers, None, path);
+            }
+        };
+
+        let cut = response
+            .authorities
+            .iter()
+            .find(|r| r.rr_type == 2)
+            .map(|r| message::label_count(&r.name));
+        let current = path.last().map_or(0, |cut| message::label_count(&cut.zone));
+        match cut {
+            // 今のゾーン自身の NS は委任ではない
+            Some(cut) if response.answers.is_empty() && cut > current => {
+                let nameservers = self.follow_referral(response, path)?;
+                let minimisation = Minimisation {
+                    labels: cut.max(minimisation.labels),
+                    ..minimisation
+                };
+                self.iterate(fqdn, qtype, &nameservers, Some(minimisation), path)
+            }
+            _ => {
+                println!(
+                    "{:?} はゾーンの境界ではありませんでした。同じサーバにラベルを増やして問い合わせます",
+                    qname
+                );
+                self.iterate(fqdn, qtype, nameservers, Some(minimisation), path)
+            }
+        }
+    }
+
+    /** 委任先のネームサーバを調べ、たどったゾーンとして path に記録する */
+    fn follow_referral(
+        &self,
+        response: Arc<message::Message>,
+        path: &mut Vec<ZoneCut>,
+    ) -> Option<Vec<SocketAddr>> {
+        if path.len() > MAX_REFERRALS {
+            println!("委任が多すぎます。終了します");
+            return None;
+        }
+        let zone = response
+            .authorities
+            .iter()
+            .find(|r| r.rr_type == 2)?
+            .name
+            .to_ascii_lowercase();
+        // 委任は今のゾーンより下で、問い合わせた名前を含むゾーンへのものでなければ、たどってもループする
+        let current = &path.last()?.zone;
+        let qname = response.question.qname_dec.to_ascii_lowercase();
+        if zone == *current
+            || !message::is_subdomain(&zone, current)
+            || !message::is_subdomain(&qname, &zone)
+        {
+            println!(
+                "{:?} から {:?} への委任は {:?} に近づきません。終了します",
+                current, zone, qname
+            );
+            return None;
+        }
+
+        let nameservers = self.referral_servers(&response)?;
+        path.push(ZoneCut {
+            zone,
+            nameservers: nameservers.clone(),
+            referral: Some(response),
+        });
+        Some(nameservers)
+    }
+
+    /** path の委任をトラストアンカーから順に検証し、最後のゾーンの鍵で response を検証する */
+    fn validate(&self, path: &[ZoneCut], response: &message::Message) -> Security {
+        let anchor_zone = self.trust_anchor.read().unwrap().zone.clone();
+        let now = validator::now();
+        // トラストアンカーのゾーンを通らなかった応答は判断できない
+        let start = match path.iter().position(|cut| cut.zone == anchor_zone) {
+            Some(start) => start,
+            None => return Security::Indeterminate,
+        };
+
+        let mut zone = &path[start];
+        let mut keys = match self.anchor_keys(zone, now) {
+            Ok(keys) => keys,
+            Err(security) => return security,
+        };
+        for cut in &path[start + 1..] {
+            let referral = match &cut.referral {
+                Some(referral) => referral,
+                None => return Security::Bogus,
+            };
+            let rrsets = validator::rrsets(&referral.authorities);
+            let ds = match rrsets
+                .iter()
+                .find(|rrset| rrset.rr_type == 43 && rrset.name == cut.zone)
+            {
+                Some(ds) => ds,
+                None => {
+                    // 親ゾーンの NSEC / NSEC3 で DS がないことが示されていれば、署名されていない委任
+                    let proof = if verify_denials(&rrsets, &keys, &zone.zone, now) {
+                        Denial::new(&zone.zone, &referral.authorities).nodata(&cut.zone, 43)
+                    } else {
+                        Security::Bogus
+                    };
+                    if proof == Security::Bogus {
+                        println!("{:?} への委任に DS もその不在の証明もありません", cut.zone);
+                        return Security::Bogus;
+                    }
+                    println!("{:?} は署名されていない委任です", cut.zone);
+                    return Security::Insecure;
+                }
+            };
+            if !validator::verify_with_keys(ds, &keys, &zone.zone, now) {
+                println!("{:?} の DS の署名を検証できませんでした", cut.zone);
+                return Security::Bogus;
+            }
+            let ds: Vec<Ds> = ds.records.iter().filter_map(|r| r.ds.clone()).collect();
+            keys = match self.zone_keys(cut, &ds, now) {
+                Ok(keys) => keys,
+                Err(security) => return security,
+            };
+            zone = cut;
+        }
+        // 同じサーバが親と子のゾーンを持つと子への委任の応答は得られないので、署名者までの境界を探す
+        let (zone, keys) = match self.descend(zone, keys, signer_name(response), now) {
+            Ok(found) => found,
+            Err(security) => return security,
+        };
+
+        let authorities = validator::rrsets(&response.authorities);
+        let denial = if verify_denials(&authorities, &keys, &zone, now) {
+            Some(Denial::new(&zone, &response.authorities))
+        } else {
+            None
+        };
+
+        let rrsets = validator::rrsets(&response.answers);
+        if rrsets.is_empty() {
+            let question = &response.question;
+            let security = match (&denial, response.header.rcode()) {
+                (Some(denial), 3) => denial.nxdomain(&question.qname_dec),
+                (Some(denial), 0) => denial.nodata(&question.qname_dec, question.qtype),
+                (None, 0 | 3) => Security::Bogus,
+                _ => Security::Indeterminate,
+            };
+            println!("否定応答の検証結果: {:?}", security);
+            if security == Security::Secure {
+                self.remember_denials(&zone, response, now);
+            }
+            return security;
+        }
+
+        if !rrsets
+            .iter()
+            .all(|rrset| validator::verify_with_keys(rrset, &keys, &zone, now))
+        {
+            println!("応答の署名を検証できませんでした");
+            return Security::Bogus;
+        }
+        // ワイルドカードから合成された応答では、問い合わせた名前そのものがないことも確かめる
+        for rrset in &rrsets {
+            let labels = match rrset
+                .rrsigs
+                .iter()
+                .map(|rrsig| rrsig.labels)
+                .find(|labels| usize::from(*labels) < message::label_count(&rrset.name))
+            {
+                Some(labels) => labels,
+                None => continue,
+            };
+            let proof = match &denial {
+                Some(denial) => denial.wildcard_answer(&rrset.name, labels),
+                None => Security::Bogus,
+            };
+            if proof != Security::Secure {
+                println!(
+                    "{:?} はワイルドカードから合成されましたが、元の名前がないことを確かめられませんでした",
+                    rrset.name
+                );
+                return proof;
+            }
+        }
+        if denial.is_some() {
+            self.remember_denials(&zone, response, now);
+        }
+        println!("応答の署名を検証できました");
+        Security::Secure
+    }
+
+    /**
+     * 応答の署名者 signer が cut のゾーンより下にあれば、その間の名前の DS を cut のサーバに問い合わせて
+     * ゾーンの境界を探し、signer のゾーンの鍵まで認証をつなぐ (RFC 4035 Section 5)。そのゾーンと鍵を返す
+     */
+    fn descend(
+        &self,
+        cut: &ZoneCut,
+        mut keys: Vec<Dnskey>,
+        signer: Option<String>,
+        now: u32,
+    ) -> Result<(String, Vec<Dnskey>), Security> {
+        let mut zone = cut.zone.clone();
+        let signer = match signer {
+            Some(signer) if signer != zone && message::is_subdomain(&signer, &zone) => signer,
+            _ => return Ok((zone, keys)),
+        };
+
+        for labels in message::label_count(&zone) + 1..=message::label_count(&signer) {
+            let name = message::last_labels(&signer, labels);
+            println!("{:?} がゾーンの境界か、DS を問い合わせて確かめます", name);
+            let response = self
+                .query_fastest(&cut.nameservers, &name, 43)
+                .ok_or(Security::Bogus)?;
+            let answers = validator::rrsets(&response.answers);
+            if let Some(ds) = answers
+                .iter()
+                .find(|rrset| rrset.rr_type == 43 && rrset.name == name)
+            {
+                if !validator::verify_with_keys(ds, &keys, &zone, now) {
+                    println!("{:?} の DS の署名を検証できませんでした", name);
+                    return Err(Security::Bogus);
+                }
+                let ds: Vec<Ds> = ds.records.iter().filter_map(|r| r.ds.clone()).collect();
+                let child = ZoneCut {
+                    zone: name.clone(),
+                    nameservers: cut.nameservers.clone(),
+                    referral: None,
+                };
+                keys = self.zone_keys(&child, &ds, now)?;
+                zone = name;
+                continue;
+            }
+
+            // DS がないなら、ゾーンの境界ではないか、署名されていない委任
+            let authorities = validator::rrsets(&response.authorities);
+            let proof = if response.header.rcode() == 0
+                && verify_denials(&authorities, &keys, &zone, now)
+            {
+                Denial::new(&zone, &response.authorities).nodata(&name, 43)
+            } else {
+                Security::Bogus
+            };
+            match proof {
+                Security::Secure if name != signer => continue,
+                Security::Secure | Security::Insecure => {
+                    println!("{:?} は署名されていない委任です", name);
+                    return Err(Security::Insecure);
+                }
+                _ => {
+                    println!("{:?} の DS もその不在の証明も得られませんでした", name);
+                    return Err(Security::Bogus);
+                }
+            }
+        }
+        Ok((zone, keys))
+    }
+
+    /** 検証できた権威部の NSEC / NSEC3 を、以降の否定応答の合成のために保持する */
+    fn remember_denials(&self, zone: &str, response: &message::Message, now: u32) {
+        if self.config.aggressive_nsec {
+            self.nsec_cache.insert(zone, &response.authorities, now);
+        }
+    }
+
+    /** トラストアンカーのゾーンの鍵を得る。取得した DNSKEY で、RFC 5011 に従ってトラストアンカーを更新する */
+    fn anchor_keys(&self, cut: &ZoneCut, now: u32) -> Result<Vec<Dnskey>, Security> {
+        let ds = self.trust_anchor.read().unwrap().trusted_ds();
+        let (keys, response) = self.dnskeys(cut, &ds, now)?;
+
+        let mut anchor = self.trust_anchor.write().unwrap();
+        if anchor.update(&response.answers, now) {
+            if let Some(path) = &self.config.trust_anchor_file {
+                match anchor.save(path) {
+                    Ok(()) => println!("トラストアンカーを {:?} に保存しました", path),
+                    Err(e) => println!("トラストアンカーを保存できませんでした: {:?}", e),
+                }
+            }
+        }
+        Ok(keys)
+    }
+
+    /** cut のゾーンの DNSKEY を問い合わせ、ds で認証できればその鍵を返す */
+    fn zone_keys(&self, cut: &ZoneCut, ds: &[Ds], now: u32) -> Result<Vec<Dnskey>, Security> {
+        self.dnskeys(cut, ds, now).map(|(keys, _)| keys)
+    }
+
+    /** zone_keys と同じく鍵を得て、DNSKEY の応答もあわせて返す */
+    fn dnskeys(
+        &self,
+        cut: &ZoneCut,
+        ds: &[Ds],
+        now: u32,
+    ) -> Result<(Vec<Dnskey>, Arc<message::Message>), Security> {
+        // 検証できるアルゴリズムの DS が 1 つもなければ、署名されていないものとして扱う (RFC 4035 Section 5.2)
+        if !ds.iter().any(validator::is_supported_ds) {
+            println!("{:?} の DS のアルゴリズムには対応していません", cut.zone);
+            return Err(Security::Insecure);
+        }
+        let response = self
+            .query_fastest(&cut.nameservers, &cut.zone, 48)
+            .ok_or(Security::Bogus)?;
+        match validator::validate_dnskeys(&cut.zone, &response.answers, ds, now) {
+            Some(keys) => Ok((keys, response)),
+            None => {
+                println!("{:?} の DNSKEY を DS で認証できませんでした", cut.zone);
+                Err(Security::Bogus)
+            }
+        }
+    }
+
+    /** 委任の応答から、次に問い合わせるネームサーバのアドレスを得る */
+    fn referral_servers(&self, response: &message::Message) -> Option<Vec<SocketAddr>> {
+        // 次の問い合わせ先を探す
+        let ns_records: Vec<&message::Resource> = response
+            .authorities
+            .iter()
+            .filter(|r| r.rr_type == 2)
+            .collect();
+
+        println!(
+            "{:?} について、 {:?} などが知っているようです。問い合わせてみましょう",
+            ns_records.first()?.name,
             ns_records[0].nsdname
         );
-        let address = resolve(ns_records[0].nsdname.as_str(), 1);
-        println!("問い合わせ先の IP アドレスは {:?} です。", address);
-        match address {
-            Some(addr) => return resolve_iterative(fqdn, qtype, addr.as_str()),
-            None => todo!(),
+
+        // 付加情報部から、委任先のネームサーバのアドレス (グルー) を集める
+        let glue: Vec<SocketAddr> = response
+            .additionals
+            .iter()
+            .filter(|r| {
+                (r.rr_type == 1 || r.rr_type == 28)
+                    && ns_records
+                        .iter()
+                        .any(|ns| ns.nsdname.eq_ignore_ascii_case(&r.name))
+            })
+            .filter_map(|r| r.address)
+            .map(|address| SocketAddr::new(address, 53))
+            .collect();
+        if !glue.is_empty() {
+            return Some(glue);
+        }
+
+        // ネームサーバの名前の解決がまたグルーのない委任をたどり、循環することがある
+        let depth = GLUELESS_DEPTH.with(Cell::get);
+        if depth >= MAX_GLUELESS_DEPTH {
+            println!("グルーのない委任の入れ子が深すぎます。終了します");
+            return None;
+        }
+        GLUELESS_DEPTH.with(|glueless| glueless.set(depth + 1));
+        // 解決できない名前があっても、ほかのネームサーバを試す
+        let addresses = ns_records.iter().find_map(|ns| {
+            println!(
+                "付加情報部にアドレスがないので、まず問い合わせ先 {:?} の IP アドレスを調べます。",
+                ns.nsdname
+            );
+            let addresses: Vec<IpAddr> = [1, 28]
+                .into_iter()
+                .filter_map(|qtype| self.lookup_addresses(&ns.nsdname, qtype))
+                .flatten()
+                .collect();
+            println!("問い合わせ先の IP アドレスは {:?} です。", addresses);
+            (!addresses.is_empty()).then_some(addresses)
+        });
+        GLUELESS_DEPTH.with(|glueless| glueless.set(depth));
+
+        Some(
+            addresses?
+                .into_iter()
+                .map(|address| SocketAddr::new(address, 53))
+                .collect(),
+        )
+    }
+
+    /** 平滑化 RTT が小さいサーバから順に、応答が得られるまで問い合わせる */
+    fn query_fastest(
+        &self,
+        nameservers: &[SocketAddr],
+        fqdn: &str,
+        qtype: u16,
+    ) -> Option<Arc<message::Message>> {
+        let mut remaining = nameservers.to_vec();
+        for _ in 0..MAX_SERVER_ATTEMPTS {
+            let nameserver = self.rtt.select(&remaining)?;
+            match self.query(nameserver, fqdn, qtype, false) {
+                Some(response) if !is_server_failure(&response) => return Some(response),
+                Some(response) => println!(
+                    "{:?} がエラーを返しました (RCODE: {:?}) 。別のネームサーバを試します",
+                    nameserver,
+                    response.header.rcode()
+                ),
+                None => println!(
+                    "{:?} から応答がないので、別のネームサーバを試します",
+                    nameserver
+                ),
+            }
+            remaining.retain(|server| *server != nameserver);
+        }
+
+        None
+    }
+
+    /** 上位リゾルバに先頭から順に問い合わせ、再帰問い合わせに応じたものの応答を返す */
+    pub fn resolve_forward(
+        &self,
+        fqdn: &str,
+        qtype: u16,
+        upstreams: &[SocketAddr],
+    ) -> Option<Arc<message::Message>> {
+        if upstreams.is_empty() || !is_valid_name(fqdn) {
+            return None;
+        }
+        let start = if self.config.rotate {
+            self.rotation.fetch_add(1, Ordering::Relaxed) % upstreams.len()
+        } else {
+            0
+        };
+        let order = upstreams
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(upstreams.len())
+            .cycle()
+            .take(upstreams.len() * self.config.attempts.max(1));
+
+        for upstream in order {
+            let response = match self.query(*upstream, fqdn, qtype, true) {
+                Some(response) => response,
+                None => {
+                    println!(
+                        "{:?} から応答がありません。次の上位リゾルバを試します",
+                        upstream
+                    );
+                    continue;
+                }
+            };
+            if response.header.ra() == 0 {
+                println!(
+                    "{:?} は再帰問い合わせに対応していません (RA=0) 。次の上位リゾルバを試します",
+                    upstream
+                );
+                continue;
+            }
+            // NOERROR と NXDOMAIN 以外は上位リゾルバ側の失敗とみなす
+            match response.header.rcode() {
+                0 | 3 => return Some(response),
+                rcode => println!(
+                    "{:?} がエラーを返しました (RCODE: {:?}) 。次の上位リゾルバを試します",
+                    upstream, rcode
+                ),
+            }
+        }
+
+        println!("すべての上位リゾルバで失敗しました");
+        None
+    }
+
+    /** 設定された mode で問い合わせ、最終的な応答を返す。検証に失敗 (Bogus) した応答は返さない */
+    pub fn lookup(&self, fqdn: &str, qtype: u16) -> Option<Arc<message::Message>> {
+        match self.lookup_validated(fqdn, qtype)? {
+            (_, Security::Bogus) => {
+                println!("{:?} の応答は DNSSEC の検証に失敗しました", fqdn);
+                None
+            }
+            (response, _) => Some(response),
+        }
+    }
+
+    /**
+     * lookup と同じく問い合わせ、DNSSEC の検証結果とともに返す (hosts と Forward の応答は検証しない)。
+     * SVCB と HTTPS の AliasMode はたどり、その先の応答を返す
+     */
+    pub fn lookup_validated(
+        &self,
+        fqdn: &str,
+        qtype: u16,
+    ) -> Option<(Arc<message::Message>, Security)> {
+        let (response, security) = self.lookup_once(fqdn, qtype)?;
+        if qtype == 64 || qtype == 65 {
+            return self.follow_aliases(fqdn, qtype, response, security);
         }
+        Some((response, security))
     }
 
-    let begin = (ret_header.an_count + ret_header.ns_count) as usize;
-    let end = begin + ret_header.ar_count as usize;
-    let ar_records = &resources[begin..end];
+    fn lookup_once(&self, fqdn: &str, qtype: u16) -> Option<(Arc<message::Message>, Security)> {
+        let addresses = self.config.hosts.lookup(fqdn, qtype);
+        if !addresses.is_empty() {
+            println!("{:?} は hosts に書かれていました: {:?}", fqdn, addresses);
+            let response = Arc::new(hosts_response(fqdn, qtype, &addresses)?);
+            return Some((response, Security::Indeterminate));
+        }
+
+        match self.config.mode_for(fqdn) {
+            Mode::Iterative => self.resolve_iterative_validated(fqdn, qtype, &self.root_servers()),
+            Mode::Forward(upstreams) => self
+                .resolve_forward(fqdn, qtype, upstreams)
+                .map(|response| (response, Security::Indeterminate)),
+        }
+    }
 
-    for ar_record in ar_records {
-        if ar_record.rr_type == 1 {
-            match &ar_record.address {
-                message::IpAddr::V4(ipv4) => {
-                    return resolve_iterative(fqdn, qtype, ipv4);
+    /** AliasMode (RFC 9460 Section 2.4.2) の別名を ServiceMode に行き着くまでたどる。たどったレコードは Answer の先頭に残す */
+    fn follow_aliases(
+        &self,
+        fqdn: &str,
+        qtype: u16,
+        mut response: Arc<message::Message>,
+        mut security: Security,
+    ) -> Option<(Arc<message::Message>, Security)> {
+        let mut chain: Vec<message::Resource> = Vec::new();
+        let mut names = vec![fqdn.trim_end_matches('.').to_ascii_lowercase()];
+        while let Some(target) = alias_target(&response, names.last().unwrap(), qtype) {
+            if names.len() > MAX_ALIAS_CHAIN || names.contains(&target) {
+                println!(
+                    "AliasMode の連鎖が長すぎるか、ループしています: {:?}",
+                    names
+                );
+                return None;
+            }
+            println!(
+                "{:?} は AliasMode で {:?} を指しています",
+                names.last().unwrap(),
+                target
+            );
+            chain.extend(response.answers.iter().cloned());
+            let (next, next_security) = self.lookup_once(&target, qtype)?;
+            security = weakest(security, next_security);
+            response = next;
+            names.push(target);
+        }
+        if chain.is_empty() {
+            return Some((response, security));
+        }
+
+        chain.extend(response.answers.iter().cloned());
+        let mut header = response.header;
+        header.an_count = chain.len() as u16;
+        let merged = message::Message {
+            header,
+            question: message::Question::new(fqdn, qtype, 0x0001)?,
+            answers: chain,
+            authorities: response.authorities.clone(),
+            additionals: response.additionals.clone(),
+            edns: response.edns,
+        };
+        Some((Arc::new(merged), security))
+    }
+
+    /** 相対名を検索リストで展開しながら問い合わせ、最初に Answer が得られた名前と応答を返す */
+    pub fn lookup_search(&self, name: &str, qtype: u16) -> Option<(String, Arc<message::Message>)> {
+        for candidate in self.config.search_candidates(name) {
+            println!("{:?} を {:?} として問い合わせます", name, candidate);
+            if let Some(response) = self.lookup(&candidate, qtype) {
+                if response.header.rcode() == 0 && !response.answers.is_empty() {
+                    println!("{:?} で見つかりました", candidate);
+                    return Some((candidate, response));
                 }
-                message::IpAddr::V6(_) => todo!(),
             }
         }
+
+        println!("{:?} はどの展開でも見つかりませんでした", name);
+        None
+    }
+
+    /** fqdn の A / AAAA を問い合わせ、Answer の最初のアドレスを返す */
+    pub fn resolve(&self, fqdn: &str, qtype: u16) -> Option<IpAddr> {
+        println!("{:?} の type {:?} を解決していくよ！", fqdn, qtype);
+
+        let response = self.lookup(fqdn, qtype)?;
+        response.answers.iter().find_map(|r| r.address)
     }
 
-    return None;
+    /**
+     * getaddrinfo のように、name の A と AAAA を並行して問い合わせ、得られたアドレスをすべて返す。
+     * CNAME はたどり、RFC 6724 の規則で並べたうえで Happy Eyeballs のために IPv6 と IPv4 を交互にする
+     */
+    pub fn lookup_ip(&self, name: &str) -> Option<Vec<IpAddr>> {
+        println!("{:?} の A と AAAA を並行して問い合わせます", name);
+        let (ipv4, ipv6) = thread::scope(|scope| {
+            let ipv6 = scope.spawn(|| self.lookup_addresses(name, 28));
+            let ipv4 = self.lookup_addresses(name, 1);
+            (ipv4, ipv6.join().unwrap())
+        });
+
+        let addresses: Vec<IpAddr> = ipv4.into_iter().chain(ipv6).flatten().collect();
+        if addresses.is_empty() {
+            println!("{:?} のアドレスは見つかりませんでした", name);
+            return None;
+        }
+        let sorted = address_selection::sort(&addresses);
+        println!("{:?} のアドレス: {:?}", name, sorted);
+        Some(sorted)
+    }
+
+    /** fqdn の qtype (A / AAAA) のアドレスを、CNAME をたどって集める。NODATA なら空を返す */
+    fn lookup_addresses(&self, fqdn: &str, qtype: u16) -> Option<Vec<IpAddr>> {
+        let mut qname = fqdn.trim_end_matches('.').to_ascii_lowercase();
+        let mut response = self.lookup(&qname, qtype)?;
+        for _ in 0..=MAX_CNAME_CHAIN {
+            if response.header.rcode() != 0 {
+                return None;
+            }
+            let addresses: Vec<IpAddr> = response
+                .answers
+                .iter()
+                .filter(|r| r.rr_type == qtype && r.name.eq_ignore_ascii_case(&qname))
+                .filter_map(|r| r.address)
+                .collect();
+            if !addresses.is_empty() {
+                return Some(addresses);
+            }
+            let target = match response
+                .answers
+                .iter()
+                .find(|r| r.rr_type == 5 && r.name.eq_ignore_ascii_case(&qname))
+            {
+                Some(cname) => cname.cname.to_ascii_lowercase(),
+                None => return Some(Vec::new()),
+            };
+            println!("{:?} は {:?} の別名です", qname, target);
+            qname = target;
+            // 応答に別名の先のレコードがなければ、改めて問い合わせる
+            if !response
+                .answers
+                .iter()
+                .any(|r| r.name.eq_ignore_ascii_case(&qname))
+            {
+                response = self.lookup(&qname, qtype)?;
+            }
+        }
+
+        println!("CNAME の連鎖が長すぎます");
+        None
+    }
+
+    /** address の逆引き (PTR) の名前を返す。RFC 2317 の CNAME による委任もたどる */
+    pub fn reverse_lookup(&self, address: IpAddr) -> Option<Vec<String>> {
+        let mut qname = reverse_name(address);
+        println!("{} を {:?} の PTR として逆引きします", address, qname);
+        for _ in 0..MAX_CNAME_CHAIN {
+            let response = self.lookup(&qname, 12)?;
+            if response.header.rcode() != 0 {
+                return None;
+            }
+            let names: Vec<String> = response
+                .answers
+                .iter()
+                .filter(|r| r.rr_type == 12)
+                .map(|r| r.ptrdname.clone())
+                .collect();
+            if !names.is_empty() {
+                return Some(names);
+            }
+            qname = response
+                .answers
+                .iter()
+                .find(|r| r.rr_type == 5 && r.name.eq_ignore_ascii_case(&qname))?
+                .cname
+                .clone();
+        }
+        println!("CNAME の連鎖が長すぎます");
+        None
+    }
+
+    /**
+     * FCrDNS: 逆引きした名前のうち、正引き (A / AAAA) で address に戻るものだけを返す。
+     * 逆引きの名前は address の管理者が自由に設定できるので、確認したものだけを信頼する
+     */
+    pub fn reverse_lookup_confirmed(&self, address: IpAddr) -> Option<Vec<String>> {
+        let qtype = match address {
+            IpAddr::V4(_) => 1,
+            IpAddr::V6(_) => 28,
+        };
+        let confirmed: Vec<String> = self
+            .reverse_lookup(address)?
+            .into_iter()
+            .filter(|name| {
+                // 逆引きの名前が別名でもよいので、CNAME をたどって正引きする
+                let confirmed = self
+                    .lookup_addresses(name, qtype)
+                    .is_some_and(|addresses| addresses.contains(&address));
+                if !confirmed {
+                    println!("{:?} の正引きは {} に戻りませんでした", name, address);
+                }
+                confirmed
+            })
+            .collect();
+        Some(confirmed)
+    }
+
+    /** 同じ問い合わせ先への同一の問い合わせが処理中なら、その応答を待って共有する */
+    fn query(
+        &self,
+        nameserver: SocketAddr,
+        fqdn: &str,
+        qtype: u16,
+        rd: bool,
+    ) -> Option<Arc<message::Message>> {
+        let key = (nameserver, fqdn.to_ascii_lowercase(), qtype, rd);
+        self.inflight.run(key, || {
+            let start = Instant::now();
+            let response = self.send_query_0x20(nameserver, fqdn, qtype, rd);
+            match &response {
+                Some(response) if !is_server_failure(response) => {
+                    self.rtt.record(nameserver, start.elapsed())
+                }
+                _ => self.rtt.penalize(nameserver),
+            }
+            response.map(Arc::new)
+        })
+    }
+
+    /** DNS 0x20: QNAME の大文字・小文字をランダムに変えて送り、応答の質問部が完全に一致するか確かめる */
+    fn send_query_0x20(
+        &self,
+        nameserver: SocketAddr,
+        fqdn: &str,
+        qtype: u16,
+        rd: bool,
+    ) -> Option<message::Message> {
+        let use_0x20 = self.config.case_randomisation
+            && !self
+                .case_insensitive_servers
+                .lock()
+                .unwrap()
+                .contains(&nameserver);
+        if !use_0x20 {
+            return send_query(&self.config, nameserver, fqdn, qtype, rd);
+        }
+
+        let qname = randomise_case(fqdn.trim_end_matches('.'));
+        let response = send_query(&self.config, nameserver, &qname, qtype, rd)?;
+        let echoed = &response.question.qname_dec;
+        if *echoed == qname {
+            return Some(response);
+        }
+        if !echoed.eq_ignore_ascii_case(&qname) {
+            println!(
+                "応答の質問部 {:?} が問い合わせ {:?} と一致しません。破棄します",
+                echoed, qname
+            );
+            return None;
+        }
+
+        // 大文字・小文字を保存しないサーバなので、以降は 0x20 を使わずに問い合わせる
+        println!(
+            "{:?} は大文字・小文字を保存しませんでした ({:?} -> {:?}) 。0x20 なしで問い合わせ直します",
+            nameserver, qname, echoed
+        );
+        self.case_insensitive_servers
+            .lock()
+            .unwrap()
+            .insert(nameserver);
+        send_query(&self.config, nameserver, fqdn, qtype, rd)
+    }
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/** QNAME 最小化の途中経過 */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Minimisation {
+    /** これまでに明かしたラベル数 */
+    labels: usize,
+    /** 最小化した問い合わせを送った回数 */
+    count: usize,
+}
+
+impl Minimisation {
+    /** 次に明かすラベル数を決める。はじめは 1 つずつ、その後は MAX_MINIMISE_COUNT 回に収まるようにまとめて増やす */
+    fn next(self, total: usize) -> Self {
+        let labels = if self.count < MINIMISE_ONE_LAB {
+            self.labels + 1
+        } else {
+            match MAX_MINIMISE_COUNT.saturating_sub(self.count) {
+                0 | 1 => total,
+                remaining => self.labels + total.saturating_sub(self.labels).div_ceil(remaining),
+            }
+        };
+        Self {
+            labels: labels.min(total),
+            count: self.count + 1,
+        }
+    }
+}
+
+/** 逆引きの名前 (RFC 1035 Section 3.5 の in-addr.arpa と RFC 3596 Section 2.5 の ip6.arpa) */
+pub fn reverse_name(address: IpAddr) -> String {
+    match address {
+        IpAddr::V4(ipv4) => {
+            let octets: Vec<String> = ipv4.octets().iter().rev().map(|o| o.to_string()).collect();
+            format!("{}.in-addr.arpa", octets.join("."))
+        }
+        IpAddr::V6(ipv6) => {
+            // 下位のニブルから 1 桁ずつ並べる
+            let nibbles: Vec<String> = ipv6
+                .octets()
+                .iter()
+                .rev()
+                .flat_map(|octet| [octet & 0x0F, octet >> 4])
+                .map(|nibble| format!("{:x}", nibble))
+                .collect();
+            format!("{}.ip6.arpa", nibbles.join("."))
+        }
+    }
+}
+
+/** qname の AliasMode のレコードが指す別名。指す先がルート (サービスなし) なら None */
+/** 63 バイトを超えるラベルや 255 バイトを超える名前は、問い合わせを送らずに失敗させる */
+fn is_valid_name(fqdn: &str) -> bool {
+    if message::encode_name(fqdn).is_some() {
+        return true;
+    }
+    println!("{:?} は名前として長すぎるので問い合わせません", fqdn);
+    false
+}
+
+/** FORMERR, SERVFAIL, NOTIMP, REFUSED は、問い合わせ先のサーバが答えられなかったものとみなす */
+fn is_server_failure(response: &message::Message) -> bool {
+    matches!(response.header.rcode(), 1 | 2 | 4 | 5)
+}
+
+fn alias_target(response: &message::Message, qname: &str, qtype: u16) -> Option<String> {
+    response
+        .answers
+        .iter()
+        .filter(|r| r.rr_type == qtype && r.name.eq_ignore_ascii_case(qname))
+        .filter_map(|r| r.svcb.as_ref())
+        .find(|svcb| svcb.is_alias())
+        .map(|svcb| svcb.target.to_ascii_lowercase())
+        .filter(|target| !target.is_empty())
+}
+
+/** 連鎖した応答の検証結果のうち、最も弱いもの */
+pub(crate) fn weakest(a: Security, b: Security) -> Security {
+    let rank = |security| match security {
+        Security::Secure => 0,
+        Security::Insecure => 1,
+        Security::Indeterminate => 2,
+        Security::Bogus => 3,
+    };
+    if rank(b) > rank(a) {
+        b
+    } else {
+        a
+    }
+}
+
+/** 応答の署名者の名前 (小文字)。Answer に RRSIG がなければ権威部のものを使う */
+fn signer_name(response: &message::Message) -> Option<String> {
+    response
+        .answers
+        .iter()
+        .chain(&response.authorities)
+        .filter_map(|r| r.rrsig.as_ref())
+        .map(|rrsig| rrsig.signer_name.to_ascii_lowercase())
+        .next()
 }
 
-pub fn resolve(fqdn: &str, qtype: u16) -> Option<String> {
-    println!("{:?} の type {:?} を解決していくよ！", fqdn, qtype);
+/** 権威部の SOA, NSEC, NSEC3 がすべてゾーン zone の keys で署名されているか */
+fn verify_denials(rrsets: &[validator::RrSet], keys: &[Dnskey], zone: &str, now: u32) -> bool {
+    rrsets
+        .iter()
+        .filter(|rrset| matches!(rrset.rr_type, 6 | 47 | 50))
+        .all(|rrset| validator::verify_with_keys(rrset, keys, zone, now))
+}
+
+/** 英字の大文字・小文字をランダムに入れ替える */
+fn randomise_case(name: &str) -> String {
+    let mut rng = rand::thread_rng();
+    name.chars()
+        .map(|c| {
+            if rng.gen() {
+                c.to_ascii_uppercase()
+            } else {
+                c.to_ascii_lowercase()
+            }
+        })
+        .collect()
+}
+
+/** hosts のアドレスから、上位リゾルバの応答と同じ形のメッセージを作る */
+fn hosts_response(fqdn: &str, qtype: u16, addresses: &[IpAddr]) -> Option<message::Message> {
+    let header = message::Header::create(
+        0,
+        0b1,
+        0b0000,
+        0b1,
+        0b0,
+        0b1,
+        0b1,
+        0b000,
+        0b0000,
+        0x0001,
+        addresses.len() as u16,
+        0x0000,
+        0x0000,
+    );
+    let mut response = message::Message::new(header, message::Question::new(fqdn, qtype, 0x0001)?);
+    response.answers = addresses
+        .iter()
+        .map(|address| {
+            let rdata = match address {
+                IpAddr::V4(ipv4) => ipv4.octets().to_vec(),
+                IpAddr::V6(ipv6) => ipv6.octets().to_vec(),
+            };
+            message::Resource {
+                name: fqdn.trim_end_matches('.').to_string(),
+                rr_type: qtype,
+                data_class: 0x0001,
+                ttl: 0,
+                rdlength: rdata.len() as u16,
+                rdata,
+                address: Some(*address),
+                ..Default::default()
+            }
+        })
+        .collect();
+    Some(response)
+}
 
+fn send_query(
+    config: &ResolverConfig,
+    nameserver: SocketAddr,
+    fqdn: &str,
+    qtype: u16,
+    rd: bool,
+) -> Option<message::Message> {
     let mut rng = rand::thread_rng();
-    let nameserver = ROOT_NAME_SERVERS[rng.gen_range(0..ROOT_NAME_SERVERS.len())];
+    let id: u16 = rng.gen();
+
+    // DNSSEC のレコードは大きいので、検証するときは EDNS0 を必ず使う
+    let edns0 = config.edns0 || config.dnssec;
+    let mut message = message::Message::new(
+        message::Header::create(
+            id,
+            0b0,
+            0b0000,
+            0b0,
+            0b0,
+            rd.into(),
+            0b0,
+            0b000,
+            0b0000,
+            0x0001,
+            0x0000,
+            0x0000,
+            edns0.into(),
+        ),
+        message::Question::new(fqdn, qtype, 0x0001)?,
+    );
+    if edns0 {
+        message.edns = Some(message::Edns {
+            dnssec_ok: config.dnssec,
+            ..message::Edns::new(EDNS_UDP_PAYLOAD_SIZE)
+        });
+    }
+
+    println!("{:?} に問い合わせます...", nameserver);
+
+    let local = if nameserver.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    // ファイル記述子が足りないときなどは、応答がなかったものとして扱う
+    let socket = match UdpSocket::bind(local).and_then(|socket| {
+        socket
+            .set_read_timeout(Some(config.timeout))
+            .map(|()| socket)
+    }) {
+        Ok(socket) => socket,
+        Err(e) => {
+            println!("ソケットを用意できませんでした: {:?}", e);
+            return None;
+        }
+    };
+    let buffer = message.to_bytes();
+    if let Err(e) = socket.send_to(buffer.as_slice(), nameserver) {
+        println!("送信に失敗しました: {:?}", e);
+        return None;
+    }
+
+    // Receive
+    // 問い合わせ先と ID が一致しない応答は読み捨てる
+    let mut buf = vec![
+        0;
+        if edns0 {
+            EDNS_UDP_PAYLOAD_SIZE.into()
+        } else {
+            512
+        }
+    ];
+    loop {
+        let (number_of_bytes, src_addr) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) => {
+                println!("応答を受信できませんでした: {:?}", e);
+                return None;
+            }
+        };
+        println!(
+            "number_of_bytes: {}, src_addr: {:?}",
+            number_of_bytes, src_addr
+        );
+        if number_of_bytes < 12
+            || src_addr != nameserver
+            || u16::from(buf[0]) * 256 + u16::from(buf[1]) != id
+        {
+            continue;
+        }
+
+        // Response
+        // 壊れた応答は、応答がなかったものとして扱う
+        let Some(mut response) = message::Message::parse(&buf[..number_of_bytes]) else {
+            println!("{:?} からの応答を解析できませんでした", nameserver);
+            return None;
+        };
+        // 切り詰められた応答 (TC=1) の各セクションは信用できないので、同じサーバに TCP で問い合わせ直す
+        if response.header.tc() == 1 {
+            println!(
+                "{:?} からの応答は切り詰められていました。TCP で問い合わせ直します",
+                nameserver
+            );
+            response = send_query_tcp(config, nameserver, &buffer, id)?;
+        }
+        println!("{:?}", response.question);
+        println!("{:?}", response.answers);
+        println!("{:?}", response.authorities);
+        println!("{:?}", response.additionals);
+        return Some(response);
+    }
+}
+
+/** 2 バイトの長さを前に付けて (RFC 1035 Section 4.2.2) TCP で query を送り、ID が id の応答を返す */
+fn send_query_tcp(
+    config: &ResolverConfig,
+    nameserver: SocketAddr,
+    query: &[u8],
+    id: u16,
+) -> Option<message::Message> {
+    let exchange = || -> io::Result<Vec<u8>> {
+        let mut stream = TcpStream::connect_timeout(&nameserver, config.timeout)?;
+        stream.set_read_timeout(Some(config.timeout))?;
+        stream.set_write_timeout(Some(config.timeout))?;
+        let mut framed = (query.len() as u16).to_be_bytes().to_vec();
+        framed.extend(query);
+        stream.write_all(&framed)?;
+        let mut length = [0; 2];
+        stream.read_exact(&mut length)?;
+        let mut buf = vec![0; usize::from(u16::from_be_bytes(length))];
+        stream.read_exact(&mut buf)?;
+        Ok(buf)
+    };
+    let buf = match exchange() {
+        Ok(buf) => buf,
+        Err(e) => {
+            println!(
+                "{:?} から TCP で応答を受信できませんでした: {:?}",
+                nameserver, e
+            );
+            return None;
+        }
+    };
+    match message::Message::parse(&buf) {
+        Some(response) if response.header.id == id => Some(response),
+        _ => {
+            println!("{:?} からの TCP の応答を解析できませんでした", nameserver);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::{Resolver, Roots};
+    use crate::config::ResolverConfig;
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+
+    /** プライミングをせず、roots をルートサーバとして反復問い合わせを行うリゾルバ */
+    pub fn with_roots(config: ResolverConfig, roots: &[SocketAddr]) -> Resolver {
+        let resolver = Resolver::with_config(config);
+        *resolver.roots.write().unwrap() = Roots {
+            addresses: roots.to_vec(),
+            expires: Some(Instant::now() + Duration::from_secs(3600)),
+        };
+        resolver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reverse_name, send_query, Minimisation, Resolver, ZoneCut};
+    use crate::config::ResolverConfig;
+    use crate::dnssec::Nsec;
+    use crate::hosts::Hosts;
+    use crate::message::{Header, Message, Question, Resource};
+    use crate::rdata::{SvcParam, Svcb};
+    use crate::rtt::RttTable;
+    use crate::trust_anchor::TrustAnchor;
+    use crate::validator::testing::{resource, SigningKey};
+    use crate::validator::Security;
+    use std::io::{Read, Write};
+    use std::net::{IpAddr, SocketAddr, TcpListener, UdpSocket};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    /** 受け取った問い合わせを handler に渡し、返されたバイト列を応答として送るサーバ */
+    fn serve(handler: impl Fn(&Message) -> Vec<u8> + Send + 'static) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            while let Ok((number_of_bytes, src_addr)) = socket.recv_from(&mut buf) {
+                let query = Message::parse(&buf[..number_of_bytes]).unwrap();
+                let response = handler(&query);
+                if !response.is_empty() {
+                    socket.send_to(&response, src_addr).unwrap();
+                }
+            }
+        });
+        addr
+    }
+
+    /** query に対する応答のヘッダと質問部 (an, ns, ar は各セクションの件数) */
+    fn reply(query: &Message, aa: u8, ra: u8, rcode: u8, an: u16, ns: u16, ar: u16) -> Vec<u8> {
+        let header = Header::create(
+            query.header.id,
+            1,
+            0,
+            aa,
+            0,
+            query.header.rd(),
+            ra,
+            0,
+            rcode,
+            1,
+            an,
+            ns,
+            ar,
+        );
+        let question = Question::new(&query.question.qname_dec, query.question.qtype, 1).unwrap();
+        Message::new(header, question).to_bytes()
+    }
+
+    /** NAME, TYPE, CLASS (IN), TTL, RDLENGTH, RDATA を並べたリソースレコード */
+    fn record(name: &str, rr_type: u16, ttl: u32, rdata: &[u8]) -> Vec<u8> {
+        let mut bytes = Question::new(name, rr_type, 1).unwrap().to_byte();
+        bytes.extend(ttl.to_be_bytes());
+        bytes.extend((rdata.len() as u16).to_be_bytes());
+        bytes.extend(rdata);
+        bytes
+    }
+
+    /** 問い合わせに対して決まった RA, RCODE で応答する上位リゾルバ (RCODE 0 なら A 192.0.2.1 を返す) */
+    fn fake_upstream(ra: u8, rcode: u8) -> SocketAddr {
+        fake_upstream_with(ra, move |_| rcode)
+    }
+
+    /** QNAME ごとに RCODE を決める上位リゾルバ */
+    fn fake_upstream_with(ra: u8, rcode_for: impl Fn(&str) -> u8 + Send + 'static) -> SocketAddr {
+        fake_server(1, ra, rcode_for)
+    }
+
+    /** RD=rd の問い合わせだけを受け付けるサーバ */
+    fn fake_server(rd: u8, ra: u8, rcode_for: impl Fn(&str) -> u8 + Send + 'static) -> SocketAddr {
+        serve(move |query| {
+            assert_eq!(query.header.rd(), rd);
+            let qname = &query.question.qname_dec;
+            let rcode = rcode_for(qname);
+            if rcode > 0 {
+                return reply(query, 0, ra, rcode, 0, 0, 0);
+            }
+            let mut response = reply(query, 0, ra, rcode, 1, 0, 0);
+            response.extend(record(qname, 1, 3600, &[192, 0, 2, 1]));
+            response
+        })
+    }
+
+    #[test]
+    fn malformed_responses_count_as_no_response() {
+        let server = serve(|query| {
+            let mut response = reply(query, 1, 0, 0, 1, 0, 0);
+            let end = response.len() as u8;
+            match query.question.qname_dec.as_str() {
+                // 所有者名が自分自身を指す圧縮ポインタ
+                "loop.example" => response.extend([0xC0, end, 0, 1, 0, 1, 0, 0, 0, 0, 0, 4]),
+                // RDATA が途中で終わっている MX
+                _ => response.extend([0xC0, 12, 0, 15, 0, 1, 0, 0, 0, 0, 0, 1, 10]),
+            }
+            response
+        });
+        let config = ResolverConfig {
+            timeout: Duration::from_millis(300),
+            ..ResolverConfig::new()
+        };
+
+        assert!(send_query(&config, server, "loop.example", 1, false).is_none());
+        assert!(send_query(&config, server, "short.example", 15, false).is_none());
+        // 壊れた応答を返しても、リゾルバはそのまま動き続ける
+        let resolver = Resolver::with_config(ResolverConfig::forward(&[server]));
+        assert!(resolver.lookup("loop.example", 1).is_none());
+        assert!(resolver.lookup("short.example", 15).is_none());
+    }
+
+    /** UDP では TC=1 で各セクションを空にし、同じポートの TCP では A 192.0.2.1 を返すサーバ */
+    fn fake_truncating_server(tcp: bool) -> SocketAddr {
+        let udp = serve(|query| {
+            let mut response = reply(query, 1, 0, 0, 0, 0, 0);
+            response[2] |= 0x02;
+            response
+        });
+        if tcp {
+            let listener = TcpListener::bind(udp).unwrap();
+            thread::spawn(move || {
+                for mut stream in listener.incoming().flatten() {
+                    let mut length = [0; 2];
+                    stream.read_exact(&mut length).unwrap();
+                    let mut buf = vec![0; usize::from(u16::from_be_bytes(length))];
+                    stream.read_exact(&mut buf).unwrap();
+                    let query = Message::parse(&buf).unwrap();
+                    let mut response = reply(&query, 1, 0, 0, 1, 0, 0);
+                    response.extend(record(&query.question.qname_dec, 1, 3600, &[192, 0, 2, 1]));
+                    let mut framed = (response.len() as u16).to_be_bytes().to_vec();
+                    framed.extend(response);
+                    stream.write_all(&framed).unwrap();
+                }
+            });
+        }
+        udp
+    }
+
+    #[test]
+    fn truncated_responses_are_retried_over_tcp() {
+        let config = ResolverConfig {
+            timeout: Duration::from_millis(300),
+            ..ResolverConfig::new()
+        };
+        let response = send_query(
+            &config,
+            fake_truncating_server(true),
+            "big.example",
+            1,
+            false,
+        )
+        .unwrap();
+        assert_eq!(response.header.tc(), 0);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(
+            response.answers[0].address,
+            Some("192.0.2.1".parse().unwrap())
+        );
+
+        // TCP で問い合わせ直せなければ、切り詰められた応答は使わない
+        assert!(send_query(
+            &config,
+            fake_truncating_server(false),
+            "big.example",
+            1,
+            false
+        )
+        .is_none());
+    }
+
+    /** ". NS" に a.root.lab (10.0.0.53 と fd00::53) だけを、NS の TTL を ttl として返すルートサーバ */
+    fn fake_root(ttl: u32) -> SocketAddr {
+        serve(move |query| {
+            assert_eq!(query.question.qname_dec, "");
+            assert_eq!(query.question.qtype, 2);
+            assert!(query.edns.is_some());
+            let mut response = reply(query, 1, 0, 0, 1, 0, 2);
+            let nsdname = Question::new("a.root.lab", 0, 0).unwrap().qname;
+            response.extend(record("", 2, ttl, &nsdname));
+            response.extend(record("A.ROOT.LAB", 1, 518400, &[10, 0, 0, 53]));
+            let ipv6: std::net::Ipv6Addr = "fd00::53".parse().unwrap();
+            response.extend(record("a.root.lab", 28, 518400, &ipv6.octets()));
+            response
+        })
+    }
+
+    #[test]
+    fn priming_refreshes_root_servers() {
+        let resolver = Resolver::new();
+
+        assert!(resolver.prime_from(fake_root(518400)));
+        assert_eq!(
+            resolver.root_servers(),
+            vec![
+                "10.0.0.53:53".parse::<SocketAddr>().unwrap(),
+                "[fd00::53]:53".parse().unwrap()
+            ]
+        );
+
+        // TTL が 0 でも、問い合わせのたびにプライミングし直さない
+        let resolver = Resolver::new();
+        assert!(resolver.prime_from(fake_root(0)));
+        assert_eq!(resolver.current_roots().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn iterative_skips_unresponsive_server() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let silent_addr = silent.local_addr().unwrap();
+        let authority = fake_server(0, 0, |_| 0);
+        let mut config = ResolverConfig::new();
+        config.timeout = Duration::from_millis(200);
+        let mut resolver = Resolver::with_config(config);
+        resolver.rtt = RttTable::with_exploration(0.0);
+        resolver.rtt.record(silent_addr, Duration::from_millis(1));
+        resolver.rtt.record(authority, Duration::from_millis(100));
+
+        let response = resolver
+            .resolve_iterative("www.nyamikan.net", 1, &[silent_addr, authority])
+            .unwrap();
+        assert_eq!(response.answers.len(), 1);
+        assert!(resolver.rtt.srtt(silent_addr).unwrap() >= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn iterative_skips_servers_that_return_errors() {
+        let mut resolver = Resolver::with_config(ResolverConfig::new());
+        resolver.rtt = RttTable::with_exploration(0.0);
+        let authority = fake_server(0, 0, |_| 0);
+        resolver.rtt.record(authority, Duration::from_millis(100));
+        for rcode in [1, 2, 4, 5] {
+            let lame = fake_server(0, 0, move |_| rcode);
+            resolver.rtt.record(lame, Duration::from_millis(1));
+
+            let response = resolver
+                .resolve_iterative("www.nyamikan.net", 1, &[lame, authority])
+                .unwrap();
+            assert_eq!(response.header.rcode(), 0);
+            assert_eq!(response.answers.len(), 1);
+            assert!(resolver.rtt.srtt(lame).unwrap() >= Duration::from_secs(1));
+        }
+
+        // どのサーバもエラーを返すなら、答えは得られない
+        let lame = fake_server(0, 0, |_| 5);
+        assert!(resolver
+            .resolve_iterative("www.nyamikan.net", 1, &[lame])
+            .is_none());
+    }
+
+    /** サーバが受け取った QNAME と QTYPE */
+    type QueryLog = Arc<Mutex<Vec<(String, u16)>>>;
+
+    /** ゾーンの境界を持たない権威サーバ。受け取った QNAME と QTYPE を記録し、answer_name にだけ A を返す */
+    fn fake_flat_authority(
+        answer_name: &'static str,
+        nxdomain_name: &'static str,
+    ) -> (SocketAddr, QueryLog) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let server_log = log.clone();
+        let addr = serve(move |query| {
+            let qname = query.question.qname_dec.clone();
+            server_log
+                .lock()
+                .unwrap()
+                .push((qname.clone(), query.question.qtype));
+            if qname == answer_name {
+                let mut response = reply(query, 1, 0, 0, 1, 0, 0);
+                response.extend(record(&qname, 1, 3600, &[192, 0, 2, 1]));
+                response
+            } else if qname == nxdomain_name {
+                reply(query, 1, 0, 3, 0, 0, 0)
+            } else {
+                reply(query, 1, 0, 0, 0, 0, 0)
+            }
+        });
+        (addr, log)
+    }
+
+    #[test]
+    fn minimisation_steps() {
+        let steps: Vec<usize> = (0..8)
+            .scan(Minimisation::default(), |minimisation, _| {
+                *minimisation = minimisation.next(20);
+                Some(minimisation.labels)
+            })
+            .collect();
+        assert_eq!(steps, vec![1, 2, 3, 4, 7, 10, 13, 16]);
+        assert_eq!(Minimisation::default().next(1).labels, 1);
+    }
+
+    #[test]
+    fn qname_minimisation_reveals_one_label_at_a_time() {
+        let (authority, log) = fake_flat_authority("www.dev.nyamikan.net", "");
+        let mut config = ResolverConfig::new();
+        config.qname_minimisation = true;
+        let resolver = Resolver::with_config(config);
+
+        let response = resolver
+            .resolve_iterative("www.dev.nyamikan.net", 1, &[authority])
+            .unwrap();
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                ("net".to_string(), 1),
+                ("nyamikan.net".to_string(), 1),
+                ("dev.nyamikan.net".to_string(), 1),
+                ("www.dev.nyamikan.net".to_string(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn qname_minimisation_falls_back_on_nxdomain() {
+        let (authority, log) = fake_flat_authority("www.dev.nyamikan.net", "nyamikan.net");
+        let mut config = ResolverConfig::new();
+        config.qname_minimisation = true;
+        let resolver = Resolver::with_config(config);
+
+        let response = resolver
+            .resolve_iterative("www.dev.nyamikan.net", 28, &[authority])
+            .unwrap();
+        assert_eq!(response.header.rcode(), 0);
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                ("net".to_string(), 1),
+                ("nyamikan.net".to_string(), 1),
+                ("www.dev.nyamikan.net".to_string(), 28),
+            ]
+        );
+    }
+
+    /** qname_case で質問部の大文字・小文字を書き換えて応答する権威サーバ */
+    fn fake_case_authority(qname_case: fn(&mut [u8])) -> (SocketAddr, QueryLog) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let server_log = log.clone();
+        let addr = serve(move |query| {
+            server_log
+                .lock()
+                .unwrap()
+                .push((query.question.qname_dec.clone(), query.question.qtype));
+            let mut response = reply(query, 1, 0, 0, 0, 0, 0);
+            qname_case(&mut response[12..]);
+            response
+        });
+        (addr, log)
+    }
+
+    fn case_randomising_resolver() -> Resolver {
+        let mut config = ResolverConfig::new();
+        config.case_randomisation = true;
+        config.timeout = Duration::from_millis(200);
+        Resolver::with_config(config)
+    }
+
+    #[test]
+    fn case_randomisation_preserved() {
+        let (authority, log) = fake_case_authority(|_| {});
+        let resolver = case_randomising_resolver();
 
-    return resolve_iterative(fqdn, qtype, nameserver);
+        let response = resolver
+            .resolve_iterative("www.randomisation.nyamikan.net", 1, &[authority])
+            .unwrap();
+        assert_eq!(response.header.rcode(), 0);
+
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 1);
+        assert!(log[0]
+            .0
+            .eq_ignore_ascii_case("www.randomisation.nyamikan.net"));
+        assert_ne!(log[0].0, "www.randomisation.nyamikan.net");
+    }
+
+    #[test]
+    fn case_randomisation_falls_back_per_server() {
+        let (authority, log) = fake_case_authority(|qname| qname.make_ascii_lowercase());
+        let resolver = case_randomising_resolver();
+        let nameservers = vec![authority];
+
+        assert!(resolver
+            .resolve_iterative("www.randomisation.nyamikan.net", 1, &nameservers)
+            .is_some());
+        assert!(resolver
+            .case_insensitive_servers
+            .lock()
+            .unwrap()
+            .contains(&authority));
+        assert!(resolver
+            .resolve_iterative("www.randomisation.nyamikan.net", 1, &nameservers)
+            .is_some());
+
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 3);
+        assert_ne!(log[0].0, "www.randomisation.nyamikan.net");
+        assert_eq!(log[1].0, "www.randomisation.nyamikan.net");
+        assert_eq!(log[2].0, "www.randomisation.nyamikan.net");
+    }
+
+    #[test]
+    fn case_randomisation_rejects_other_question() {
+        let (authority, _) = fake_case_authority(|qname| qname[1] = b'x');
+        let resolver = case_randomising_resolver();
+
+        assert!(resolver
+            .resolve_iterative("www.nyamikan.net", 1, &[authority])
+            .is_none());
+    }
+
+    /** zone を nameservers に委任する応答 (glue は付加情報部のアドレス) */
+    fn delegation(qname: &str, zone: &str, nameservers: &[&str], glue: Vec<Resource>) -> Message {
+        Message {
+            authorities: nameservers
+                .iter()
+                .map(|ns| resource(zone, 2, &Question::new(ns, 0, 0).unwrap().qname))
+                .collect(),
+            additionals: glue,
+            ..Message::new(Header::new(), Question::new(qname, 1, 1).unwrap())
+        }
+    }
+
+    #[test]
+    fn referrals_use_ipv6_glue_and_every_nameserver() {
+        let ipv6: std::net::Ipv6Addr = "2001:db8::53".parse().unwrap();
+        let resolver = Resolver::new();
+        let ipv6_only = delegation(
+            "www.lab",
+            "lab",
+            &["ns.lab"],
+            vec![resource("NS.lab", 28, &ipv6.octets())],
+        );
+        assert_eq!(
+            resolver.referral_servers(&ipv6_only),
+            Some(vec!["[2001:db8::53]:53".parse().unwrap()])
+        );
+
+        // グルーがなければネームサーバの名前を解決する。解決できない名前は飛ばし、AAAA も使う
+        let upstream = serve(move |query| {
+            let qname = query.question.qname_dec.as_str();
+            match (qname, query.question.qtype) {
+                ("ns2.example", 28) => {
+                    let mut response = reply(query, 0, 1, 0, 1, 0, 0);
+                    response.extend(record(qname, 28, 3600, &ipv6.octets()));
+                    response
+                }
+                ("ns2.example", 1) => reply(query, 0, 1, 0, 0, 0, 0),
+                _ => reply(query, 0, 1, 3, 0, 0, 0),
+            }
+        });
+        let resolver = Resolver::with_config(ResolverConfig::forward(&[upstream]));
+        let glueless = delegation(
+            "www.lab",
+            "lab",
+            &["ns1.example", "ns2.example"],
+            Vec::new(),
+        );
+        assert_eq!(
+            resolver.referral_servers(&glueless),
+            Some(vec!["[2001:db8::53]:53".parse().unwrap()])
+        );
+        let lame = delegation("www.lab", "lab", &["ns1.example"], Vec::new());
+        assert_eq!(resolver.referral_servers(&lame), None);
+    }
+
+    #[test]
+    fn referrals_that_do_not_get_closer_are_not_followed() {
+        // 問い合わせた名前に近づかない委任 (横のゾーン、上のゾーン) を返し続けるサーバ
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let server_log = log.clone();
+        let server = serve(move |query| {
+            let qname = query.question.qname_dec.clone();
+            server_log.lock().unwrap().push(qname.clone());
+            let zone = if qname.ends_with("sideways.lab") {
+                "other.lab"
+            } else {
+                ""
+            };
+            let mut response = reply(query, 0, 0, 0, 0, 1, 1);
+            response.extend(record(
+                zone,
+                2,
+                3600,
+                &Question::new("ns.lab", 0, 0).unwrap().qname,
+            ));
+            response.extend(record("ns.lab", 1, 3600, &[127, 0, 0, 1]));
+            response
+        });
+        let resolver = Resolver::new();
+
+        assert!(resolver
+            .resolve_iterative("www.sideways.lab", 1, &[server])
+            .is_none());
+        assert!(resolver
+            .resolve_iterative("www.up.lab", 1, &[server])
+            .is_none());
+        assert_eq!(log.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn forward_fails_over_to_recursive_upstream() {
+        let not_recursive = fake_upstream(0, 0);
+        let servfail = fake_upstream(1, 2);
+        let recursive = fake_upstream(1, 0);
+        let resolver = Resolver::with_config(ResolverConfig::forward(&[
+            not_recursive,
+            servfail,
+            recursive,
+        ]));
+
+        assert_eq!(
+            resolver.resolve("www.nyamikan.net", 1),
+            Some("192.0.2.1".parse::<IpAddr>().unwrap())
+        );
+    }
+
+    #[test]
+    fn https_alias_mode_is_followed() {
+        let alias = |target: &str| Svcb {
+            priority: 0,
+            target: target.to_string(),
+            params: Vec::new(),
+        };
+        let upstream = serve(move |query| {
+            assert_eq!(query.question.qtype, 65);
+            let qname = query.question.qname_dec.as_str();
+            let svcb = match qname {
+                "example.com" => alias("svc.example.net"),
+                "svc.example.net" => Svcb {
+                    priority: 1,
+                    target: "".to_string(),
+                    params: vec![SvcParam::Alpn(vec![b"h2".to_vec()])],
+                },
+                "loop.example" => alias("loop2.example"),
+                "loop2.example" => alias("LOOP.example"),
+                "none.example" => alias(""),
+                _ => return reply(query, 0, 1, 3, 0, 0, 0),
+            };
+            let mut response = reply(query, 0, 1, 0, 1, 0, 0);
+            response.extend(record(qname, 65, 300, &svcb.to_rdata()));
+            response
+        });
+        let resolver = Resolver::with_config(ResolverConfig::forward(&[upstream]));
+
+        let response = resolver.lookup("example.com", 65).unwrap();
+        assert_eq!(response.question.qname_dec, "example.com");
+        let answers: Vec<(&str, u16)> = response
+            .answers
+            .iter()
+            .map(|r| (r.name.as_str(), r.svcb.as_ref().unwrap().priority))
+            .collect();
+        assert_eq!(answers, vec![("example.com", 0), ("svc.example.net", 1)]);
+        assert_eq!(response.header.an_count, 2);
+
+        // 別名がルートなら、サービスがないことを示す応答をそのまま返す
+        let none = resolver.lookup("none.example", 65).unwrap();
+        assert_eq!(none.answers.len(), 1);
+        assert!(resolver.lookup("loop.example", 65).is_none());
+    }
+
+    #[test]
+    fn reverse_names() {
+        assert_eq!(
+            reverse_name("192.0.2.1".parse().unwrap()),
+            "1.2.0.192.in-addr.arpa"
+        );
+        // RFC 3596 Section 2.5 の例
+        assert_eq!(
+            reverse_name("4321:0:1:2:3:4:567:89ab".parse().unwrap()),
+            "b.a.9.8.7.6.5.0.4.0.0.0.3.0.0.0.2.0.0.0.1.0.0.0.0.0.0.0.1.2.3.4.ip6.arpa"
+        );
+    }
+
+    #[test]
+    fn reverse_lookup_with_forward_confirmation() {
+        let upstream = serve(|query| {
+            let qname = query.question.qname_dec.as_str();
+            let records: Vec<Vec<u8>> = match (qname, query.question.qtype) {
+                ("1.2.0.192.in-addr.arpa", 12) => vec![
+                    record(
+                        qname,
+                        12,
+                        3600,
+                        &Question::new("host.example", 0, 0).unwrap().qname,
+                    ),
+                    record(
+                        qname,
+                        12,
+                        3600,
+                        &Question::new("spoof.example", 0, 0).unwrap().qname,
+                    ),
+                ],
+                // RFC 2317 の CNAME による委任
+                (
+                    "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa",
+                    12,
+                ) => {
+                    vec![record(
+                        qname,
+                        5,
+                        3600,
+                        &Question::new("1.0-15.ip6.example", 0, 0).unwrap().qname,
+                    )]
+                }
+                ("1.0-15.ip6.example", 12) => vec![record(
+                    qname,
+                    12,
+                    3600,
+                    &Question::new("host.example", 0, 0).unwrap().qname,
+                )],
+                ("host.example", 1) => vec![record(qname, 1, 3600, &[192, 0, 2, 1])],
+                ("host.example", 28) => vec![record(
+                    qname,
+                    28,
+                    3600,
+                    &"2001:db8::1"
+                        .parse::<std::net::Ipv6Addr>()
+                        .unwrap()
+                        .octets(),
+                )],
+                ("spoof.example", 1) => vec![record(qname, 1, 3600, &[198, 51, 100, 1])],
+                // 逆引きの名前が別名で、正引きの応答には CNAME しかない
+                ("2.2.0.192.in-addr.arpa", 12) => vec![record(
+                    qname,
+                    12,
+                    3600,
+                    &Question::new("alias.example", 0, 0).unwrap().qname,
+                )],
+                ("alias.example", 1) => vec![record(
+                    qname,
+                    5,
+                    3600,
+                    &Question::new("web.example", 0, 0).unwrap().qname,
+                )],
+                ("web.example", 1) => vec![record(qname, 1, 3600, &[192, 0, 2, 2])],
+                _ => return reply(query, 0, 1, 3, 0, 0, 0),
+            };
+            let mut response = reply(query, 0, 1, 0, records.len() as u16, 0, 0);
+            response.extend(records.concat());
+            response
+        });
+        let resolver = Resolver::with_config(ResolverConfig::forward(&[upstream]));
+        let v4 = "192.0.2.1".parse().unwrap();
+        let v6 = "2001:db8::1".parse().unwrap();
+
+        assert_eq!(
+            resolver.reverse_lookup(v4),
+            Some(vec![
+                "host.example".to_string(),
+                "spoof.example".to_string()
+            ])
+        );
+        assert_eq!(
+            resolver.reverse_lookup_confirmed(v4),
+            Some(vec!["host.example".to_string()])
+        );
+        assert_eq!(
+            resolver.reverse_lookup_confirmed(v6),
+            Some(vec!["host.example".to_string()])
+        );
+        assert_eq!(
+            resolver.reverse_lookup_confirmed("192.0.2.2".parse().unwrap()),
+            Some(vec!["alias.example".to_string()])
+        );
+        assert_eq!(
+            resolver.reverse_lookup("198.51.100.1".parse().unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn lookup_ip_merges_both_families_through_cnames() {
+        let upstream = serve(|query| {
+            let qname = query.question.qname_dec.as_str();
+            let alias =
+                |target: &str| record(qname, 5, 3600, &Question::new(target, 0, 0).unwrap().qname);
+            let records: Vec<Vec<u8>> = match (qname, query.question.qtype) {
+                // A は別名の先のレコードまで 1 つの応答に含める
+                ("www.example", 1) => vec![
+                    alias("web.example"),
+                    record("web.example", 1, 3600, &[192, 0, 2, 1]),
+                    record("web.example", 1, 3600, &[192, 0, 2, 2]),
+                ],
+                // AAAA は CNAME だけを返し、別名の先を問い合わせ直させる
+                ("www.example", 28) => vec![alias("web.example")],
+                ("web.example", 28) => vec![record(
+                    qname,
+                    28,
+                    3600,
+                    &"2001:db8::1"
+                        .parse::<std::net::Ipv6Addr>()
+                        .unwrap()
+                        .octets(),
+                )],
+                ("v4only.example", 1) => vec![record(qname, 1, 3600, &[192, 0, 2, 3])],
+                ("v4only.example", 28) => vec![],
+                ("loop.example", _) => vec![alias("loop.example")],
+                _ => return reply(query, 0, 1, 3, 0, 0, 0),
+            };
+            let mut response = reply(query, 0, 1, 0, records.len() as u16, 0, 0);
+            response.extend(records.concat());
+            response
+        });
+        let resolver = Resolver::with_config(ResolverConfig::forward(&[upstream]));
+
+        // 並び順は手元の経路で変わるので、集合として比べる
+        let mut addresses = resolver.lookup_ip("www.example").unwrap();
+        addresses.sort();
+        assert_eq!(
+            addresses,
+            ["192.0.2.1", "192.0.2.2", "2001:db8::1"]
+                .iter()
+                .map(|a| a.parse::<IpAddr>().unwrap())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            resolver.lookup_ip("v4only.example"),
+            Some(vec!["192.0.2.3".parse::<IpAddr>().unwrap()])
+        );
+        assert_eq!(resolver.lookup_ip("nxdomain.example"), None);
+        assert_eq!(resolver.lookup_ip("loop.example"), None);
+    }
+
+    #[test]
+    fn forward_rotates_and_retries() {
+        let refused = fake_upstream(1, 5);
+        let recursive = fake_upstream(1, 0);
+        let mut config = ResolverConfig::forward(&[recursive, refused]);
+        config.rotate = true;
+        config.attempts = 1;
+        config.edns0 = true;
+        let resolver = Resolver::with_config(config);
+
+        // 2 回目は refused から始まるが、recursive にフェイルオーバーする
+        assert!(resolver.resolve("www.nyamikan.net", 1).is_some());
+        assert!(resolver.resolve("www.nyamikan.net", 1).is_some());
+    }
+
+    #[test]
+    fn search_list_expansion() {
+        let upstream = fake_upstream_with(1, |qname| {
+            if qname.eq_ignore_ascii_case("db.example.com") {
+                0
+            } else {
+                3
+            }
+        });
+        let mut config = ResolverConfig::forward(&[upstream]);
+        config.search = vec!["corp.example".to_string(), "example.com".to_string()];
+        let resolver = Resolver::with_config(config);
+
+        let (name, response) = resolver.lookup_search("db", 1).unwrap();
+        assert_eq!(name, "db.example.com");
+        assert_eq!(response.answers.len(), 1);
+        assert!(resolver.lookup_search("db.", 1).is_none());
+        assert!(resolver.lookup_search("web", 1).is_none());
+    }
+
+    #[test]
+    fn hosts_answered_before_network() {
+        let mut config = ResolverConfig::forward(&["127.0.0.1:9".parse().unwrap()]);
+        config.hosts = Hosts::parse("192.0.2.1 db.corp.example\n");
+        let resolver = Resolver::with_config(config);
+
+        assert_eq!(
+            resolver.resolve("DB.corp.example", 1),
+            Some("192.0.2.1".parse::<IpAddr>().unwrap())
+        );
+    }
+
+    #[test]
+    fn forward_gives_up_when_all_upstreams_fail() {
+        let refused = fake_upstream(1, 5);
+        let resolver = Resolver::with_config(ResolverConfig::forward(&[refused]));
+
+        assert!(resolver.lookup("www.nyamikan.net", 1).is_none());
+    }
+
+    #[test]
+    fn over_long_names_are_not_sent() {
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&queries);
+        let upstream = serve(move |query| {
+            counted.fetch_add(1, Ordering::SeqCst);
+            reply(query, 0, 1, 3, 0, 0, 0)
+        });
+        let resolver = Resolver::with_config(ResolverConfig::forward(&[upstream]));
+
+        let name = ["a".repeat(64), "example".to_string()].join(".");
+        assert!(resolver.lookup(&name, 1).is_none());
+        assert!(resolver.resolve_iterative(&name, 1, &[upstream]).is_none());
+        assert!(resolver.lookup_ip(&name).is_none());
+        assert_eq!(queries.load(Ordering::SeqCst), 0);
+    }
+
+    /** 問い合わせに決まったセクションで答える権威サーバ。DO ビットのない問い合わせは受け付けない */
+    fn fake_signed_authority(answers: Vec<((&'static str, u16), Vec<Resource>)>) -> SocketAddr {
+        fake_signed_authority_with(answers, Vec::new()).0
+    }
+
+    /**
+     * answers: (QNAME, QTYPE) ごとの Answer
+     * negatives: それ以外の問い合わせに QNAME ごとに返す RCODE と Authority
+     */
+    fn fake_signed_authority_with(
+        answers: Vec<((&'static str, u16), Vec<Resource>)>,
+        negatives: Vec<(&'static str, u8, Vec<Resource>)>,
+    ) -> (SocketAddr, QueryLog) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let server_log = log.clone();
+        let addr = serve(move |query| {
+            assert!(query.edns.unwrap().dnssec_ok);
+            let qname = query.question.qname_dec.as_str();
+            server_log
+                .lock()
+                .unwrap()
+                .push((qname.to_string(), query.question.qtype));
+            let key = (qname, query.question.qtype);
+            if let Some((_, records)) = answers.iter().find(|(k, _)| *k == key) {
+                let mut response = reply(query, 1, 0, 0, records.len() as u16, 0, 0);
+                for record in records {
+                    response.extend(record.to_byte());
+                }
+                return response;
+            }
+            match negatives.iter().find(|(name, _, _)| *name == qname) {
+                Some((_, rcode, records)) => {
+                    let mut response = reply(query, 1, 0, *rcode, 0, records.len() as u16, 0);
+                    for record in records {
+                        response.extend(record.to_byte());
+                    }
+                    response
+                }
+                None => reply(query, 1, 0, 3, 0, 0, 0),
+            }
+        });
+        (addr, log)
+    }
+
+    /** records に key の RRSIG を加える */
+    fn signed(key: &SigningKey, mut records: Vec<Resource>) -> Vec<Resource> {
+        records.push(key.sign(&records));
+        records
+    }
+
+    fn validating_resolver(anchor: &SigningKey) -> Resolver {
+        let mut config = ResolverConfig::new();
+        config.dnssec = true;
+        config.trust_anchor = TrustAnchor {
+            zone: anchor.zone.clone(),
+            ds: vec![anchor.ds()],
+            keys: Vec::new(),
+        };
+        Resolver::with_config(config)
+    }
+
+    #[test]
+    fn dnssec_validates_answers_from_trust_anchor() {
+        let root = SigningKey::ed25519("", 1);
+        let forged = signed(&root, vec![resource("evil.lab", 1, &[192, 0, 2, 1])]);
+        let server = fake_signed_authority(vec![
+            (("", 48), signed(&root, vec![root.dnskey_record()])),
+            (
+                ("www.lab", 1),
+                signed(&root, vec![resource("www.lab", 1, &[192, 0, 2, 1])]),
+            ),
+            (
+                ("evil.lab", 1),
+                vec![resource("evil.lab", 1, &[192, 0, 2, 66]), forged[1].clone()],
+            ),
+            (
+                ("unsigned.lab", 1),
+                vec![resource("unsigned.lab", 1, &[192, 0, 2, 1])],
+            ),
+            // ラベルの中のドット: 2 つのラベルの名前で、ワイルドカードから合成されたものではない
+            (
+                ("a\\.b.lab", 1),
+                signed(&root, vec![resource("a\\.b.lab", 1, &[192, 0, 2, 1])]),
+            ),
+        ]);
+        let resolver = validating_resolver(&root);
+        let nameservers = vec![server];
+
+        let validate = |fqdn| {
+            resolver
+                .resolve_iterative_validated(fqdn, 1, &nameservers)
+                .unwrap()
+                .1
+        };
+        assert_eq!(validate("www.lab"), Security::Secure);
+        assert_eq!(validate("evil.lab"), Security::Bogus);
+        assert_eq!(validate("unsigned.lab"), Security::Bogus);
+        assert_eq!(validate("a\\.b.lab"), Security::Secure);
+
+        // 信頼していない鍵から始めると、ルートの DNSKEY を認証できない
+        let untrusted = validating_resolver(&SigningKey::ed25519("", 2));
+        assert_eq!(
+            untrusted
+                .resolve_iterative_validated("www.lab", 1, &nameservers)
+                .unwrap()
+                .1,
+            Security::Bogus
+        );
+    }
+
+    #[test]
+    fn dnssec_follows_delegations() {
+        let root = SigningKey::ed25519("", 1);
+        let lab = SigningKey::ecdsa_p256("lab");
+        let ds = resource("lab", 43, &lab.ds().to_rdata());
+        let server = fake_signed_authority(vec![
+            (("", 48), signed(&root, vec![root.dnskey_record()])),
+            (("lab", 48), signed(&lab, vec![lab.dnskey_record()])),
+        ]);
+        let resolver = validating_resolver(&root);
+
+        let referral = |zone: &str, authorities: Vec<Resource>| ZoneCut {
+            zone: zone.to_string(),
+            nameservers: vec![server],
+            referral: Some(Arc::new(Message {
+                authorities,
+                ..Message::new(Header::new(), Question::new(zone, 1, 1).unwrap())
+            })),
+        };
+        let path = |cut: ZoneCut| {
+            vec![
+                ZoneCut {
+                    zone: "".to_string(),
+                    nameservers: vec![server],
+                    referral: None,
+                },
+                cut,
+            ]
+        };
+        let answer = |records: Vec<Resource>| Message {
+            answers: records,
+            ..Message::new(Header::new(), Question::new("www.lab", 1, 1).unwrap())
+        };
+        let www = || vec![resource("www.lab", 1, &[192, 0, 2, 1])];
+
+        let signed_delegation = path(referral("lab", signed(&root, vec![ds.clone()])));
+        assert_eq!(
+            resolver.validate(&signed_delegation, &answer(signed(&lab, www()))),
+            Security::Secure
+        );
+        // 子ゾーンの応答を親ゾーンの鍵で署名しても認めない
+        assert_eq!(
+            resolver.validate(&signed_delegation, &answer(signed(&root, www()))),
+            Security::Bogus
+        );
+        // DS の署名がない委任
+        assert_eq!(
+            resolver.validate(&path(referral("lab", vec![ds])), &answer(www())),
+            Security::Bogus
+        );
+
+        // NSEC で DS の不在が示された委任の先は検証しない
+        let nsec = Nsec {
+            next_domain_name: "www".to_string(),
+            types: vec![2, 46, 47],
+        };
+        let insecure = path(referral(
+            "lab",
+            signed(&root, vec![resource("lab", 47, &nsec.to_rdata())]),
+        ));
+        assert_eq!(
+            resolver.validate(&insecure, &answer(www())),
+            Security::Insecure
+        );
+        assert_eq!(
+            resolver.validate(&path(referral("lab", Vec::new())), &answer(www())),
+            Security::Bogus
+        );
+    }
+
+    #[test]
+    fn dnssec_finds_zone_cuts_from_the_signer() {
+        // 親 (ルート) と子 (lab) を同じサーバが持つので、lab への委任の応答は返ってこない
+        let root = SigningKey::ed25519("", 1);
+        let lab = SigningKey::ecdsa_p256("lab");
+        let server = fake_signed_authority(vec![
+            (("", 48), signed(&root, vec![root.dnskey_record()])),
+            (
+                ("lab", 43),
+                signed(&root, vec![resource("lab", 43, &lab.ds().to_rdata())]),
+            ),
+            (("lab", 48), signed(&lab, vec![lab.dnskey_record()])),
+            (
+                ("www.lab", 1),
+                signed(&lab, vec![resource("www.lab", 1, &[192, 0, 2, 1])]),
+            ),
+            (
+                ("www.sub.lab", 1),
+                signed(&lab, vec![resource("www.sub.lab", 1, &[192, 0, 2, 2])]),
+            ),
+            // lab の DS が指していない鍵で署名した応答
+            (
+                ("evil.lab", 1),
+                signed(
+                    &SigningKey::ed25519("lab", 3),
+                    vec![resource("evil.lab", 1, &[192, 0, 2, 66])],
+                ),
+            ),
+        ]);
+        let resolver = validating_resolver(&root);
+        let nameservers = vec![server];
+        let validate = |fqdn| {
+            resolver
+                .resolve_iterative_validated(fqdn, 1, &nameservers)
+                .unwrap()
+                .1
+        };
+
+        assert_eq!(validate("www.lab"), Security::Secure);
+        assert_eq!(validate("www.sub.lab"), Security::Secure);
+        assert_eq!(validate("evil.lab"), Security::Bogus);
+    }
+
+    #[test]
+    fn dnssec_validates_negative_answers() {
+        let root = SigningKey::ed25519("", 1);
+        // MNAME と RNAME はルート、SERIAL などは 0
+        let soa = resource("", 6, &[0; 22]);
+        let nsec = |owner: &str, next: &str, types: Vec<u16>| {
+            let nsec = Nsec {
+                next_domain_name: next.to_string(),
+                types,
+            };
+            signed(&root, vec![resource(owner, 47, &nsec.to_rdata())])
+        };
+        let proof = |records: Vec<Vec<Resource>>| {
+            let mut authorities = signed(&root, vec![soa.clone()]);
+            authorities.extend(records.into_iter().flatten());
+            authorities
+        };
+        let (server, _) = fake_signed_authority_with(
+            vec![(("", 48), signed(&root, vec![root.dnskey_record()]))],
+            vec![
+                // lab. と www.lab. だけがあるゾーン
+                (
+                    "nowhere.lab",
+                    3,
+                    proof(vec![
+                        nsec("lab", "www.lab", vec![46, 47]),
+                        nsec("", "lab", vec![2, 6, 46, 47, 48]),
+                    ]),
+                ),
+                (
+                    "www.lab",
+                    0,
+                    proof(vec![nsec("www.lab", "", vec![1, 46, 47])]),
+                ),
+                // 証明が足りない (ワイルドカード *.lab がないことを示していない)
+                (
+                    "zzz.lab",
+                    3,
+                    proof(vec![nsec("www.lab", "", vec![1, 46, 47])]),
+                ),
+                ("unsigned.lab", 3, vec![soa.clone()]),
+            ],
+        );
+        let resolver = validating_resolver(&root);
+        let nameservers = vec![server];
+
+        let validate = |fqdn, qtype| {
+            let (response, security) = resolver
+                .resolve_iterative_validated(fqdn, qtype, &nameservers)
+                .unwrap();
+            assert!(response.answers.is_empty());
+            security
+        };
+        assert_eq!(validate("nowhere.lab", 1), Security::Secure);
+        assert_eq!(validate("www.lab", 28), Security::Secure);
+        assert_eq!(validate("zzz.lab", 1), Security::Bogus);
+        assert_eq!(validate("unsigned.lab", 1), Security::Bogus);
+    }
+
+    #[test]
+    fn aggressive_nsec_synthesises_negative_answers() {
+        let root = SigningKey::ed25519("", 1);
+        // MNAME と RNAME はルート、MINIMUM は 300
+        let mut rdata = vec![0; 18];
+        rdata.extend(300u32.to_be_bytes());
+        let soa = signed(&root, vec![resource("", 6, &rdata)]);
+        let nsec = |owner: &str, next: &str, types: Vec<u16>| {
+            let nsec = Nsec {
+                next_domain_name: next.to_string(),
+                types,
+            };
+            signed(&root, vec![resource(owner, 47, &nsec.to_rdata())])
+        };
+        let mut nxdomain = soa.clone();
+        nxdomain.extend(nsec("lab", "www.lab", vec![46, 47]));
+        nxdomain.extend(nsec("", "lab", vec![2, 6, 46, 47, 48]));
+        let mut nodata = soa;
+        nodata.extend(nsec("www.lab", "", vec![1, 46, 47]));
+        let (server, log) = fake_signed_authority_with(
+            vec![(("", 48), signed(&root, vec![root.dnskey_record()]))],
+            vec![("nowhere.lab", 3, nxdomain), ("www.lab", 0, nodata)],
+        );
+        let resolver = validating_resolver(&root);
+        let nameservers = vec![server];
+        let resolve = |fqdn, qtype| {
+            let (response, security) = resolver
+                .resolve_iterative_validated(fqdn, qtype, &nameservers)
+                .unwrap();
+            assert_eq!(security, Security::Secure);
+            response.header.rcode()
+        };
+
+        assert_eq!(resolve("nowhere.lab", 1), 3);
+        assert_eq!(resolve("www.lab", 28), 0);
+        let queries = log.lock().unwrap().len();
+
+        // 同じ NSEC が覆う別の名前とタイプには、権威サーバに問い合わせずに答える
+        assert_eq!(resolve("random123.lab", 1), 3);
+        assert_eq!(resolve("abc.lab", 28), 3);
+        assert_eq!(resolve("www.lab", 16), 0);
+        assert_eq!(log.lock().unwrap().len(), queries);
+
+        // 示せない問い合わせは権威サーバに送る
+        assert!(resolver
+            .resolve_iterative_validated("www.lab", 1, &nameservers)
+            .is_some());
+        assert!(log.lock().unwrap().len() > queries);
+    }
 }
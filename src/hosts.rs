@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+pub const HOSTS_PATH: &str = "/etc/hosts";
+
+/** hosts(5) の内容。名前は小文字・末尾のドットなしで保持する */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Hosts {
+    addresses: HashMap<String, Vec<IpAddr>>,
+}
+
+impl Hosts {
+    pub fn parse(text: &str) -> Self {
+        let mut hosts = Self::default();
+
+        for line in text.lines() {
+            let line = match line.find('#') {
+                Some(index) => &line[..index],
+                None => line,
+            };
+            let mut words = line.split_whitespace();
+            let address = match words.next().map(|word| word.parse::<IpAddr>()) {
+                Some(Ok(address)) => address,
+                _ => continue,
+            };
+            // 正式名とエイリアスを区別せずに登録する
+            for name in words {
+                let addresses = hosts.addresses.entry(normalize(name)).or_default();
+                if !addresses.contains(&address) {
+                    addresses.push(address);
+                }
+            }
+        }
+
+        hosts
+    }
+
+    /** name に対応するアドレスを、qtype (1: A, 28: AAAA) で絞り込んで返す */
+    pub fn lookup(&self, name: &str, qtype: u16) -> Vec<IpAddr> {
+        self.addresses
+            .get(&normalize(name))
+            .map(|addresses| {
+                addresses
+                    .iter()
+                    .filter(|address| match address {
+                        IpAddr::V4(_) => qtype == 1,
+                        IpAddr::V6(_) => qtype == 28,
+                    })
+                    .copied()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Hosts;
+    use std::net::IpAddr;
+
+    #[test]
+    fn parse_hosts() {
+        let hosts = Hosts::parse(
+            "127.0.0.1 localhost\n\
+             ::1 localhost ip6-localhost # loopback\n\
+             # 192.0.2.9 commented.example\n\
+             192.0.2.1 db.corp.example db\n\
+             192.0.2.2 db.corp.example\n\
+             not-an-address broken.example\n",
+        );
+
+        assert_eq!(
+            hosts.lookup("localhost", 1),
+            vec!["127.0.0.1".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(
+            hosts.lookup("LOCALHOST.", 28),
+            vec!["::1".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(
+            hosts.lookup("db.corp.example", 1),
+            vec![
+                "192.0.2.1".parse::<IpAddr>().unwrap(),
+                "192.0.2.2".parse::<IpAddr>().unwrap()
+            ]
+        );
+        assert_eq!(hosts.lookup("db", 1).len(), 1);
+        assert!(hosts.lookup("commented.example", 1).is_empty());
+        assert!(hosts.lookup("broken.example", 1).is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Condvar, Mutex};
+
+/** 同じキーに対する処理が同時に走った場合、先頭の 1 つだけを実行して結果を共有する */
+pub struct Inflight<K, V> {
+    calls: Mutex<HashMap<K, Arc<Call<V>>>>,
+}
+
+struct Call<V> {
+    state: Mutex<State<V>>,
+    done: Condvar,
+}
+
+enum State<V> {
+    Pending,
+    Done(V),
+    // 先頭の呼び出しが結果を残さずに終わった (panic など)
+    Abandoned,
+}
+
+/** 先頭の呼び出しが終わったとき (panic を含む) に、登録の解除と待機者への通知を行う */
+struct Finish<'a, K: Eq + Hash, V> {
+    inflight: &'a Inflight<K, V>,
+    key: &'a K,
+    call: &'a Call<V>,
+}
+
+impl<K: Eq + Hash, V> Drop for Finish<'_, K, V> {
+    fn drop(&mut self) {
+        if let Ok(mut calls) = self.inflight.calls.lock() {
+            calls.remove(self.key);
+        }
+        if let Ok(mut state) = self.call.state.lock() {
+            if let State::Pending = *state {
+                *state = State::Abandoned;
+            }
+        }
+        self.call.done.notify_all();
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Inflight<K, V> {
+    pub fn new() -> Self {
+        Self {
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /** key について処理中の呼び出しがあればその結果を待ち、なければ f を実行する */
+    pub fn run<F: FnOnce() -> V>(&self, key: K, f: F) -> V {
+        let (call, leader) = {
+            let mut calls = self.calls.lock().unwrap();
+            match calls.get(&key) {
+                Some(call) => (call.clone(), false),
+                None => {
+                    let call = Arc::new(Call {
+                        state: Mutex::new(State::Pending),
+                        done: Condvar::new(),
+                    });
+                    calls.insert(key.clone(), call.clone());
+                    (call, true)
+                }
+            }
+        };
+
+        if !leader {
+            let mut state = call.state.lock().unwrap();
+            while let State::Pending = *state {
+                state = call.done.wait(state).unwrap();
+            }
+            if let State::Done(value) = &*state {
+                return value.clone();
+            }
+            // 先頭の呼び出しが失敗したので、自分で実行する
+            drop(state);
+            return f();
+        }
+
+        let finish = Finish {
+            inflight: self,
+            key: &key,
+            call: &call,
+        };
+        let value = f();
+        *call.state.lock().unwrap() = State::Done(value.clone());
+        drop(finish);
+        value
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Default for Inflight<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Inflight;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Barrier;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn coalesce_same_key() {
+        let inflight: Inflight<&str, u32> = Inflight::new();
+        let count = AtomicUsize::new(0);
+        let barrier = Barrier::new(8);
+
+        let results: Vec<u32> = thread::scope(|s| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    s.spawn(|| {
+                        barrier.wait();
+                        inflight.run("nyamikan.net", || {
+                            count.fetch_add(1, Ordering::SeqCst);
+                            thread::sleep(Duration::from_millis(200));
+                            42
+                        })
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        assert!(results.iter().all(|&r| r == 42));
+    }
+
+    #[test]
+    fn separate_keys_and_sequential_calls() {
+        let inflight: Inflight<&str, u32> = Inflight::new();
+        let count = AtomicUsize::new(0);
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                inflight.run("a", || {
+                    count.fetch_add(1, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(100));
+                    1
+                })
+            });
+            s.spawn(|| {
+                inflight.run("b", || {
+                    count.fetch_add(1, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(100));
+                    2
+                })
+            });
+        });
+        // 完了した呼び出しの結果はキャッシュしない
+        assert_eq!(inflight.run("a", || 3), 3);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn waiters_retry_when_leader_panics() {
+        let inflight: Inflight<&str, u32> = Inflight::new();
+        let barrier = Barrier::new(2);
+
+        thread::scope(|s| {
+            let leader = s.spawn(|| {
+                inflight.run("a", || {
+                    barrier.wait();
+                    thread::sleep(Duration::from_millis(100));
+                    panic!("leader failed");
+                })
+            });
+            let waiter = s.spawn(|| {
+                barrier.wait();
+                inflight.run("a", || 7)
+            });
+            assert!(leader.join().is_err());
+            assert_eq!(waiter.join().unwrap(), 7);
+        });
+    }
+}
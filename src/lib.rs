@@ -0,0 +1,21 @@
+pub mod acl;
+pub mod address_selection;
+pub mod authoritative;
+pub mod cache;
+pub mod config;
+pub mod denial;
+pub mod dnssec;
+pub mod full_resolver;
+pub mod hosts;
+pub mod inflight;
+pub mod message;
+pub mod nsec_cache;
+pub mod rdata;
+pub mod recursive;
+pub mod resolv_conf;
+pub mod root_hints;
+pub mod rtt;
+pub mod server;
+pub mod trust_anchor;
+pub mod validator;
+pub mod zone_file;
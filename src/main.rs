@@ -1,79 +1,127 @@
-mod message;
-mod full_resolver;
+use rust_dns_resolver::acl::{AccessControl, Guarded};
+use rust_dns_resolver::authoritative::{Authority, Zone};
+use rust_dns_resolver::full_resolver;
+use rust_dns_resolver::recursive::Recursor;
+use rust_dns_resolver::server::{self, Handler};
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::process::ExitCode;
+use std::thread;
 
-use std::net::UdpSocket;
+const USAGE: &str = "usage: rust-dns-resolver [-x ADDRESS [--confirm]]
+       rust-dns-resolver --authoritative ADDRESS:PORT ORIGIN=ZONEFILE...
+       rust-dns-resolver --recursive ADDRESS:PORT [--acl ACLFILE]";
 
-use crate::message::Header;
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
 
-fn main() {
-    full_resolver::resolve("nyamikan.net", 16);
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        [] => {
+            started_resolver().resolve("nyamikan.net", 16);
+            ExitCode::SUCCESS
+        }
+        // -x: 逆引き。--confirm を付けると正引きで確かめられた名前だけを表示する
+        ["-x", address, options @ ..] if options.iter().all(|o| *o == "--confirm") => {
+            let address: IpAddr = match address.parse() {
+                Ok(address) => address,
+                Err(_) => {
+                    eprintln!("{:?} は IP アドレスではありません", address);
+                    return ExitCode::FAILURE;
+                }
+            };
+            let resolver = started_resolver();
+            let names = if options.is_empty() {
+                resolver.reverse_lookup(address)
+            } else {
+                resolver.reverse_lookup_confirmed(address)
+            };
+            match names {
+                Some(names) if !names.is_empty() => {
+                    for name in names {
+                        println!("{} PTR {}.", full_resolver::reverse_name(address), name);
+                    }
+                    ExitCode::SUCCESS
+                }
+                _ => {
+                    eprintln!("{} の逆引きは見つかりませんでした", address);
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        // --authoritative: ゾーンファイルを読み込み、権威サーバとして待ち受ける
+        ["--authoritative", address, zones @ ..] if !zones.is_empty() => {
+            let mut loaded = Vec::new();
+            for zone in zones {
+                let Some((origin, path)) = zone.split_once('=') else {
+                    eprintln!("{}", USAGE);
+                    return ExitCode::FAILURE;
+                };
+                match Zone::load(Path::new(path), origin) {
+                    Ok(zone) => loaded.push(zone),
+                    Err(e) => {
+                        eprintln!("ゾーン {} を読み込めませんでした: {}", origin, e);
+                        return ExitCode::FAILURE;
+                    }
+                }
+            }
+            listen(address, Authority::new(loaded))
+        }
+        // --recursive: 反復問い合わせで解決するフルリゾルバとして待ち受ける。
+        // オープンリゾルバにならないよう、--acl がなければループバックのクライアントにだけ答える
+        ["--recursive", address, options @ ..] => {
+            let access_control = match options {
+                [] => AccessControl::localhost(),
+                ["--acl", path] => match AccessControl::load(Path::new(path)) {
+                    Ok(access_control) => access_control,
+                    Err(e) => {
+                        eprintln!("ACL を読み込めませんでした: {}", e);
+                        return ExitCode::FAILURE;
+                    }
+                },
+                _ => {
+                    eprintln!("{}", USAGE);
+                    return ExitCode::FAILURE;
+                }
+            };
+            listen(
+                address,
+                Guarded::new(access_control, Recursor::new(started_resolver())),
+            )
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            ExitCode::FAILURE
+        }
+    }
 }
 
-/*
-    // Request
-    let message = message::Message {
-        header: message::Header::create(
-            // 1234, 0b1, 0b0101, 0b0, 0b1, 0b1, 0b1, 0b010, 0b1110, 65531, 255, 54,
-            // 2091,
-            1000, 0b0, 0b0000, 0b0, 0b0, 0b1, 0b0, 0b000, 0b0000, 1, 0, 0, 0,
-        ),
-        question: message::Question::new("nyamikan.net", 2, 1),
-    };
-    print_header(&message.header);
-
-    // Send
-    let socket = UdpSocket::bind("0.0.0.0:12345").expect("Couldn't bind to address");
-    let buffer = message.to_bytes();
-    let buffer_slice = buffer.as_slice();
-    socket
-        .send_to(&buffer_slice, "192.168.12.1:53")
-        .expect("Couldn't send");
-
-    // Receive
-    let mut buf = [0; 512];
-    let (number_of_bytes, src_addr) = socket.recv_from(&mut buf).expect("Didn't receive data");
-    println!(
-        "number_of_bytes: {}, src_addr: {:?}",
-        number_of_bytes, src_addr
-    );
-    println!("{:?}", buf);
-
-    // Response
-    let mut ret_header: [u8; 12] = Default::default();
-    ret_header.copy_from_slice(&buf[0..12]);
-    let ret_header = message::Header::parse(&ret_header);
-    print_header(&ret_header);
-
-    let ret_body = &buf[12..512];
-    let questions = message::Question::parse(&ret_body, ret_header.qd_count.into());
-    println!("{:?}", questions.0);
-
-    let ret_body = &buf[(questions.1 + 12)..512];
-    let resources = message::Resource::parse(
-        &buf,
-        ret_body,
-        (ret_header.an_count + ret_header.ns_count + ret_header.ar_count).into(),
-    );
-
-    println!("{:?}", resources);
+/** ルートサーバの一覧をプライミングで得てから使うリゾルバ */
+fn started_resolver() -> full_resolver::Resolver {
+    let resolver = full_resolver::Resolver::new();
+    resolver.prime();
+    resolver
 }
 
-fn print_header(header: &message::Header) {
-    println!(
-        "ID: {}, QR: {}, OPCODE: {}, AA: {}, TC: {}, RD: {}, RA: {}, Z: {}, RCODE: {}, QDCOUNT: {}, ANCOUNT: {}, NSCOUNT: {}, ARCOUNT: {}",
-        header.id,
-        header.qr(),
-        header.opcode(),
-        header.aa(),
-        header.tc(),
-        header.rd(),
-        header.ra(),
-        header.z(),
-        header.rcode(),
-        header.qd_count,
-        header.an_count,
-        header.ns_count,
-        header.ar_count,
-    );
+/** address で UDP と TCP の問い合わせを待ち受け、handler で答え続ける */
+fn listen(address: &str, handler: impl Handler) -> ExitCode {
+    let address: SocketAddr = match address.parse() {
+        Ok(address) => address,
+        Err(_) => {
+            eprintln!("{:?} はソケットアドレスではありません", address);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(e) = server::spawn(address, handler) {
+        eprintln!("{:?} で待ち受けられませんでした: {}", address, e);
+        return ExitCode::FAILURE;
+    }
+    loop {
+        thread::park();
+    }
 }
-*/
\ No newline at end of file
@@ -1,20 +1,201 @@
+use data_encoding::HEXUPPER;
 use packed_struct::prelude::*;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::dnssec::{Dnskey, Ds, Nsec, Nsec3, Nsec3param, Rrsig};
+use crate::rdata::{Caa, Hinfo, Naptr, Openpgpkey, Srv, Sshfp, Svcb, Tlsa, Txt};
 
 pub struct Message {
     pub header: Header,
     pub question: Question,
+    pub answers: Vec<Resource>,
+    pub authorities: Vec<Resource>,
+    pub additionals: Vec<Resource>,
+    /** 付加情報部の OPT レコード (EDNS0) */
+    pub edns: Option<Edns>,
 }
 
 impl Message {
+    pub fn new(header: Header, question: Question) -> Self {
+        Self {
+            header,
+            question,
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
+        }
+    }
+
+    /**
+     * 各セクションの件数はヘッダに設定済みであること (OPT レコードは付加情報部の最後に書き出す)。
+     * QDCOUNT が 0 なら質問部は書き出さない
+     */
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut vec = Vec::new();
         vec.extend(self.header.to_byte());
-        vec.extend(self.question.to_byte());
+        if self.header.qd_count > 0 {
+            vec.extend(self.question.to_byte());
+        }
+        for resource in self
+            .answers
+            .iter()
+            .chain(&self.authorities)
+            .chain(&self.additionals)
+        {
+            vec.extend(resource.to_byte());
+        }
+        if let Some(edns) = &self.edns {
+            vec.extend(edns.to_byte());
+        }
         vec
     }
+
+    /** 受信したメッセージ全体を解析し、各セクションに振り分ける。短すぎるものや壊れたものは None */
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        let header_bytes: [u8; 12] = buf.get(0..12)?.try_into().ok()?;
+        let header = Header::parse(&header_bytes);
+
+        let body = &buf[12..];
+        let (mut questions, question_length) = Question::parse(body, header.qd_count.into())?;
+        let question = if questions.is_empty() {
+            Question::default()
+        } else {
+            questions.swap_remove(0)
+        };
+
+        let body = &buf[(question_length + 12)..];
+        let an_count = usize::from(header.an_count);
+        let ns_count = usize::from(header.ns_count);
+        let mut resources = Resource::parse(
+            buf,
+            body,
+            an_count + ns_count + usize::from(header.ar_count),
+        )?;
+        let mut additionals = resources.split_off(an_count + ns_count);
+        let authorities = resources.split_off(an_count);
+
+        let edns = additionals
+            .iter()
+            .position(|r| r.rr_type == 41)
+            .map(|index| Edns::from_resource(&additionals.remove(index)));
+
+        Some(Self {
+            header,
+            question,
+            answers: resources,
+            authorities,
+            additionals,
+            edns,
+        })
+    }
+}
+
+/** dig と同じ体裁で、ヘッダと各セクションを表示する */
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let header = &self.header;
+        let opcode = match OPCODE_NAMES.get(usize::from(header.opcode())) {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => format!("OPCODE{}", header.opcode()),
+        };
+        writeln!(
+            f,
+            ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+            opcode,
+            rcode_name(header.rcode()),
+            header.id
+        )?;
+        // Z の下位 2 ビットは AD と CD (RFC 4035 Section 3.2)
+        let flags: Vec<&str> = [
+            (header.qr(), "qr"),
+            (header.aa(), "aa"),
+            (header.tc(), "tc"),
+            (header.rd(), "rd"),
+            (header.ra(), "ra"),
+            (header.z() & 0b010, "ad"),
+            (header.z() & 0b001, "cd"),
+        ]
+        .iter()
+        .filter(|(bit, _)| *bit != 0)
+        .map(|(_, name)| *name)
+        .collect();
+        writeln!(
+            f,
+            ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+            flags.join(" "),
+            header.qd_count,
+            header.an_count,
+            header.ns_count,
+            header.ar_count
+        )?;
+
+        if let Some(edns) = &self.edns {
+            writeln!(f, "\n;; OPT PSEUDOSECTION:")?;
+            writeln!(
+                f,
+                "; EDNS: version: 0, flags:{}; udp: {}",
+                if edns.dnssec_ok { " do" } else { "" },
+                edns.udp_payload_size
+            )?;
+        }
+        if header.qd_count > 0 {
+            writeln!(f, "\n;; QUESTION SECTION:\n;{}", self.question)?;
+        }
+        for (title, section) in [
+            ("ANSWER", &self.answers),
+            ("AUTHORITY", &self.authorities),
+            ("ADDITIONAL", &self.additionals),
+        ] {
+            if section.is_empty() {
+                continue;
+            }
+            writeln!(f, "\n;; {} SECTION:", title)?;
+            for resource in section {
+                writeln!(f, "{}", resource)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/** EDNS0 (RFC 6891) の OPT 疑似レコード */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edns {
+    pub udp_payload_size: u16,
+    /** DO ビット (RFC 3225)。DNSSEC のレコードを応答に含めてもらう */
+    pub dnssec_ok: bool,
 }
 
-#[derive(PackedStruct)]
+impl Edns {
+    pub fn new(udp_payload_size: u16) -> Self {
+        Self {
+            udp_payload_size,
+            dnssec_ok: false,
+        }
+    }
+
+    fn from_resource(resource: &Resource) -> Self {
+        Self {
+            udp_payload_size: resource.data_class,
+            dnssec_ok: resource.ttl & 0x8000 != 0,
+        }
+    }
+
+    pub fn to_byte(&self) -> [u8; 11] {
+        // NAME はルート、TYPE は 41 (OPT)、CLASS に UDP ペイロードサイズ、TTL は DO ビットのみ、RDLENGTH は 0
+        let mut bytes: [u8; 11] = [0, 0, 41, 0, 0, 0, 0, 0, 0, 0, 0];
+        bytes[3] = (self.udp_payload_size / 256) as u8;
+        bytes[4] = (self.udp_payload_size % 256) as u8;
+        if self.dnssec_ok {
+            bytes[7] = 0x80;
+        }
+        bytes
+    }
+}
+
+#[derive(PackedStruct, Clone, Copy, Debug)]
 #[packed_struct(bit_numbering = "msb0", endian = "msb")]
 pub struct Header {
     #[packed_field]
@@ -31,6 +212,12 @@ pub struct Header {
     pub ar_count: u16,
 }
 
+impl Default for Header {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Header {
     pub fn new() -> Self {
         Self {
@@ -43,6 +230,7 @@ impl Header {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn create(
         id: u16,
         qr: u8,
@@ -59,7 +247,7 @@ impl Header {
         ar_count: u16,
     ) -> Self {
         Self {
-            id: id,
+            id,
             flags: ((qr.wrapping_shl(7)
                 + opcode.wrapping_shl(3)
                 + aa.wrapping_shl(2)
@@ -67,10 +255,10 @@ impl Header {
                 + rd) as u16)
                 .wrapping_shl(8)
                 + (ra.wrapping_shl(7) + z.wrapping_shl(4) + rcode) as u16,
-            qd_count: qd_count,
-            an_count: an_count,
-            ns_count: ns_count,
-            ar_count: ar_count,
+            qd_count,
+            an_count,
+            ns_count,
+            ar_count,
         }
     }
 
@@ -125,12 +313,11 @@ impl Header {
     }
 
     pub fn parse(bytes: &[u8; 12]) -> Self {
-        let unpacked = Header::unpack(bytes).expect("Unpack error");
-        unpacked
+        Header::unpack(bytes).expect("Unpack error")
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 pub struct Question {
     pub qname: Vec<u8>,
     pub qname_dec: String,
@@ -139,20 +326,16 @@ pub struct Question {
 }
 
 impl Question {
-    pub fn new(fqdn: &str, qtype: u16, qclass: u16) -> Self {
-        let mut qname = Vec::new();
-        for word in fqdn.split('.') {
-            qname.push(word.len() as u8);
-            qname.extend(word.as_bytes());
-        }
-        qname.push(0 as u8);
+    /** fqdn は常に絶対名として扱う (末尾のドットはあってもなくてもよい)。ワイヤ形式にできないほど長い名前は None */
+    pub fn new(fqdn: &str, qtype: u16, qclass: u16) -> Option<Self> {
+        let fqdn = fqdn.strip_suffix('.').unwrap_or(fqdn);
 
-        Self {
-            qname: qname,
-            qname_dec: "".to_string(),
-            qtype: qtype, // 1: A, 5: CNAME, 28: AAAA
-            qclass: qclass,
-        }
+        Some(Self {
+            qname: encode_name(fqdn)?,
+            qname_dec: fqdn.to_string(),
+            qtype, // 1: A, 5: CNAME, 28: AAAA
+            qclass,
+        })
     }
 
     pub fn to_byte(&self) -> Vec<u8> {
@@ -165,48 +348,48 @@ impl Question {
         bytes
     }
 
-    pub fn parse(resources: &[u8], count: usize) -> (Vec<Self>, usize) {
+    /** 壊れた名前や、途中で終わっているものは None */
+    pub fn parse(resources: &[u8], count: usize) -> Option<(Vec<Self>, usize)> {
         let mut selfs = Vec::new();
 
         let mut position = 0;
-        loop {
+        while selfs.len() < count {
             // NAME
-            let name_pair = Resource::extract_name(resources, resources, position);
-            let name = name_pair.0;
-            // let mut name_vec = Vec::new();
-            // name_vec.extend(name.into_bytes());
-            position = name_pair.1;
-            // TYPE
-            let qtype = u16::from(resources[position]) * 256 + u16::from(resources[position + 1]);
-            position += 2;
-            // CLASS
-            let class = u16::from(resources[position]) * 256 + u16::from(resources[position + 1]);
-            position += 2;
+            let (name, next) = read_name(resources, resources, position)?;
+            position = next;
+            // TYPE, CLASS
+            let fields = resources.get(position..position + 4)?;
+            let qtype = u16::from(fields[0]) * 256 + u16::from(fields[1]);
+            let class = u16::from(fields[2]) * 256 + u16::from(fields[3]);
+            position += 4;
 
             let resource = Self {
-                qname: Vec::new(),
+                qname: encode_name(&name)?,
                 qname_dec: name,
-                qtype: qtype,
+                qtype,
                 qclass: class,
             };
             selfs.push(resource);
-
-            if selfs.len() >= count {
-                break;
-            }
         }
 
-        (selfs, position)
+        Some((selfs, position))
     }
 }
 
-#[derive(Debug)]
-pub enum IpAddr {
-    V4(String),
-    V6(String),
+/** マスターファイル形式の "名前 クラス タイプ" */
+impl fmt::Display for Question {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            presentation_name(&self.qname_dec),
+            class_name(self.qclass),
+            type_name(self.qtype)
+        )
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 pub struct Resource {
     pub name: String,
     pub rr_type: u16,
@@ -215,196 +398,684 @@ pub struct Resource {
     pub rdlength: u16,
     pub rdata: Vec<u8>,
 
-    pub address: IpAddr,  // A, AAAA
-    pub cname: String,    // CNAME
-    pub nsdname: String,  // NS
-    pub preference: u16,  // MX
-    pub exchange: String, // MX
-    pub mname: String,    // SOA
-    pub rname: String,    // SOA
-    pub serial: u32,      // SOA
-    pub refresh: u32,     // SOA
-    pub retry: u32,       // SOA
-    pub expire: u32,      // SOA
-    pub minimum: u32,     // SOA
-    pub txt_data: String, // TXT
+    pub address: Option<IpAddr>,        // A, AAAA
+    pub cname: String,                  // CNAME
+    pub nsdname: String,                // NS
+    pub ptrdname: String,               // PTR
+    pub preference: u16,                // MX
+    pub exchange: String,               // MX
+    pub mname: String,                  // SOA
+    pub rname: String,                  // SOA
+    pub serial: u32,                    // SOA
+    pub refresh: u32,                   // SOA
+    pub retry: u32,                     // SOA
+    pub expire: u32,                    // SOA
+    pub minimum: u32,                   // SOA
+    pub hinfo: Option<Hinfo>,           // HINFO
+    pub txt: Option<Txt>,               // TXT
+    pub srv: Option<Srv>,               // SRV
+    pub naptr: Option<Naptr>,           // NAPTR
+    pub sshfp: Option<Sshfp>,           // SSHFP
+    pub tlsa: Option<Tlsa>,             // TLSA
+    pub openpgpkey: Option<Openpgpkey>, // OPENPGPKEY
+    pub svcb: Option<Svcb>,             // SVCB, HTTPS
+    pub caa: Option<Caa>,               // CAA
+
+    pub ds: Option<Ds>,                 // DS
+    pub rrsig: Option<Rrsig>,           // RRSIG
+    pub nsec: Option<Nsec>,             // NSEC
+    pub dnskey: Option<Dnskey>,         // DNSKEY
+    pub nsec3: Option<Nsec3>,           // NSEC3
+    pub nsec3param: Option<Nsec3param>, // NSEC3PARAM
 }
 
 impl Resource {
-    /** Message: メッセージ圧縮での参照に必要 */
-    pub fn parse(message: &[u8], resources: &[u8], count: usize) -> Vec<Self> {
+    /** Message: メッセージ圧縮での参照に必要。壊れた名前や、RDATA が途中で終わっているものは None */
+    pub fn parse(message: &[u8], resources: &[u8], count: usize) -> Option<Vec<Self>> {
         let mut selfs = Vec::new();
 
         let mut position = 0;
-        loop {
+        while selfs.len() < count {
             // NAME
-            let name_pair = Resource::extract_name(message, resources, position);
-            let name = name_pair.0;
-            position = name_pair.1;
-            // TYPE
-            let rr_type = u16::from(resources[position]) * 256 + u16::from(resources[position + 1]);
-            position += 2;
-            // CLASS
-            let class = u16::from(resources[position]) * 256 + u16::from(resources[position + 1]);
-            position += 2;
-            // TTL
-            let ttl = u32::from(resources[position]) * 256 * 256 * 256
-                + u32::from(resources[position + 1]) * 256 * 256
-                + u32::from(resources[position + 2]) * 256
-                + u32::from(resources[position + 3]);
-            position += 4;
-            // RDLENGTH
-            let rdlength =
-                u16::from(resources[position]) * 256 + u16::from(resources[position + 1]);
-            position += 2;
+            let (name, next) = read_name(message, resources, position)?;
+            position = next;
+            // TYPE, CLASS, TTL, RDLENGTH
+            let fields = resources.get(position..position + 10)?;
+            let rr_type = u16::from(fields[0]) * 256 + u16::from(fields[1]);
+            let class = u16::from(fields[2]) * 256 + u16::from(fields[3]);
+            let ttl = u32::from(fields[4]) * 256 * 256 * 256
+                + u32::from(fields[5]) * 256 * 256
+                + u32::from(fields[6]) * 256
+                + u32::from(fields[7]);
+            let rdlength = u16::from(fields[8]) * 256 + u16::from(fields[9]);
+            position += 10;
             // RDATA
-            let mut rdata: Vec<u8> = Vec::new();
-            let begin = position;
-            let end = position + usize::from(rdlength);
-            rdata.extend(resources[begin..end].into_iter());
+            let rdata = resources
+                .get(position..position + usize::from(rdlength))?
+                .to_vec();
             position += usize::from(rdlength);
 
             // タイプ別のフィールド
-            let mut cname = "".to_string();
-            if rr_type == 5 {
-                let cname_tuple = Resource::extract_name(message, rdata.as_slice(), 0);
-                cname = cname_tuple.0;
-            }
-
-            let mut nsdname = "".to_string();
-            if rr_type == 2 {
-                let nsdname_tuple = Resource::extract_name(message, rdata.as_slice(), 0);
-                nsdname = nsdname_tuple.0;
-            }
+            let name_at = |offset: usize| -> Option<String> {
+                read_name(message, &rdata, offset).map(|(name, _)| name)
+            };
+            let cname = if rr_type == 5 {
+                name_at(0)?
+            } else {
+                String::new()
+            };
+            let nsdname = if rr_type == 2 {
+                name_at(0)?
+            } else {
+                String::new()
+            };
+            let ptrdname = if rr_type == 12 {
+                name_at(0)?
+            } else {
+                String::new()
+            };
 
-            let mut address = IpAddr::V4("".to_string());
-            if rr_type == 1 && rdata.len() == 4 {
-                address = IpAddr::V4(format!(
-                    "{}.{}.{}.{}",
-                    rdata[0], rdata[1], rdata[2], rdata[3]
-                ));
+            let mut address = None;
+            if rr_type == 1 {
+                if let Ok(octets) = <[u8; 4]>::try_from(rdata.as_slice()) {
+                    address = Some(IpAddr::V4(Ipv4Addr::from(octets)));
+                }
             }
-            if rr_type == 28 && rdata.len() == 16 {
-                address = IpAddr::V6(format!(
-                    "{:x}:{:x}:{:x}:{:x}:{:x}:{:x}:{:x}:{:x}",
-                    u16::from(rdata[0]) * 256 + u16::from(rdata[1]),
-                    u16::from(rdata[2]) * 256 + u16::from(rdata[3]),
-                    u16::from(rdata[4]) * 256 + u16::from(rdata[5]),
-                    u16::from(rdata[6]) * 256 + u16::from(rdata[7]),
-                    u16::from(rdata[8]) * 256 + u16::from(rdata[9]),
-                    u16::from(rdata[10]) * 256 + u16::from(rdata[11]),
-                    u16::from(rdata[12]) * 256 + u16::from(rdata[13]),
-                    u16::from(rdata[14]) * 256 + u16::from(rdata[15]),
-                ));
+            if rr_type == 28 {
+                if let Ok(octets) = <[u8; 16]>::try_from(rdata.as_slice()) {
+                    address = Some(IpAddr::V6(Ipv6Addr::from(octets)));
+                }
             }
 
             let mut preference: u16 = 0;
-            let mut exchange = "".to_string();
+            let mut exchange = String::new();
             if rr_type == 15 {
-                preference = u16::from(rdata[0]) * 256 + u16::from(rdata[1]);
-                let exchange_tuple = Resource::extract_name(message, rdata.as_slice(), 2);
-                exchange = exchange_tuple.0;
+                let bytes = rdata.get(0..2)?;
+                preference = u16::from(bytes[0]) * 256 + u16::from(bytes[1]);
+                exchange = name_at(2)?;
             }
 
-            let mut mname = "".to_string();
-            let mut rname = "".to_string();
+            let mut mname = String::new();
+            let mut rname = String::new();
             let mut serial: u32 = 0;
             let mut refresh: u32 = 0;
             let mut retry: u32 = 0;
             let mut expire: u32 = 0;
             let mut minimum: u32 = 0;
             if rr_type == 6 {
-                let mname_tuple = Resource::extract_name(message, rdata.as_slice(), 0);
-                mname = mname_tuple.0;
-                let rname_tuple = Resource::extract_name(message, rdata.as_slice(), mname_tuple.1);
-                rname = rname_tuple.0;
-                let offset = rname_tuple.1;
-                let mut v = (0..5).map(|i| {
-                    u32::from(rdata[offset + i * 4 + 0]) * 256 * 256 * 256
-                        + u32::from(rdata[offset + i * 4 + 1]) * 256 * 256
-                        + u32::from(rdata[offset + i * 4 + 2]) * 256
-                        + u32::from(rdata[offset + i * 4 + 3])
-                });
-                serial = v.next().unwrap();
-                refresh = v.next().unwrap();
-                retry = v.next().unwrap();
-                expire = v.next().unwrap();
-                minimum = v.next().unwrap();
+                let (name, offset) = read_name(message, &rdata, 0)?;
+                mname = name;
+                let (name, offset) = read_name(message, &rdata, offset)?;
+                rname = name;
+                let mut v = rdata
+                    .get(offset..offset + 20)?
+                    .chunks_exact(4)
+                    .map(|bytes| {
+                        u32::from(bytes[0]) * 256 * 256 * 256
+                            + u32::from(bytes[1]) * 256 * 256
+                            + u32::from(bytes[2]) * 256
+                            + u32::from(bytes[3])
+                    });
+                serial = v.next()?;
+                refresh = v.next()?;
+                retry = v.next()?;
+                expire = v.next()?;
+                minimum = v.next()?;
             }
 
-            let mut txt_data = "".to_string();
-            if rr_type == 16 {
-                let end = usize::from(rdata[0]) + 1;
-                txt_data = String::from_utf8_lossy(&rdata[1..end]).into_owned();
-            }
+            let txt = if rr_type == 16 {
+                Txt::parse(&rdata)
+            } else {
+                None
+            };
 
-            let resource = Self {
-                name: name,
-                rr_type: rr_type,
+            let hinfo = if rr_type == 13 {
+                Hinfo::parse(&rdata)
+            } else {
+                None
+            };
+            let srv = if rr_type == 33 {
+                Srv::parse(message, &rdata)
+            } else {
+                None
+            };
+            let naptr = if rr_type == 35 {
+                Naptr::parse(message, &rdata)
+            } else {
+                None
+            };
+            let sshfp = if rr_type == 44 {
+                Sshfp::parse(&rdata)
+            } else {
+                None
+            };
+            let tlsa = if rr_type == 52 {
+                Tlsa::parse(&rdata)
+            } else {
+                None
+            };
+            let openpgpkey = if rr_type == 61 {
+                Openpgpkey::parse(&rdata)
+            } else {
+                None
+            };
+            let svcb = if rr_type == 64 || rr_type == 65 {
+                Svcb::parse(&rdata)
+            } else {
+                None
+            };
+            let caa = if rr_type == 257 {
+                Caa::parse(&rdata)
+            } else {
+                None
+            };
+            let ds = if rr_type == 43 {
+                Ds::parse(&rdata)
+            } else {
+                None
+            };
+            let rrsig = if rr_type == 46 {
+                Rrsig::parse(&rdata)
+            } else {
+                None
+            };
+            let nsec = if rr_type == 47 {
+                Nsec::parse(&rdata)
+            } else {
+                None
+            };
+            let dnskey = if rr_type == 48 {
+                Dnskey::parse(&rdata)
+            } else {
+                None
+            };
+            let nsec3 = if rr_type == 50 {
+                Nsec3::parse(&rdata)
+            } else {
+                None
+            };
+            let nsec3param = if rr_type == 51 {
+                Nsec3param::parse(&rdata)
+            } else {
+                None
+            };
+
+            let mut resource = Self {
+                name,
+                rr_type,
                 data_class: class,
-                ttl: ttl,
-                rdlength: rdlength,
-                rdata: rdata,
-                cname: cname,
-                nsdname: nsdname,
-                address: address,
-                preference: preference,
-                exchange: exchange,
-                mname: mname,
-                rname: rname,
-                serial: serial,
-                refresh: refresh,
-                retry: retry,
-                expire: expire,
-                minimum: minimum,
-                txt_data: txt_data,
+                ttl,
+                rdlength,
+                rdata,
+                cname,
+                nsdname,
+                ptrdname,
+                address,
+                preference,
+                exchange,
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                hinfo,
+                txt,
+                srv,
+                naptr,
+                sshfp,
+                tlsa,
+                openpgpkey,
+                svcb,
+                caa,
+                ds,
+                rrsig,
+                nsec,
+                dnskey,
+                nsec3,
+                nsec3param,
             };
+            // 圧縮された名前はメッセージの外では読めないので、展開した形で持ち直す
+            if let Some(uncompressed) = resource.rdata_with_names(false) {
+                resource.rdlength = uncompressed.len() as u16;
+                resource.rdata = uncompressed;
+            }
             selfs.push(resource);
+        }
+
+        Some(selfs)
+    }
+
+    /** RDATA のマスターファイル形式。知らないタイプや解析できなかったものは RFC 3597 の \# 形式にする */
+    pub fn presentation_rdata(&self) -> String {
+        let text = match self.rr_type {
+            1 | 28 => self.address.map(|address| address.to_string()),
+            2 => Some(presentation_name(&self.nsdname)),
+            5 => Some(presentation_name(&self.cname)),
+            12 => Some(presentation_name(&self.ptrdname)),
+            6 => Some(format!(
+                "{} {} {} {} {} {} {}",
+                presentation_name(&self.mname),
+                presentation_name(&self.rname),
+                self.serial,
+                self.refresh,
+                self.retry,
+                self.expire,
+                self.minimum
+            )),
+            13 => self.hinfo.as_ref().map(|hinfo| hinfo.to_string()),
+            15 => Some(format!(
+                "{} {}",
+                self.preference,
+                presentation_name(&self.exchange)
+            )),
+            16 => self.txt.as_ref().map(|txt| txt.to_string()),
+            33 => self.srv.as_ref().map(|srv| srv.to_string()),
+            35 => self.naptr.as_ref().map(|naptr| naptr.to_string()),
+            43 => self.ds.as_ref().map(|ds| ds.to_string()),
+            44 => self.sshfp.as_ref().map(|sshfp| sshfp.to_string()),
+            46 => self.rrsig.as_ref().map(|rrsig| rrsig.to_string()),
+            47 => self.nsec.as_ref().map(|nsec| nsec.to_string()),
+            48 => self.dnskey.as_ref().map(|dnskey| dnskey.to_string()),
+            50 => self.nsec3.as_ref().map(|nsec3| nsec3.to_string()),
+            51 => self.nsec3param.as_ref().map(|param| param.to_string()),
+            52 => self.tlsa.as_ref().map(|tlsa| tlsa.to_string()),
+            61 => self.openpgpkey.as_ref().map(|key| key.to_string()),
+            64 | 65 => self.svcb.as_ref().map(|svcb| svcb.to_string()),
+            257 => self.caa.as_ref().map(|caa| caa.to_string()),
+            _ => None,
+        };
+        text.unwrap_or_else(|| {
+            if self.rdata.is_empty() {
+                "\\# 0".to_string()
+            } else {
+                format!("\\# {} {}", self.rdata.len(), HEXUPPER.encode(&self.rdata))
+            }
+        })
+    }
+
+    /** 圧縮せずに書き出す (RDLENGTH は RDATA から計算する) */
+    pub fn to_byte(&self) -> Vec<u8> {
+        self.encode(&self.name, self.ttl, &self.rdata)
+    }
+
+    /** 署名の検証に使う正規形 (RFC 4034 Section 6.2)。TTL には RRSIG の Original TTL を使う */
+    pub fn to_canonical_byte(&self, original_ttl: u32) -> Vec<u8> {
+        self.encode(
+            &self.name.to_ascii_lowercase(),
+            original_ttl,
+            &self.canonical_rdata(),
+        )
+    }
+
+    /** 正規形の RDATA。RFC 4034 Section 6.2 に挙げられたタイプの名前を小文字にする */
+    pub fn canonical_rdata(&self) -> Vec<u8> {
+        if let Some(rrsig) = &self.rrsig {
+            let mut rdata = rrsig.to_rdata_without_signature();
+            rdata.extend(&rrsig.signature);
+            return rdata;
+        }
+        self.rdata_with_names(true)
+            .unwrap_or_else(|| self.rdata.clone())
+    }
 
-            if selfs.len() >= count {
-                break;
+    /** 名前を含むタイプの RDATA を解析済みのフィールドから組み立て直す */
+    fn rdata_with_names(&self, lowercase: bool) -> Option<Vec<u8>> {
+        let name = |name: &str| {
+            if lowercase {
+                encode_name(&name.to_ascii_lowercase())
+            } else {
+                encode_name(name)
+            }
+        };
+        let mut rdata = Vec::new();
+        match self.rr_type {
+            2 => rdata.extend(name(&self.nsdname)?),
+            5 => rdata.extend(name(&self.cname)?),
+            6 => {
+                rdata.extend(name(&self.mname)?);
+                rdata.extend(name(&self.rname)?);
+                for value in [
+                    self.serial,
+                    self.refresh,
+                    self.retry,
+                    self.expire,
+                    self.minimum,
+                ] {
+                    rdata.extend(value.to_be_bytes());
+                }
+            }
+            12 => rdata.extend(name(&self.ptrdname)?),
+            15 => {
+                rdata.extend(self.preference.to_be_bytes());
+                rdata.extend(name(&self.exchange)?);
             }
+            33 => {
+                let mut srv = self.srv.clone()?;
+                if lowercase {
+                    srv.target = srv.target.to_ascii_lowercase();
+                }
+                rdata.extend(srv.to_rdata());
+            }
+            35 => {
+                let mut naptr = self.naptr.clone()?;
+                if lowercase {
+                    naptr.replacement = naptr.replacement.to_ascii_lowercase();
+                }
+                rdata.extend(naptr.to_rdata());
+            }
+            _ => return None,
         }
+        Some(rdata)
+    }
+
+    fn encode(&self, name: &str, ttl: u32, rdata: &[u8]) -> Vec<u8> {
+        let mut bytes = encode_checked_name(name);
+        bytes.extend(self.rr_type.to_be_bytes());
+        bytes.extend(self.data_class.to_be_bytes());
+        bytes.extend(ttl.to_be_bytes());
+        bytes.extend((rdata.len() as u16).to_be_bytes());
+        bytes.extend(rdata);
+        bytes
+    }
+}
 
-        selfs
+/** マスターファイル形式の "名前 TTL クラス タイプ RDATA" (zone_file::parse_record で読み戻せる) */
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {}",
+            presentation_name(&self.name),
+            self.ttl,
+            class_name(self.data_class),
+            type_name(self.rr_type),
+            self.presentation_rdata()
+        )
     }
+}
 
-    /** メッセージ圧縮に対応した NAME の抽出 */
-    fn extract_name(message: &[u8], resources: &[u8], offset: usize) -> (String, usize) {
-        let mut position = usize::from(offset);
-        let mut name = Vec::new();
-        loop {
-            let length = resources[position];
+/**
+ * bytes の offset から名前を読み、名前と bytes での次の位置を返す。
+ * 圧縮ポインタは message の中をたどる。壊れた名前やポインタのループは None
+ */
+pub fn read_name(message: &[u8], bytes: &[u8], offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut data = bytes;
+    let mut position = offset;
+    let mut next = None;
+    let mut jumps = 0;
+    // 末尾のルートの 1 バイトを含めたワイヤ形式での長さ
+    let mut name_length = 1;
+    loop {
+        let length = *data.get(position)?;
+        if length == 0 {
             position += 1;
-            // 末尾である
-            if length == 0 {
-                break;
+            break;
+        }
+        match length & 0b11000000 {
+            0b11000000 => {
+                let pointer =
+                    usize::from(length & 0b00111111) * 256 + usize::from(*data.get(position + 1)?);
+                next.get_or_insert(position + 2);
+                // ポインタだけで名前の最大長 (255) を超えるほどたどるのはループ
+                jumps += 1;
+                if jumps > 127 {
+                    return None;
+                }
+                data = message;
+                position = pointer;
             }
-            // 圧縮である
-            if length & 0b11000000 == 0b11000000 {
-                let offset =
-                    usize::from(length & 0b00111111) * 256 + usize::from(resources[position]);
-                let reference_name = Resource::extract_name(message, message, offset);
-                name.push(reference_name.0);
-                position += 1;
-                break;
+            0b00000000 => {
+                let label = data.get(position + 1..position + 1 + usize::from(length))?;
+                name_length += 1 + label.len();
+                if name_length > MAX_NAME_LENGTH {
+                    return None;
+                }
+                labels.push(escape_label(label));
+                position += 1 + usize::from(length);
             }
-            // 通常のデータ
-            let begin = position;
-            let end = begin + usize::from(length);
-            let chars = &resources[begin..end];
-            let str = String::from_utf8_lossy(chars).into_owned();
-            name.push(str);
-            position += usize::from(length);
+            _ => return None,
         }
+    }
+    Some((labels.join("."), next.unwrap_or(position)))
+}
 
-        (itertools::join(name, "."), position)
+/**
+ * ワイヤ形式のラベルを、名前の文字列での表記にする。ラベルの中のドットと '\' は "\." と "\\"、
+ * 表示できないバイト (空白と ASCII 以外) は "\DDD" にする
+ */
+pub fn escape_label(label: &[u8]) -> String {
+    let mut escaped = String::new();
+    for &byte in label {
+        match byte {
+            b'.' | b'\\' => {
+                escaped.push('\\');
+                escaped.push(char::from(byte));
+            }
+            0x21..=0x7E => escaped.push(char::from(byte)),
+            _ => escaped.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+    escaped
+}
+
+/** 名前をエスケープされていないドットで区切り、\X と \DDD を解釈したラベルのバイト列にする (空のラベルは除く) */
+pub fn name_labels(name: &str) -> Vec<Vec<u8>> {
+    let chars: Vec<char> = name.chars().collect();
+    let mut labels = vec![Vec::new()];
+    let mut index = 0;
+    while index < chars.len() {
+        let label = labels.last_mut().unwrap();
+        match chars[index] {
+            '.' => labels.push(Vec::new()),
+            '\\' => {
+                let digits: String = chars.iter().skip(index + 1).take(3).collect();
+                match digits.parse::<u8>() {
+                    Ok(byte) if digits.len() == 3 && digits.bytes().all(|b| b.is_ascii_digit()) => {
+                        label.push(byte);
+                        index += 3;
+                    }
+                    _ => match chars.get(index + 1) {
+                        Some(c) => {
+                            label.extend(c.to_string().as_bytes());
+                            index += 1;
+                        }
+                        None => label.push(b'\\'),
+                    },
+                }
+            }
+            c => label.extend(c.to_string().as_bytes()),
+        }
+        index += 1;
+    }
+    labels.retain(|label| !label.is_empty());
+    labels
+}
+
+/** 名前のラベルの数。エスケープされたドット ("\.") はラベルを区切らない */
+pub fn label_count(name: &str) -> usize {
+    name_labels(name).len()
+}
+
+/** name が zone と同じか、その下にあるか (どちらも小文字)。ラベルごとに比べるので、ラベルの中のドットでは区切らない */
+pub fn is_subdomain(name: &str, zone: &str) -> bool {
+    let name = name_labels(name);
+    let zone = name_labels(zone);
+    name.ends_with(&zone)
+}
+
+/** name の末尾 count 個のラベル */
+pub fn last_labels(name: &str, count: usize) -> String {
+    let labels = name_labels(name);
+    labels[labels.len().saturating_sub(count)..]
+        .iter()
+        .map(|label| escape_label(label))
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+/** bytes の position から始まるビッグエンディアンの 16 ビット整数 */
+pub fn read_u16(bytes: &[u8], position: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        bytes.get(position..position + 2)?.try_into().ok()?,
+    ))
+}
+
+/**
+ * 名前 (末尾のドットはあってもなくてもよい) を圧縮せずにワイヤ形式にする。
+ * 63 バイトを超えるラベルや、全体で 255 バイトを超える名前 (RFC 1035 Section 2.3.4) は None
+ */
+pub fn encode_name(name: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    // ルート (空文字列) はラベルを持たない
+    for label in name_labels(name) {
+        if label.len() > MAX_LABEL_LENGTH {
+            return None;
+        }
+        bytes.push(label.len() as u8);
+        bytes.extend(label);
+    }
+    bytes.push(0);
+    if bytes.len() > MAX_NAME_LENGTH {
+        return None;
     }
+    Some(bytes)
+}
+
+/**
+ * 長さを確かめた名前 (read_name で読んだもの、ゾーンファイルのもの、Question::new を通ったもの) をワイヤ形式にする。
+ * レコードやその RDATA を書き出すときに使う
+ */
+pub fn encode_checked_name(name: &str) -> Vec<u8> {
+    encode_name(name).expect("名前の長さは読み込んだときに確かめている")
+}
+
+/**
+ * マスターファイル形式の絶対名 (ルートは ".")。ラベルの中のドットなどは名前の文字列ですでにエスケープされているので、
+ * そのほかの区切りになる文字と表示できないバイトをエスケープする
+ */
+pub fn presentation_name(name: &str) -> String {
+    if name.is_empty() {
+        return ".".to_string();
+    }
+    let mut presentation = String::new();
+    let mut bytes = name.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'\\' => match bytes.next() {
+                Some(next @ 0x21..=0x7E) => {
+                    presentation.push('\\');
+                    presentation.push(char::from(next));
+                }
+                Some(next) => presentation.push_str(&format!("\\{:03}", next)),
+                None => presentation.push_str("\\\\"),
+            },
+            b'"' | b'(' | b')' | b';' | b'@' | b'$' => {
+                presentation.push('\\');
+                presentation.push(char::from(byte));
+            }
+            0x21..=0x7E => presentation.push(char::from(byte)),
+            _ => presentation.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+    presentation.push('.');
+    presentation
+}
+
+/** ラベルと名前のワイヤ形式での最大長 (RFC 1035 Section 2.3.4) */
+const MAX_LABEL_LENGTH: usize = 63;
+const MAX_NAME_LENGTH: usize = 255;
+
+const TYPE_NAMES: [(u16, &str); 25] = [
+    (1, "A"),
+    (2, "NS"),
+    (5, "CNAME"),
+    (6, "SOA"),
+    (12, "PTR"),
+    (13, "HINFO"),
+    (15, "MX"),
+    (16, "TXT"),
+    (28, "AAAA"),
+    (33, "SRV"),
+    (35, "NAPTR"),
+    (41, "OPT"),
+    (43, "DS"),
+    (44, "SSHFP"),
+    (46, "RRSIG"),
+    (47, "NSEC"),
+    (48, "DNSKEY"),
+    (50, "NSEC3"),
+    (51, "NSEC3PARAM"),
+    (52, "TLSA"),
+    (61, "OPENPGPKEY"),
+    (64, "SVCB"),
+    (65, "HTTPS"),
+    (255, "ANY"),
+    (257, "CAA"),
+];
+
+/** タイプのニーモニック。知らないタイプは RFC 3597 の TYPEnnn 形式にする */
+pub fn type_name(rr_type: u16) -> String {
+    TYPE_NAMES
+        .iter()
+        .find(|(value, _)| *value == rr_type)
+        .map(|(_, name)| name.to_string())
+        .unwrap_or_else(|| format!("TYPE{}", rr_type))
+}
+
+pub fn type_from_name(name: &str) -> Option<u16> {
+    let name = name.to_ascii_uppercase();
+    TYPE_NAMES
+        .iter()
+        .find(|(_, mnemonic)| *mnemonic == name)
+        .map(|(value, _)| *value)
+        .or_else(|| name.strip_prefix("TYPE")?.parse().ok())
+}
+
+const CLASS_NAMES: [(u16, &str); 4] = [(1, "IN"), (2, "CS"), (3, "CH"), (4, "HS")];
+
+/** クラスのニーモニック。知らないクラスは RFC 3597 の CLASSnnn 形式にする */
+pub fn class_name(class: u16) -> String {
+    CLASS_NAMES
+        .iter()
+        .find(|(value, _)| *value == class)
+        .map(|(_, name)| name.to_string())
+        .unwrap_or_else(|| format!("CLASS{}", class))
+}
+
+pub fn class_from_name(name: &str) -> Option<u16> {
+    let name = name.to_ascii_uppercase();
+    CLASS_NAMES
+        .iter()
+        .find(|(_, mnemonic)| *mnemonic == name)
+        .map(|(value, _)| *value)
+        .or_else(|| name.strip_prefix("CLASS")?.parse().ok())
+}
+
+const OPCODE_NAMES: [&str; 6] = ["QUERY", "IQUERY", "STATUS", "", "NOTIFY", "UPDATE"];
+
+const RCODE_NAMES: [&str; 11] = [
+    "NOERROR", "FORMERR", "SERVFAIL", "NXDOMAIN", "NOTIMP", "REFUSED", "YXDOMAIN", "YXRRSET",
+    "NXRRSET", "NOTAUTH", "NOTZONE",
+];
+
+/** RCODE のニーモニック (知らないものは数字のまま) */
+pub fn rcode_name(rcode: u8) -> String {
+    RCODE_NAMES
+        .get(usize::from(rcode))
+        .map(|name| name.to_string())
+        .unwrap_or_else(|| format!("RCODE{}", rcode))
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Header, Question};
+    use super::{
+        encode_name, is_subdomain, label_count, last_labels, presentation_name, read_name,
+        type_from_name, type_name, Edns, Header, Message, Question, Resource,
+    };
+    use std::net::{IpAddr, Ipv4Addr};
 
     #[test]
     fn header_bytes() {
@@ -439,7 +1110,7 @@ mod tests {
 
     #[test]
     fn question_bytes() {
-        let question = Question::new("nyamikan.net", 2, 1);
+        let question = Question::new("nyamikan.net", 2, 1).unwrap();
 
         let actual = question.to_byte();
         let expect = vec![
@@ -448,6 +1119,20 @@ mod tests {
         assert_eq!(expect, actual);
     }
 
+    #[test]
+    fn question_bytes_absolute_and_root() {
+        let absolute = Question::new("nyamikan.net.", 2, 1).unwrap();
+        assert_eq!(
+            absolute.to_byte(),
+            Question::new("nyamikan.net", 2, 1).unwrap().to_byte()
+        );
+        assert_eq!(absolute.qname_dec, "nyamikan.net");
+
+        let root = Question::new(".", 2, 1).unwrap();
+        assert_eq!(root.to_byte(), vec![0, 0, 2, 0, 1]);
+        assert_eq!(Question::new("", 2, 1).unwrap().to_byte(), root.to_byte());
+    }
+
     #[test]
     fn question_parse() {
         let question = vec![
@@ -455,11 +1140,285 @@ mod tests {
             0x74, 0, 0, 2, 0, 1,
         ];
 
-        let parsed_questions = Question::parse(&question, 1);
+        let parsed_questions = Question::parse(&question, 1).unwrap();
         assert_eq!(parsed_questions.0.len(), 1);
         let parsed_question = &parsed_questions.0[0];
         assert_eq!(parsed_question.qname_dec, "www.nyamikan.net");
         assert_eq!(parsed_question.qtype, 2);
         assert_eq!(parsed_question.qclass, 1);
     }
+
+    #[test]
+    fn message_edns_roundtrip() {
+        let mut message = Message::new(
+            Header::create(255, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 1),
+            Question::new("nyamikan.net", 1, 1).unwrap(),
+        );
+        message.edns = Some(Edns::new(1232));
+
+        let bytes = message.to_bytes();
+        assert_eq!(
+            &bytes[bytes.len() - 11..],
+            &[0, 0, 41, 4, 208, 0, 0, 0, 0, 0, 0]
+        );
+
+        let parsed = Message::parse(&bytes).unwrap();
+        assert_eq!(parsed.question.qname_dec, "nyamikan.net");
+        assert_eq!(parsed.edns, Some(Edns::new(1232)));
+        assert!(parsed.additionals.is_empty());
+
+        message.edns = Some(Edns {
+            dnssec_ok: true,
+            ..Edns::new(1232)
+        });
+        let bytes = message.to_bytes();
+        assert_eq!(&bytes[bytes.len() - 4..], &[0x80, 0, 0, 0]);
+        assert!(Message::parse(&bytes).unwrap().edns.unwrap().dnssec_ok);
+    }
+
+    #[test]
+    fn resource_roundtrip_and_canonical_form() {
+        // 応答に含まれる MX の交換ホスト名は所有者名への圧縮ポインタになっている
+        let mut bytes = Message::new(
+            Header::create(1, 1, 0, 1, 0, 0, 0, 0, 0, 1, 1, 0, 0),
+            Question::new("Example.COM", 15, 1).unwrap(),
+        )
+        .to_bytes();
+        bytes.extend([0xC0, 12, 0, 15, 0, 1, 0, 0, 0x0E, 0x10, 0, 9, 0, 10]);
+        bytes.extend([4, b'M', b'a', b'i', b'L', 0xC0, 12]);
+
+        let message = Message::parse(&bytes).unwrap();
+        let mx = &message.answers[0];
+        assert_eq!(mx.exchange, "MaiL.Example.COM");
+        assert_eq!(usize::from(mx.rdlength), mx.rdata.len());
+
+        // 書き出すときは圧縮せず、大文字小文字もそのまま
+        let reparsed = Message::parse(&message.to_bytes()).unwrap();
+        assert_eq!(reparsed.answers[0].exchange, "MaiL.Example.COM");
+        assert_eq!(reparsed.answers[0].to_byte(), mx.to_byte());
+
+        let canonical = mx.to_canonical_byte(300);
+        assert_eq!(&canonical[..13], b"\x07example\x03com\x00");
+        assert_eq!(&canonical[17..21], &300u32.to_be_bytes());
+        assert_eq!(&canonical[25..], b"\x04mail\x07example\x03com\x00");
+        assert_eq!(
+            Resource::default().canonical_rdata(),
+            Resource::default().rdata
+        );
+    }
+
+    #[test]
+    fn txt_keeps_every_character_string() {
+        // 255 バイトを超える DKIM の鍵のように、複数の <character-string> に分かれた TXT
+        let value = format!("v=DKIM1; k=rsa; p={}", "A".repeat(300));
+        let mut bytes = Message::new(
+            Header::create(1, 1, 0, 1, 0, 0, 0, 0, 0, 1, 1, 0, 0),
+            Question::new("sel._domainkey.example.com", 16, 1).unwrap(),
+        )
+        .to_bytes();
+        let rdata = crate::rdata::Txt::from_bytes(value.as_bytes()).to_rdata();
+        bytes.extend([0xC0, 12, 0, 16, 0, 1, 0, 0, 0x0E, 0x10]);
+        bytes.extend((rdata.len() as u16).to_be_bytes());
+        bytes.extend(&rdata);
+
+        let message = Message::parse(&bytes).unwrap();
+        let txt = message.answers[0].txt.as_ref().unwrap();
+        assert_eq!(txt.strings.len(), 2);
+        assert_eq!(txt.text(), value);
+    }
+
+    #[test]
+    fn srv_and_ptr_are_decompressed_and_canonicalised() {
+        let mut bytes = Message::new(
+            Header::create(1, 1, 0, 1, 0, 0, 0, 0, 0, 1, 2, 0, 0),
+            Question::new("_ldap._tcp.Example.COM", 33, 1).unwrap(),
+        )
+        .to_bytes();
+        // 優先度 0、重み 0、ポート 389 の DC1.Example.COM (圧縮されている)
+        bytes.extend([
+            0xC0, 12, 0, 33, 0, 1, 0, 0, 0x0E, 0x10, 0, 12, 0, 0, 0, 0, 1, 0x85,
+        ]);
+        bytes.extend([3, b'D', b'C', b'1', 0xC0, 23]);
+        bytes.extend([0xC0, 12, 0, 12, 0, 1, 0, 0, 0x0E, 0x10, 0, 6]);
+        bytes.extend([3, b'd', b'c', b'2', 0xC0, 23]);
+
+        let message = Message::parse(&bytes).unwrap();
+        let srv = message.answers[0].srv.as_ref().unwrap();
+        assert_eq!((srv.port, srv.target.as_str()), (389, "DC1.Example.COM"));
+        assert_eq!(message.answers[1].ptrdname, "dc2.Example.COM");
+        assert_eq!(usize::from(message.answers[0].rdlength), 6 + 17);
+
+        let canonical = message.answers[0].canonical_rdata();
+        assert_eq!(&canonical[6..], b"\x03dc1\x07example\x03com\x00");
+        assert_eq!(
+            message.answers[1].canonical_rdata(),
+            b"\x03dc2\x07example\x03com\x00"
+        );
+        assert_eq!(type_from_name("naptr"), Some(35));
+        assert_eq!(type_name(257), "CAA");
+    }
+
+    #[test]
+    fn addresses_are_parsed_into_ip_types() {
+        let mut bytes = Message::new(
+            Header::create(1, 1, 0, 1, 0, 0, 0, 0, 0, 1, 3, 0, 0),
+            Question::new("www.example.com", 28, 1).unwrap(),
+        )
+        .to_bytes();
+        bytes.extend([0xC0, 12, 0, 1, 0, 1, 0, 0, 0x0E, 0x10, 0, 4, 192, 0, 2, 1]);
+        bytes.extend([0xC0, 12, 0, 28, 0, 1, 0, 0, 0x0E, 0x10, 0, 16]);
+        bytes.extend([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        // RDLENGTH が合わない A はアドレスとして扱わない
+        bytes.extend([0xC0, 12, 0, 1, 0, 1, 0, 0, 0x0E, 0x10, 0, 3, 192, 0, 2]);
+
+        let message = Message::parse(&bytes).unwrap();
+        let addresses: Vec<Option<IpAddr>> = message.answers.iter().map(|r| r.address).collect();
+        assert_eq!(
+            addresses,
+            vec![
+                Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))),
+                Some("2001:db8::1".parse().unwrap()),
+                None
+            ]
+        );
+        assert_eq!(
+            message.answers[1].address.unwrap().to_string(),
+            "2001:db8::1"
+        );
+    }
+
+    #[test]
+    fn over_long_labels_and_names_are_rejected() {
+        let label = |c: &str, length: usize| c.repeat(length);
+        assert_eq!(encode_name(&label("a", 63)).unwrap().len(), 65);
+        assert!(encode_name(&label("a", 64)).is_none());
+        assert!(Question::new(&[&label("a", 64), "example"].join("."), 1, 1).is_none());
+
+        // 全体で 255 バイトちょうどまで
+        let longest = [
+            label("a", 63),
+            label("b", 63),
+            label("c", 63),
+            label("d", 61),
+        ]
+        .join(".");
+        assert_eq!(encode_name(&longest).unwrap().len(), 255);
+        assert!(encode_name(&[&longest, "e"].join(".")).is_none());
+
+        let mut wire = encode_name(&longest).unwrap();
+        wire.pop();
+        wire.extend([1, b'e', 0]);
+        assert!(read_name(&wire, &wire, 0).is_none());
+    }
+
+    #[test]
+    fn malformed_messages_are_rejected() {
+        let response = |an_count: u16, ns_count: u16, records: &[u8]| {
+            let mut bytes = Message::new(
+                Header::create(1, 1, 0, 1, 0, 0, 0, 0, 0, 1, an_count, ns_count, 0),
+                Question::new("example.com", 15, 1).unwrap(),
+            )
+            .to_bytes();
+            bytes.extend(records);
+            bytes
+        };
+        // 自分自身を指す圧縮ポインタ
+        assert!(
+            Message::parse(&response(1, 0, &[0xC0, 29, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0])).is_none()
+        );
+        // RDATA が短すぎる MX と SOA
+        assert!(Message::parse(&response(
+            1,
+            0,
+            &[0xC0, 12, 0, 15, 0, 1, 0, 0, 0, 0, 0, 1, 10]
+        ))
+        .is_none());
+        assert!(Message::parse(&response(
+            1,
+            0,
+            &[0xC0, 12, 0, 6, 0, 1, 0, 0, 0, 0, 0, 4, 0, 0, 0, 1]
+        ))
+        .is_none());
+        // RDLENGTH がメッセージの外まで続く
+        assert!(Message::parse(&response(
+            1,
+            0,
+            &[0xC0, 12, 0, 1, 0, 1, 0, 0, 0, 0, 0, 9, 192]
+        ))
+        .is_none());
+        // ANCOUNT と NSCOUNT の和が u16 を超える
+        assert!(Message::parse(&response(u16::MAX, 1, &[])).is_none());
+        assert!(Message::parse(&[0; 11]).is_none());
+        assert!(Message::parse(&response(0, 0, &[])).is_some());
+    }
+
+    #[test]
+    fn read_name_rejects_loops() {
+        let message = [0xC0, 2, 0xC0, 0];
+        assert_eq!(read_name(&message, &message, 0), None);
+        assert_eq!(read_name(&[], &[3, b'w', b'w'], 0), None);
+        assert_eq!(
+            read_name(&[], &[1, b'a', 0, 9], 0),
+            Some(("a".to_string(), 3))
+        );
+    }
+
+    #[test]
+    fn labels_with_dots_and_binary_bytes_are_escaped() {
+        let wire = [
+            5, b'a', b'.', b'b', 0xC3, b' ', 1, b'\\', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e',
+            0,
+        ];
+        let (name, _) = read_name(&wire, &wire, 0).unwrap();
+        assert_eq!(name, "a\\.b\\195\\032.\\\\.example");
+        assert_eq!(encode_name(&name).unwrap(), wire);
+        assert_eq!(presentation_name(&name), "a\\.b\\195\\032.\\\\.example.");
+        assert_eq!(presentation_name("a;b"), "a\\;b.");
+        assert_eq!(label_count(&name), 3);
+        assert_eq!(label_count(""), 0);
+        assert_eq!(last_labels(&name, 2), "\\\\.example");
+        assert_eq!(last_labels("a\\.b.example", 5), "a\\.b.example");
+        assert!(is_subdomain("a\\.b.example", "example"));
+        assert!(!is_subdomain("a\\.b.example", "b.example"));
+        assert!(is_subdomain("www.example", ""));
+        assert_eq!(encode_name("www.example."), encode_name("www.example"));
+    }
+
+    #[test]
+    fn type_mnemonics() {
+        assert_eq!(type_name(48), "DNSKEY");
+        assert_eq!(type_name(65280), "TYPE65280");
+        assert_eq!(type_from_name("nsec3param"), Some(51));
+        assert_eq!(type_from_name("TYPE65280"), Some(65280));
+        assert_eq!(type_from_name("BOGUS"), None);
+    }
+
+    #[test]
+    fn dig_style_presentation() {
+        let mut message = Message::new(
+            Header::create(4660, 1, 0, 0, 0, 1, 1, 0b010, 3, 1, 1, 0, 1),
+            Question::new("www.example.com", 1, 1).unwrap(),
+        );
+        message
+            .answers
+            .push(crate::zone_file::parse_record("www.example.com. 3600 IN A 192.0.2.1").unwrap());
+        message.edns = Some(Edns {
+            udp_payload_size: 1232,
+            dnssec_ok: true,
+        });
+        assert_eq!(
+            message.to_string(),
+            ";; ->>HEADER<<- opcode: QUERY, status: NXDOMAIN, id: 4660\n\
+             ;; flags: qr rd ra ad; QUERY: 1, ANSWER: 1, AUTHORITY: 0, ADDITIONAL: 1\n\
+             \n\
+             ;; OPT PSEUDOSECTION:\n\
+             ; EDNS: version: 0, flags: do; udp: 1232\n\
+             \n\
+             ;; QUESTION SECTION:\n\
+             ;www.example.com. IN A\n\
+             \n\
+             ;; ANSWER SECTION:\n\
+             www.example.com. 3600 IN A 192.0.2.1\n"
+        );
+    }
 }
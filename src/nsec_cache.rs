@@ -0,0 +1,354 @@
+use data_encoding::BASE32HEX_NOPAD;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::denial::{covers, nsec3_hash, Denial, MAX_NSEC3_ITERATIONS};
+use crate::message::{is_subdomain, label_count, last_labels, Header, Message, Question, Resource};
+use crate::validator::Security;
+
+/** ゾーンごとに保持するレコードの上限。超えたら期限の近いものから捨てる */
+const MAX_RECORDS_PER_ZONE: usize = 4096;
+
+/**
+ * DNSSEC で検証済みの SOA, NSEC, NSEC3 とその RRSIG をゾーンごとに保持し、
+ * それらが示す範囲の名前には、権威サーバに問い合わせずに否定応答を合成する (RFC 8198)
+ */
+pub struct NsecCache {
+    /** ゾーン (小文字・末尾のドットなし) ごとのレコード */
+    zones: Mutex<HashMap<String, Vec<Entry>>>,
+}
+
+struct Entry {
+    resource: Resource,
+    /** 有効期限 (validator::now と同じ UNIX 時刻) */
+    expires: u32,
+}
+
+impl NsecCache {
+    pub fn new() -> Self {
+        Self {
+            zones: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /** ゾーン zone の鍵で署名を検証済みの authorities から、SOA, NSEC, NSEC3 とその RRSIG を取り込む */
+    pub fn insert(&self, zone: &str, authorities: &[Resource], now: u32) {
+        let zone = zone.to_ascii_lowercase();
+        let mut zones = self.zones.lock().unwrap();
+        let entries = zones.entry(zone.clone()).or_default();
+
+        // 否定応答をキャッシュしてよい時間 (RFC 2308 Section 5) より長くは使わない (RFC 8198 Section 5.4)
+        let negative_ttl = authorities
+            .iter()
+            .find(|r| r.rr_type == 6 && r.name.eq_ignore_ascii_case(&zone))
+            .or_else(|| {
+                entries
+                    .iter()
+                    .map(|entry| &entry.resource)
+                    .find(|r| r.rr_type == 6)
+            })
+            .map(|soa| soa.ttl.min(soa.minimum));
+        for resource in authorities.iter().filter(|r| {
+            matches!(covered_type(r), 6 | 47 | 50)
+                && is_subdomain(&r.name.to_ascii_lowercase(), &zone)
+                // 繰り返しが多すぎる NSEC3 からは合成しないので、保持もしない
+                && r.nsec3.as_ref().is_none_or(|nsec3| nsec3.iterations <= MAX_NSEC3_ITERATIONS)
+        }) {
+            let ttl = negative_ttl.map_or(resource.ttl, |ttl| ttl.min(resource.ttl));
+            if ttl == 0 {
+                continue;
+            }
+            entries.retain(|entry| identity(&entry.resource) != identity(resource));
+            entries.push(Entry {
+                resource: resource.clone(),
+                expires: now.saturating_add(ttl),
+            });
+        }
+
+        if entries.len() > MAX_RECORDS_PER_ZONE {
+            entries.sort_by_key(|entry| Reverse(entry.expires));
+            entries.truncate(MAX_RECORDS_PER_ZONE);
+        }
+    }
+
+    /** qname がないこと、または qtype のレコードがないことを保持しているレコードで示せれば、その否定応答を作る */
+    pub fn synthesise(&self, qname: &str, qtype: u16, now: u32) -> Option<Message> {
+        let qname = qname.trim_end_matches('.').to_ascii_lowercase();
+        let mut zones = self.zones.lock().unwrap();
+        // qname を含む最も深いゾーン。DS は委任点の親ゾーンにある
+        let zone = zones
+            .keys()
+            .filter(|zone| is_subdomain(&qname, zone) && !(qtype == 43 && qname == **zone))
+            .max_by_key(|zone| label_count(zone))?
+            .clone();
+        let entries = zones.get_mut(&zone)?;
+        entries.retain(|entry| entry.expires > now);
+
+        // 残りの有効期間を TTL とする
+        let resources: Vec<Resource> = entries
+            .iter()
+            .map(|entry| Resource {
+                ttl: entry.expires - now,
+                ..entry.resource.clone()
+            })
+            .collect();
+        let soa: Vec<&Resource> = resources
+            .iter()
+            .filter(|r| covered_type(r) == 6 && r.name.eq_ignore_ascii_case(&zone))
+            .collect();
+        if soa.is_empty() {
+            return None;
+        }
+
+        // 証明に関係しうる NSEC / NSEC3 だけを権威部に入れる
+        let names = proof_names(&qname, &zone);
+        let proof: Vec<Resource> = resources
+            .iter()
+            .filter(|r| r.rr_type == 47 || r.rr_type == 50)
+            .filter(|r| is_relevant(r, &zone, &names))
+            .cloned()
+            .collect();
+        let denial = Denial::new(&zone, &proof);
+        // Opt-Out の範囲などで Insecure になるものからは合成しない (RFC 8198 Section 5.1)
+        let rcode = if denial.nxdomain(&qname) == Security::Secure {
+            3
+        } else if denial.nodata(&qname, qtype) == Security::Secure {
+            0
+        } else {
+            return None;
+        };
+
+        let mut authorities: Vec<Resource> = soa.into_iter().cloned().collect();
+        for record in &proof {
+            let rrset: Vec<Resource> = resources
+                .iter()
+                .filter(|r| {
+                    covered_type(r) == record.rr_type && r.name.eq_ignore_ascii_case(&record.name)
+                })
+                .filter(|r| !authorities.iter().any(|a| identity(a) == identity(r)))
+                .cloned()
+                .collect();
+            authorities.extend(rrset);
+        }
+        authorities.sort_by_key(|r| covered_type(r) != 6);
+
+        let header = Header::create(
+            0,
+            0b1,
+            0b0000,
+            0b0,
+            0b0,
+            0b1,
+            0b1,
+            0b000,
+            rcode,
+            0x0001,
+            0x0000,
+            authorities.len() as u16,
+            0x0000,
+        );
+        let mut response = Message::new(header, Question::new(&qname, qtype, 0x0001)?);
+        response.authorities = authorities;
+        Some(response)
+    }
+}
+
+impl Default for NsecCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/** RRSIG なら署名の対象のタイプ、それ以外はレコードのタイプ */
+fn covered_type(resource: &Resource) -> u16 {
+    match &resource.rrsig {
+        Some(rrsig) if resource.rr_type == 46 => rrsig.type_covered,
+        _ => resource.rr_type,
+    }
+}
+
+/** 新しいレコードで置き換える単位。RRSIG は署名した鍵ごとに分ける */
+fn identity(resource: &Resource) -> (String, u16, u16, u16) {
+    (
+        resource.name.to_ascii_lowercase(),
+        resource.rr_type,
+        covered_type(resource),
+        resource.rrsig.as_ref().map_or(0, |rrsig| rrsig.key_tag),
+    )
+}
+
+/** 不在の証明に使われうる名前: ゾーンの頂点から qname までの祖先と、その直下のワイルドカード */
+fn proof_names(qname: &str, zone: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    for count in label_count(zone)..=label_count(qname) {
+        let ancestor = last_labels(qname, count);
+        names.push(if ancestor.is_empty() {
+            "*".to_string()
+        } else {
+            ["*", ancestor.as_str()].join(".")
+        });
+        names.push(ancestor);
+    }
+    names
+}
+
+/** NSEC / NSEC3 が names のいずれかに一致するか、それを覆うか */
+fn is_relevant(resource: &Resource, zone: &str, names: &[String]) -> bool {
+    let owner = resource.name.to_ascii_lowercase();
+    if let Some(nsec) = &resource.nsec {
+        let next = nsec.next_domain_name.to_ascii_lowercase();
+        return names
+            .iter()
+            .any(|name| *name == owner || covers(&owner, &next, name));
+    }
+    if let Some(nsec3) = &resource.nsec3 {
+        // NSEC3 の所有者名は、ゾーンの頂点の直下にあるハッシュのラベル
+        let (label, parent) = owner.split_once('.').unwrap_or((&owner, ""));
+        let hash = match BASE32HEX_NOPAD.decode(label.to_ascii_uppercase().as_bytes()) {
+            Ok(hash) if parent == zone => hash,
+            _ => return false,
+        };
+        let next = &nsec3.next_hashed_owner;
+        return names.iter().any(|name| {
+            let target = nsec3_hash(name, &nsec3.salt, nsec3.iterations);
+            target == hash
+                || if hash < *next {
+                    hash < target && target < *next
+                } else {
+                    hash < target || target < *next
+                }
+        });
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NsecCache;
+    use crate::denial::testing::nsec3_chain;
+    use crate::dnssec::Nsec;
+    use crate::message::Resource;
+    use crate::validator::testing::{resource, SigningKey};
+
+    /** MNAME と RNAME は example、MINIMUM は minimum の SOA */
+    fn soa(minimum: u32) -> Resource {
+        let mut rdata = vec![7];
+        rdata.extend(b"example");
+        rdata.push(0);
+        rdata.extend(rdata.clone());
+        rdata.extend([0; 16]);
+        rdata.extend(minimum.to_be_bytes());
+        resource("example", 6, &rdata)
+    }
+
+    fn nsec(key: &SigningKey, owner: &str, next: &str, types: Vec<u16>) -> Vec<Resource> {
+        let nsec = Nsec {
+            next_domain_name: next.to_string(),
+            types,
+        };
+        let records = vec![resource(owner, 47, &nsec.to_rdata())];
+        vec![records[0].clone(), key.sign(&records)]
+    }
+
+    #[test]
+    fn synthesises_from_cached_nsec() {
+        let key = SigningKey::ed25519("example", 1);
+        let now = 1700000000;
+        let cache = NsecCache::new();
+        let mut authorities = vec![soa(300), key.sign(&[soa(300)])];
+        authorities.extend(nsec(&key, "example", "a.example", vec![2, 6, 46, 47, 48]));
+        authorities.extend(nsec(&key, "a.example", "m.example", vec![1, 46, 47]));
+        authorities.extend(nsec(&key, "m.example", "example", vec![1, 2, 46, 47]));
+        cache.insert("example", &authorities, now);
+
+        // b.example と *.example はどちらも a.example の NSEC が覆う
+        let response = cache.synthesise("b.example", 1, now + 10).unwrap();
+        assert_eq!(response.header.rcode(), 3);
+        assert_eq!(response.question.qname_dec, "b.example");
+        assert_eq!(response.authorities[0].rr_type, 6);
+        assert!(response.authorities.iter().all(|r| r.ttl == 290));
+        let owners: Vec<(&str, u16)> = response
+            .authorities
+            .iter()
+            .map(|r| (r.name.as_str(), r.rr_type))
+            .collect();
+        assert_eq!(
+            owners,
+            vec![
+                ("example", 6),
+                ("example", 46),
+                ("example", 47),
+                ("example", 46),
+                ("a.example", 47),
+                ("a.example", 46)
+            ]
+        );
+
+        let nodata = cache.synthesise("a.example", 28, now).unwrap();
+        assert_eq!(nodata.header.rcode(), 0);
+        assert!(nodata.answers.is_empty());
+
+        // 示せない問い合わせと、委任の先の名前は合成しない
+        assert!(cache.synthesise("a.example", 1, now).is_none());
+        assert!(cache.synthesise("www.m.example", 1, now).is_none());
+        assert!(cache.synthesise("www.other", 1, now).is_none());
+
+        // SOA の MINIMUM を過ぎたら使わない
+        assert!(cache.synthesise("b.example", 1, now + 300).is_none());
+    }
+
+    #[test]
+    fn requires_soa_and_positive_ttl() {
+        let key = SigningKey::ed25519("example", 1);
+        let now = 1700000000;
+        let cache = NsecCache::new();
+        let mut authorities = nsec(&key, "example", "a.example", vec![2, 6, 46, 47, 48]);
+        authorities.extend(nsec(&key, "a.example", "example", vec![1, 46, 47]));
+        cache.insert("example", &authorities, now);
+        assert!(cache.synthesise("b.example", 1, now).is_none());
+
+        // MINIMUM が 0 なら否定応答はキャッシュしない
+        let cache = NsecCache::new();
+        authorities.push(soa(0));
+        cache.insert("example", &authorities, now);
+        assert!(cache.synthesise("b.example", 1, now).is_none());
+        assert!(cache.zones.lock().unwrap()["example"].is_empty());
+    }
+
+    #[test]
+    fn synthesises_from_cached_nsec3() {
+        let now = 1700000000;
+        let names = [
+            ("example", vec![2, 6, 46, 48, 51]),
+            ("a.example", vec![1, 46]),
+            ("w.example", vec![]),
+            ("*.w.example", vec![16, 46]),
+        ];
+        let cache = NsecCache::new();
+        let mut authorities = vec![soa(300)];
+        authorities.extend(nsec3_chain("example", &names, 0));
+        cache.insert("example", &authorities, now);
+
+        let response = cache.synthesise("b.example", 1, now).unwrap();
+        assert_eq!(response.header.rcode(), 3);
+        // 最も近い祖先 example の一致、b.example と *.example を覆うもの
+        assert!(response.authorities.len() <= 4);
+        assert_eq!(
+            cache
+                .synthesise("y.w.example", 1, now)
+                .unwrap()
+                .header
+                .rcode(),
+            0
+        );
+        assert!(cache.synthesise("y.w.example", 16, now).is_none());
+
+        // Opt-Out の範囲からは合成しない
+        let cache = NsecCache::new();
+        let mut authorities = vec![soa(300)];
+        authorities.extend(nsec3_chain("example", &names, 1));
+        cache.insert("example", &authorities, now);
+        assert!(cache.synthesise("b.example", 1, now).is_none());
+    }
+}
@@ -0,0 +1,1005 @@
+use data_encoding::{BASE64, HEXUPPER};
+use ring::digest;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::message::{encode_checked_name, presentation_name, read_name, read_u16};
+
+/** TXT (RFC 1035 Section 3.3.14)。1 つ以上の <character-string> を持つ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Txt {
+    /** 長さのオクテットを除いた各 <character-string> (それぞれ 255 バイト以下) */
+    pub strings: Vec<Vec<u8>>,
+}
+
+impl Txt {
+    pub fn parse(rdata: &[u8]) -> Option<Self> {
+        let mut strings = Vec::new();
+        let mut position = 0;
+        while position < rdata.len() {
+            let (string, next) = read_character_string(rdata, position)?;
+            strings.push(string);
+            position = next;
+        }
+        if strings.is_empty() {
+            return None;
+        }
+        Some(Self { strings })
+    }
+
+    /** data を 255 バイトごとの <character-string> に分ける */
+    pub fn from_bytes(data: &[u8]) -> Self {
+        let strings = if data.is_empty() {
+            vec![Vec::new()]
+        } else {
+            data.chunks(255).map(|chunk| chunk.to_vec()).collect()
+        };
+        Self { strings }
+    }
+
+    pub fn to_rdata(&self) -> Vec<u8> {
+        let mut rdata = Vec::new();
+        for string in &self.strings {
+            write_character_string(&mut rdata, string);
+        }
+        rdata
+    }
+
+    /** すべての <character-string> をつなげたもの (SPF や DKIM はこれを 1 つの値として読む) */
+    pub fn concat(&self) -> Vec<u8> {
+        self.strings.concat()
+    }
+
+    /** concat を UTF-8 として読んだ文字列 (不正なバイトは置き換える) */
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.concat()).into_owned()
+    }
+}
+
+impl fmt::Display for Txt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, string) in self.strings.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            f.write_str(&quote(string))?;
+        }
+        Ok(())
+    }
+}
+
+/** HINFO (RFC 1035 Section 3.3.2)。ANY への最小限の応答 (RFC 8482) にも使われる */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hinfo {
+    pub cpu: Vec<u8>,
+    pub os: Vec<u8>,
+}
+
+impl Hinfo {
+    pub fn parse(rdata: &[u8]) -> Option<Self> {
+        let (cpu, position) = read_character_string(rdata, 0)?;
+        let (os, _) = read_character_string(rdata, position)?;
+        Some(Self { cpu, os })
+    }
+
+    pub fn to_rdata(&self) -> Vec<u8> {
+        let mut rdata = Vec::new();
+        write_character_string(&mut rdata, &self.cpu);
+        write_character_string(&mut rdata, &self.os);
+        rdata
+    }
+}
+
+impl fmt::Display for Hinfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", quote(&self.cpu), quote(&self.os))
+    }
+}
+
+/** SRV (RFC 2782)。TARGET は圧縮しないが、受け取ったものは展開する (RFC 3597 Section 4) */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Srv {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+}
+
+impl Srv {
+    /** message: 圧縮ポインタの参照先 */
+    pub fn parse(message: &[u8], rdata: &[u8]) -> Option<Self> {
+        Some(Self {
+            priority: read_u16(rdata, 0)?,
+            weight: read_u16(rdata, 2)?,
+            port: read_u16(rdata, 4)?,
+            target: read_name(message, rdata, 6)?.0,
+        })
+    }
+
+    pub fn to_rdata(&self) -> Vec<u8> {
+        let mut rdata = Vec::new();
+        rdata.extend(self.priority.to_be_bytes());
+        rdata.extend(self.weight.to_be_bytes());
+        rdata.extend(self.port.to_be_bytes());
+        rdata.extend(encode_checked_name(&self.target));
+        rdata
+    }
+}
+
+impl fmt::Display for Srv {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.priority,
+            self.weight,
+            self.port,
+            presentation_name(&self.target)
+        )
+    }
+}
+
+/** NAPTR (RFC 3403 Section 4.1)。REPLACEMENT は SRV と同じく圧縮しない */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Naptr {
+    pub order: u16,
+    pub preference: u16,
+    pub flags: Vec<u8>,
+    pub services: Vec<u8>,
+    pub regexp: Vec<u8>,
+    pub replacement: String,
+}
+
+impl Naptr {
+    /** message: 圧縮ポインタの参照先 */
+    pub fn parse(message: &[u8], rdata: &[u8]) -> Option<Self> {
+        let (flags, position) = read_character_string(rdata, 4)?;
+        let (services, position) = read_character_string(rdata, position)?;
+        let (regexp, position) = read_character_string(rdata, position)?;
+        Some(Self {
+            order: read_u16(rdata, 0)?,
+            preference: read_u16(rdata, 2)?,
+            flags,
+            services,
+            regexp,
+            replacement: read_name(message, rdata, position)?.0,
+        })
+    }
+
+    pub fn to_rdata(&self) -> Vec<u8> {
+        let mut rdata = Vec::new();
+        rdata.extend(self.order.to_be_bytes());
+        rdata.extend(self.preference.to_be_bytes());
+        write_character_string(&mut rdata, &self.flags);
+        write_character_string(&mut rdata, &self.services);
+        write_character_string(&mut rdata, &self.regexp);
+        rdata.extend(encode_checked_name(&self.replacement));
+        rdata
+    }
+}
+
+impl fmt::Display for Naptr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {} {}",
+            self.order,
+            self.preference,
+            quote(&self.flags),
+            quote(&self.services),
+            quote(&self.regexp),
+            presentation_name(&self.replacement)
+        )
+    }
+}
+
+/** CAA (RFC 8659) */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caa {
+    pub flags: u8,
+    /** "issue", "issuewild", "iodef" など (英数字のみ) */
+    pub tag: String,
+    pub value: Vec<u8>,
+}
+
+impl Caa {
+    pub fn parse(rdata: &[u8]) -> Option<Self> {
+        let length = usize::from(*rdata.get(1)?);
+        let tag = rdata.get(2..2 + length)?;
+        if tag.is_empty() || !tag.iter().all(u8::is_ascii_alphanumeric) {
+            return None;
+        }
+        Some(Self {
+            flags: rdata[0],
+            tag: String::from_utf8_lossy(tag).into_owned(),
+            value: rdata[2 + length..].to_vec(),
+        })
+    }
+
+    pub fn to_rdata(&self) -> Vec<u8> {
+        let mut rdata = vec![self.flags, self.tag.len() as u8];
+        rdata.extend(self.tag.as_bytes());
+        rdata.extend(&self.value);
+        rdata
+    }
+
+    /** Issuer Critical フラグ (ビット 0)。知らないタグなら発行してはならない */
+    pub fn is_critical(&self) -> bool {
+        self.flags & 0x80 != 0
+    }
+}
+
+impl fmt::Display for Caa {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.flags, self.tag, quote(&self.value))
+    }
+}
+
+/** SSHFP (RFC 4255) */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sshfp {
+    /** 1: RSA, 2: DSA, 3: ECDSA, 4: Ed25519 */
+    pub algorithm: u8,
+    /** 1: SHA-1, 2: SHA-256 */
+    pub fingerprint_type: u8,
+    pub fingerprint: Vec<u8>,
+}
+
+impl Sshfp {
+    pub fn parse(rdata: &[u8]) -> Option<Self> {
+        Some(Self {
+            algorithm: *rdata.first()?,
+            fingerprint_type: *rdata.get(1)?,
+            fingerprint: rdata[2..].to_vec(),
+        })
+    }
+
+    pub fn to_rdata(&self) -> Vec<u8> {
+        let mut rdata = vec![self.algorithm, self.fingerprint_type];
+        rdata.extend(&self.fingerprint);
+        rdata
+    }
+}
+
+impl fmt::Display for Sshfp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.algorithm,
+            self.fingerprint_type,
+            HEXUPPER.encode(&self.fingerprint)
+        )
+    }
+}
+
+/** TLSA (RFC 6698) */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tlsa {
+    /** 0: PKIX-TA, 1: PKIX-EE, 2: DANE-TA, 3: DANE-EE */
+    pub usage: u8,
+    /** 0: 証明書全体, 1: SubjectPublicKeyInfo */
+    pub selector: u8,
+    /** 0: そのまま, 1: SHA-256, 2: SHA-512 */
+    pub matching_type: u8,
+    pub data: Vec<u8>,
+}
+
+impl Tlsa {
+    pub fn parse(rdata: &[u8]) -> Option<Self> {
+        Some(Self {
+            usage: *rdata.first()?,
+            selector: *rdata.get(1)?,
+            matching_type: *rdata.get(2)?,
+            data: rdata[3..].to_vec(),
+        })
+    }
+
+    pub fn to_rdata(&self) -> Vec<u8> {
+        let mut rdata = vec![self.usage, self.selector, self.matching_type];
+        rdata.extend(&self.data);
+        rdata
+    }
+
+    /** DER 形式の証明書が、selector と matching_type に従ってこのレコードに一致するか */
+    pub fn matches(&self, certificate: &[u8]) -> bool {
+        let selected = match self.selector {
+            0 => certificate,
+            1 => match spki(certificate) {
+                Some(spki) => spki,
+                None => return false,
+            },
+            _ => return false,
+        };
+        self.matches_selected(selected)
+    }
+
+    /** DER 形式の SubjectPublicKeyInfo が一致するか (selector が 1 のレコードだけ) */
+    pub fn matches_spki(&self, spki: &[u8]) -> bool {
+        self.selector == 1 && self.matches_selected(spki)
+    }
+
+    /** SubjectPublicKeyInfo の SHA-256 (32 バイト) か SHA-512 (64 バイト) のハッシュが一致するか */
+    pub fn matches_spki_digest(&self, digest: &[u8]) -> bool {
+        let matching_type = match digest.len() {
+            32 => 1,
+            64 => 2,
+            _ => return false,
+        };
+        self.selector == 1 && self.matching_type == matching_type && self.data == digest
+    }
+
+    fn matches_selected(&self, selected: &[u8]) -> bool {
+        let algorithm = match self.matching_type {
+            0 => return self.data == selected,
+            1 => &digest::SHA256,
+            2 => &digest::SHA512,
+            _ => return false,
+        };
+        self.data == digest::digest(algorithm, selected).as_ref()
+    }
+}
+
+impl fmt::Display for Tlsa {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.usage,
+            self.selector,
+            self.matching_type,
+            HEXUPPER.encode(&self.data)
+        )
+    }
+}
+
+/** OPENPGPKEY (RFC 7929)。RDATA は OpenPGP の Transferable Public Key そのもの */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Openpgpkey {
+    pub public_key: Vec<u8>,
+}
+
+impl Openpgpkey {
+    pub fn parse(rdata: &[u8]) -> Option<Self> {
+        if rdata.is_empty() {
+            return None;
+        }
+        Some(Self {
+            public_key: rdata.to_vec(),
+        })
+    }
+
+    pub fn to_rdata(&self) -> Vec<u8> {
+        self.public_key.clone()
+    }
+}
+
+impl fmt::Display for Openpgpkey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&BASE64.encode(&self.public_key))
+    }
+}
+
+/** X.509 証明書 (DER) の tbsCertificate から SubjectPublicKeyInfo を取り出す (RFC 5280 Section 4.1) */
+pub fn spki(certificate: &[u8]) -> Option<&[u8]> {
+    let (tag, start, _) = read_der(certificate, 0)?;
+    if tag != 0x30 {
+        return None;
+    }
+    let (tag, mut position, _) = read_der(certificate, start)?;
+    if tag != 0x30 {
+        return None;
+    }
+    // version は [0] で省略されることがある
+    if *certificate.get(position)? == 0xA0 {
+        position = read_der(certificate, position)?.2;
+    }
+    // serialNumber, signature, issuer, validity, subject を読み飛ばす
+    for _ in 0..5 {
+        position = read_der(certificate, position)?.2;
+    }
+    match read_der(certificate, position)? {
+        (0x30, _, end) => Some(&certificate[position..end]),
+        _ => None,
+    }
+}
+
+/** DER の TLV を読み、タグと値の開始位置、次の位置を返す */
+fn read_der(bytes: &[u8], position: usize) -> Option<(u8, usize, usize)> {
+    let tag = *bytes.get(position)?;
+    let first = *bytes.get(position + 1)?;
+    let (length, start) = if first < 0x80 {
+        (usize::from(first), position + 2)
+    } else {
+        // 長形式: 続く count バイトが長さ
+        let count = usize::from(first & 0x7F);
+        if count == 0 || count > 4 {
+            return None;
+        }
+        let length = bytes
+            .get(position + 2..position + 2 + count)?
+            .iter()
+            .fold(0, |length, byte| length * 256 + usize::from(*byte));
+        (length, position + 2 + count)
+    };
+    let end = start.checked_add(length)?;
+    if end > bytes.len() {
+        return None;
+    }
+    Some((tag, start, end))
+}
+
+/** SVCB と HTTPS (RFC 9460 Section 2.2) */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Svcb {
+    /** 0 は AliasMode、それ以外は ServiceMode の優先度 */
+    pub priority: u16,
+    /** 圧縮しない。ルート ("") は AliasMode では「サービスなし」、ServiceMode では所有者名を表す */
+    pub target: String,
+    /** キーの昇順 */
+    pub params: Vec<SvcParam>,
+}
+
+/** SvcParam (RFC 9460 Section 7) */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SvcParam {
+    /** 0: クライアントが必ず理解しなければならないキー */
+    Mandatory(Vec<u16>),
+    /** 1: ALPN のプロトコル ID */
+    Alpn(Vec<Vec<u8>>),
+    /** 2: 既定の ALPN (HTTPS では http/1.1) を使わない */
+    NoDefaultAlpn,
+    /** 3 */
+    Port(u16),
+    /** 4 */
+    Ipv4Hint(Vec<Ipv4Addr>),
+    /** 5: ECHConfigList */
+    Ech(Vec<u8>),
+    /** 6 */
+    Ipv6Hint(Vec<Ipv6Addr>),
+    /** 知らないキー */
+    Unknown(u16, Vec<u8>),
+}
+
+const SVC_PARAM_KEYS: [&str; 7] = [
+    "mandatory",
+    "alpn",
+    "no-default-alpn",
+    "port",
+    "ipv4hint",
+    "ech",
+    "ipv6hint",
+];
+
+impl Svcb {
+    pub fn parse(rdata: &[u8]) -> Option<Self> {
+        let priority = read_u16(rdata, 0)?;
+        // TargetName は圧縮されていないはずなので、ポインタは読まない
+        let (target, mut position) = read_name(&[], rdata, 2)?;
+        let mut params: Vec<SvcParam> = Vec::new();
+        while position < rdata.len() {
+            let key = read_u16(rdata, position)?;
+            let length = usize::from(read_u16(rdata, position + 2)?);
+            let value = rdata.get(position + 4..position + 4 + length)?;
+            // キーは重複せず昇順に並んでいなければならない
+            if params.last().is_some_and(|last| last.key() >= key) {
+                return None;
+            }
+            params.push(SvcParam::parse(key, value)?);
+            position += 4 + length;
+        }
+        Some(Self {
+            priority,
+            target,
+            params,
+        })
+    }
+
+    pub fn to_rdata(&self) -> Vec<u8> {
+        let mut rdata = Vec::new();
+        rdata.extend(self.priority.to_be_bytes());
+        rdata.extend(encode_checked_name(&self.target));
+        let mut params: Vec<&SvcParam> = self.params.iter().collect();
+        params.sort_by_key(|param| param.key());
+        for param in params {
+            let value = param.value();
+            rdata.extend(param.key().to_be_bytes());
+            rdata.extend((value.len() as u16).to_be_bytes());
+            rdata.extend(value);
+        }
+        rdata
+    }
+
+    pub fn is_alias(&self) -> bool {
+        self.priority == 0
+    }
+
+    pub fn param(&self, key: u16) -> Option<&SvcParam> {
+        self.params.iter().find(|param| param.key() == key)
+    }
+}
+
+impl fmt::Display for Svcb {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.priority, presentation_name(&self.target))?;
+        for param in &self.params {
+            write!(f, " {}", param)?;
+        }
+        Ok(())
+    }
+}
+
+impl SvcParam {
+    pub fn parse(key: u16, value: &[u8]) -> Option<Self> {
+        let param = match key {
+            0 => {
+                let keys: Option<Vec<u16>> = (0..value.len())
+                    .step_by(2)
+                    .map(|i| read_u16(value, i))
+                    .collect();
+                let keys = keys?;
+                // mandatory 自身は含めない
+                if keys.is_empty() || keys.contains(&0) {
+                    return None;
+                }
+                Self::Mandatory(keys)
+            }
+            1 => {
+                let mut ids = Vec::new();
+                let mut position = 0;
+                while position < value.len() {
+                    let (id, next) = read_character_string(value, position)?;
+                    if id.is_empty() {
+                        return None;
+                    }
+                    ids.push(id);
+                    position = next;
+                }
+                if ids.is_empty() {
+                    return None;
+                }
+                Self::Alpn(ids)
+            }
+            2 if value.is_empty() => Self::NoDefaultAlpn,
+            3 => Self::Port(read_u16(value, 0).filter(|_| value.len() == 2)?),
+            4 if !value.is_empty() && value.len().is_multiple_of(4) => Self::Ipv4Hint(
+                value
+                    .chunks(4)
+                    .map(|chunk| Ipv4Addr::from(<[u8; 4]>::try_from(chunk).unwrap()))
+                    .collect(),
+            ),
+            5 => Self::Ech(value.to_vec()),
+            6 if !value.is_empty() && value.len().is_multiple_of(16) => Self::Ipv6Hint(
+                value
+                    .chunks(16)
+                    .map(|chunk| Ipv6Addr::from(<[u8; 16]>::try_from(chunk).unwrap()))
+                    .collect(),
+            ),
+            2 | 4 | 6 => return None,
+            _ => Self::Unknown(key, value.to_vec()),
+        };
+        Some(param)
+    }
+
+    pub fn key(&self) -> u16 {
+        match self {
+            Self::Mandatory(_) => 0,
+            Self::Alpn(_) => 1,
+            Self::NoDefaultAlpn => 2,
+            Self::Port(_) => 3,
+            Self::Ipv4Hint(_) => 4,
+            Self::Ech(_) => 5,
+            Self::Ipv6Hint(_) => 6,
+            Self::Unknown(key, _) => *key,
+        }
+    }
+
+    /** ワイヤ形式の SvcParamValue */
+    pub fn value(&self) -> Vec<u8> {
+        let mut value = Vec::new();
+        match self {
+            Self::Mandatory(keys) => keys.iter().for_each(|key| value.extend(key.to_be_bytes())),
+            Self::Alpn(ids) => ids
+                .iter()
+                .for_each(|id| write_character_string(&mut value, id)),
+            Self::NoDefaultAlpn => {}
+            Self::Port(port) => value.extend(port.to_be_bytes()),
+            Self::Ipv4Hint(addresses) => addresses
+                .iter()
+                .for_each(|address| value.extend(address.octets())),
+            Self::Ech(config) => value.extend(config),
+            Self::Ipv6Hint(addresses) => addresses
+                .iter()
+                .for_each(|address| value.extend(address.octets())),
+            Self::Unknown(_, bytes) => value.extend(bytes),
+        }
+        value
+    }
+}
+
+impl fmt::Display for SvcParam {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let key = svc_param_key_name(self.key());
+        match self {
+            Self::Mandatory(keys) => {
+                let names: Vec<String> = keys.iter().map(|key| svc_param_key_name(*key)).collect();
+                write!(f, "{}={}", key, names.join(","))
+            }
+            Self::Alpn(ids) => {
+                // 値の中の , と \ は \ でエスケープしてから、ほかの値と同じく表記する
+                let mut list = Vec::new();
+                for (i, id) in ids.iter().enumerate() {
+                    if i > 0 {
+                        list.push(b',');
+                    }
+                    for &byte in id {
+                        if byte == b',' || byte == b'\\' {
+                            list.push(b'\\');
+                        }
+                        list.push(byte);
+                    }
+                }
+                write!(f, "{}={}", key, presentation_value(&list))
+            }
+            Self::NoDefaultAlpn => f.write_str(&key),
+            Self::Port(port) => write!(f, "{}={}", key, port),
+            Self::Ipv4Hint(addresses) => {
+                let addresses: Vec<String> = addresses.iter().map(|a| a.to_string()).collect();
+                write!(f, "{}={}", key, addresses.join(","))
+            }
+            Self::Ech(config) => write!(f, "{}={}", key, BASE64.encode(config)),
+            Self::Ipv6Hint(addresses) => {
+                let addresses: Vec<String> = addresses.iter().map(|a| a.to_string()).collect();
+                write!(f, "{}={}", key, addresses.join(","))
+            }
+            Self::Unknown(_, bytes) => write!(f, "{}={}", key, presentation_value(bytes)),
+        }
+    }
+}
+
+/** SvcParamKey の表記。知らないキーは keyNNNNN */
+pub fn svc_param_key_name(key: u16) -> String {
+    SVC_PARAM_KEYS
+        .get(usize::from(key))
+        .map(|name| name.to_string())
+        .unwrap_or_else(|| format!("key{}", key))
+}
+
+pub fn svc_param_key_from_name(name: &str) -> Option<u16> {
+    let name = name.to_ascii_lowercase();
+    SVC_PARAM_KEYS
+        .iter()
+        .position(|key| *key == name)
+        .map(|key| key as u16)
+        .or_else(|| name.strip_prefix("key")?.parse().ok())
+}
+
+/** 区切りや引用符を含まない値はそのまま、それ以外は引用符で囲んで表記する */
+fn presentation_value(value: &[u8]) -> String {
+    if !value.is_empty()
+        && value
+            .iter()
+            .all(|byte| byte.is_ascii_graphic() && !b"\"\\;()".contains(byte))
+    {
+        String::from_utf8_lossy(value).into_owned()
+    } else {
+        quote(value)
+    }
+}
+
+/** 長さのオクテットに続く <character-string> を読み、その内容と次の位置を返す */
+pub fn read_character_string(bytes: &[u8], position: usize) -> Option<(Vec<u8>, usize)> {
+    let length = usize::from(*bytes.get(position)?);
+    let end = position + 1 + length;
+    Some((bytes.get(position + 1..end)?.to_vec(), end))
+}
+
+/** <character-string> を書き出す。255 バイトを超える分は切り捨てる */
+pub fn write_character_string(bytes: &mut Vec<u8>, string: &[u8]) {
+    let string = &string[..string.len().min(255)];
+    bytes.push(string.len() as u8);
+    bytes.extend(string);
+}
+
+/** マスターファイル形式で二重引用符に囲んだ <character-string>。" と \ と表示できないバイトはエスケープする */
+pub fn quote(string: &[u8]) -> String {
+    let mut quoted = String::from("\"");
+    for &byte in string {
+        match byte {
+            b'"' | b'\\' => {
+                quoted.push('\\');
+                quoted.push(char::from(byte));
+            }
+            0x20..=0x7E => quoted.push(char::from(byte)),
+            _ => quoted.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        quote, spki, svc_param_key_from_name, Caa, Hinfo, Naptr, Openpgpkey, Srv, Sshfp, SvcParam,
+        Svcb, Tlsa, Txt,
+    };
+    use crate::message::{Header, Message, Question};
+    use data_encoding::HEXUPPER;
+    use ring::digest;
+
+    #[test]
+    fn txt_with_multiple_strings() {
+        let mut rdata = vec![255];
+        rdata.extend([b'a'; 255]);
+        rdata.push(3);
+        rdata.extend(b"bcd");
+        let txt = Txt::parse(&rdata).unwrap();
+        assert_eq!(txt.strings.len(), 2);
+        assert_eq!(txt.concat().len(), 258);
+        assert!(txt.text().ends_with("abcd"));
+        assert_eq!(txt.to_rdata(), rdata);
+        assert_eq!(Txt::from_bytes(&txt.concat()), txt);
+
+        // 空の <character-string> も 1 つの値
+        let empty = Txt::parse(&[0]).unwrap();
+        assert_eq!(empty.strings, vec![Vec::<u8>::new()]);
+        assert_eq!(Txt::from_bytes(b""), empty);
+
+        // 長さが RDATA を超えるものや空の RDATA は不正
+        assert_eq!(Txt::parse(&[4, b'a']), None);
+        assert_eq!(Txt::parse(&[]), None);
+    }
+
+    #[test]
+    fn txt_presentation() {
+        let txt = Txt {
+            strings: vec![
+                b"v=spf1 -all".to_vec(),
+                b"say \"hi\" \\ bye".to_vec(),
+                vec![0x00, 0x7F, 0xE3],
+            ],
+        };
+        assert_eq!(
+            txt.to_string(),
+            r#""v=spf1 -all" "say \"hi\" \\ bye" "\000\127\227""#
+        );
+        assert_eq!(quote(b""), "\"\"");
+    }
+
+    #[test]
+    fn hinfo_and_caa() {
+        let hinfo = Hinfo {
+            cpu: b"RFC8482".to_vec(),
+            os: Vec::new(),
+        };
+        assert_eq!(Hinfo::parse(&hinfo.to_rdata()), Some(hinfo.clone()));
+        assert_eq!(hinfo.to_string(), r#""RFC8482" """#);
+        assert_eq!(Hinfo::parse(&[1, b'x']), None);
+
+        let caa = Caa {
+            flags: 128,
+            tag: "issue".to_string(),
+            value: b"ca.example.net; account=230123".to_vec(),
+        };
+        assert_eq!(&caa.to_rdata()[..7], b"\x80\x05issue");
+        assert_eq!(Caa::parse(&caa.to_rdata()), Some(caa.clone()));
+        assert!(caa.is_critical());
+        assert_eq!(
+            caa.to_string(),
+            r#"128 issue "ca.example.net; account=230123""#
+        );
+        // タグは 1 文字以上の英数字
+        assert_eq!(Caa::parse(b"\x00\x00"), None);
+        assert_eq!(Caa::parse(b"\x00\x02a-"), None);
+    }
+
+    #[test]
+    fn srv_and_naptr_with_compressed_names() {
+        // 送り手が RFC に反して圧縮した名前も、質問部の名前を指すポインタとして読む
+        let message = Message::new(
+            Header::create(1, 1, 0, 1, 0, 0, 0, 0, 0, 1, 0, 0, 0),
+            Question::new("_sip._udp.Example.com", 33, 1).unwrap(),
+        )
+        .to_bytes();
+        let mut rdata = vec![0, 10, 0, 60, 0x13, 0xC4];
+        rdata.extend([3, b'S', b'I', b'P', 0xC0, 22]);
+        let srv = Srv::parse(&message, &rdata).unwrap();
+        assert_eq!(srv.target, "SIP.Example.com");
+        assert_eq!(srv.to_string(), "10 60 5060 SIP.Example.com.");
+        // 書き出すときは圧縮しない
+        assert_eq!(&srv.to_rdata()[6..10], b"\x03SIP");
+        assert_eq!(Srv::parse(&[], &srv.to_rdata()), Some(srv));
+        assert_eq!(Srv::parse(&message, &rdata[..8]), None);
+
+        let naptr = Naptr {
+            order: 100,
+            preference: 10,
+            flags: b"S".to_vec(),
+            services: b"SIP+D2U".to_vec(),
+            regexp: Vec::new(),
+            replacement: "_sip._udp.example.com".to_string(),
+        };
+        assert_eq!(Naptr::parse(&[], &naptr.to_rdata()), Some(naptr.clone()));
+        assert_eq!(
+            naptr.to_string(),
+            r#"100 10 "S" "SIP+D2U" "" _sip._udp.example.com."#
+        );
+        let mut compressed = naptr.to_rdata()[..15].to_vec();
+        compressed.extend([0xC0, 12]);
+        assert_eq!(
+            Naptr::parse(&message, &compressed).unwrap().replacement,
+            "_sip._udp.Example.com"
+        );
+    }
+
+    #[test]
+    fn svcb_service_mode() {
+        // RFC 9460 Appendix D.2 の最後の例
+        let svcb = Svcb {
+            priority: 16,
+            target: "foo.example.org".to_string(),
+            params: vec![
+                SvcParam::Mandatory(vec![1, 4]),
+                SvcParam::Alpn(vec![b"h2".to_vec(), b"h3-19".to_vec()]),
+                SvcParam::Ipv4Hint(vec!["192.0.2.1".parse().unwrap()]),
+            ],
+        };
+        let rdata = svcb.to_rdata();
+        assert_eq!(
+            rdata,
+            b"\x00\x10\x03foo\x07example\x03org\x00\
+              \x00\x00\x00\x04\x00\x01\x00\x04\
+              \x00\x01\x00\x09\x02h2\x05h3-19\
+              \x00\x04\x00\x04\xc0\x00\x02\x01"
+        );
+        assert_eq!(Svcb::parse(&rdata), Some(svcb.clone()));
+        assert!(!svcb.is_alias());
+        assert_eq!(
+            svcb.to_string(),
+            "16 foo.example.org. mandatory=alpn,ipv4hint alpn=h2,h3-19 ipv4hint=192.0.2.1"
+        );
+
+        let all = Svcb {
+            priority: 1,
+            target: "".to_string(),
+            params: vec![
+                SvcParam::Alpn(vec![b"f\\oo,bar".to_vec(), b"h2".to_vec()]),
+                SvcParam::NoDefaultAlpn,
+                SvcParam::Port(8443),
+                SvcParam::Ech(vec![0xFE, 0x0D]),
+                SvcParam::Ipv6Hint(vec!["2001:db8::1".parse().unwrap()]),
+                SvcParam::Unknown(65333, b"ex ample".to_vec()),
+            ],
+        };
+        assert_eq!(Svcb::parse(&all.to_rdata()), Some(all.clone()));
+        assert_eq!(
+            all.to_string(),
+            r#"1 . alpn="f\\\\oo\\,bar,h2" no-default-alpn port=8443 ech=/g0= ipv6hint=2001:db8::1 key65333="ex ample""#
+        );
+        assert_eq!(svc_param_key_from_name("IPv6Hint"), Some(6));
+        assert_eq!(svc_param_key_from_name("key65333"), Some(65333));
+    }
+
+    #[test]
+    fn svcb_rejects_malformed_params() {
+        let alias = Svcb {
+            priority: 0,
+            target: "pool.svc.example".to_string(),
+            params: Vec::new(),
+        };
+        assert!(Svcb::parse(&alias.to_rdata()).unwrap().is_alias());
+
+        let with = |params: &[u8]| {
+            let mut rdata = b"\x00\x01\x00".to_vec();
+            rdata.extend(params);
+            Svcb::parse(&rdata)
+        };
+        // 昇順でないキー、重複したキー
+        assert_eq!(with(b"\x00\x03\x00\x02\x01\xbb\x00\x02\x00\x00"), None);
+        assert_eq!(
+            with(b"\x00\x03\x00\x02\x01\xbb\x00\x03\x00\x02\x01\xbb"),
+            None
+        );
+        // 値の長さが合わない port, 値を持つ no-default-alpn, 空の alpn
+        assert_eq!(with(b"\x00\x03\x00\x01\x01"), None);
+        assert_eq!(with(b"\x00\x02\x00\x01\x01"), None);
+        assert_eq!(with(b"\x00\x01\x00\x00"), None);
+        // mandatory に mandatory 自身を含めてはならない
+        assert_eq!(with(b"\x00\x00\x00\x02\x00\x00"), None);
+        // TargetName の圧縮
+        assert_eq!(Svcb::parse(b"\x00\x01\xc0\x0c"), None);
+    }
+
+    /** DER の TLV */
+    fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![tag];
+        match content.len() {
+            0..=127 => bytes.push(content.len() as u8),
+            128..=255 => bytes.extend([0x81, content.len() as u8]),
+            _ => {
+                bytes.push(0x82);
+                bytes.extend((content.len() as u16).to_be_bytes());
+            }
+        }
+        bytes.extend(content);
+        bytes
+    }
+
+    /** 中身は意味を持たないが、構造は X.509 に従った証明書と、その SubjectPublicKeyInfo */
+    fn fake_certificate(version: bool) -> (Vec<u8>, Vec<u8>) {
+        let algorithm = der(0x30, &der(0x06, &[0x2B, 0x65, 0x70]));
+        let spki = der(0x30, &[algorithm.clone(), der(0x03, &[0xA5; 200])].concat());
+        let name = der(0x30, &der(0x31, b"example"));
+        let mut tbs = Vec::new();
+        if version {
+            tbs.extend(der(0xA0, &der(0x02, &[2])));
+        }
+        tbs.extend(der(0x02, &[0x01, 0x23]));
+        tbs.extend(&algorithm);
+        tbs.extend(&name);
+        tbs.extend(der(
+            0x30,
+            &[der(0x17, b"260101"), der(0x17, b"270101")].concat(),
+        ));
+        tbs.extend(&name);
+        tbs.extend(&spki);
+        let certificate = der(
+            0x30,
+            &[der(0x30, &tbs), algorithm, der(0x03, &[0x5A; 64])].concat(),
+        );
+        (certificate, spki)
+    }
+
+    #[test]
+    fn tlsa_matches_certificate_and_spki() {
+        let (certificate, expected) = fake_certificate(true);
+        assert_eq!(spki(&certificate), Some(expected.as_slice()));
+        assert_eq!(spki(&fake_certificate(false).0), Some(expected.as_slice()));
+        assert_eq!(spki(&certificate[..100]), None);
+
+        let sha256 = |bytes: &[u8]| digest::digest(&digest::SHA256, bytes).as_ref().to_vec();
+        let sha512 = |bytes: &[u8]| digest::digest(&digest::SHA512, bytes).as_ref().to_vec();
+        let tlsa = |selector, matching_type, data| Tlsa {
+            usage: 3,
+            selector,
+            matching_type,
+            data,
+        };
+
+        assert!(tlsa(0, 0, certificate.clone()).matches(&certificate));
+        assert!(tlsa(0, 1, sha256(&certificate)).matches(&certificate));
+        assert!(tlsa(1, 1, sha256(&expected)).matches(&certificate));
+        assert!(tlsa(1, 2, sha512(&expected)).matches(&certificate));
+        assert!(!tlsa(1, 1, sha256(&certificate)).matches(&certificate));
+        assert!(!tlsa(1, 3, sha256(&expected)).matches(&certificate));
+
+        assert!(tlsa(1, 0, expected.clone()).matches_spki(&expected));
+        assert!(!tlsa(0, 0, expected.clone()).matches_spki(&expected));
+        assert!(tlsa(1, 1, sha256(&expected)).matches_spki_digest(&sha256(&expected)));
+        assert!(!tlsa(1, 2, sha256(&expected)).matches_spki_digest(&sha256(&expected)));
+
+        let record = tlsa(3, 1, HEXUPPER.decode(b"0A0B").unwrap());
+        assert_eq!(Tlsa::parse(&record.to_rdata()), Some(record.clone()));
+        assert_eq!(record.to_string(), "3 3 1 0A0B");
+    }
+
+    #[test]
+    fn sshfp_and_openpgpkey() {
+        let sshfp = Sshfp {
+            algorithm: 4,
+            fingerprint_type: 2,
+            fingerprint: vec![0x12, 0xEF],
+        };
+        assert_eq!(Sshfp::parse(&sshfp.to_rdata()), Some(sshfp.clone()));
+        assert_eq!(sshfp.to_string(), "4 2 12EF");
+        assert_eq!(Sshfp::parse(&[4]), None);
+
+        let key = Openpgpkey {
+            public_key: b"mQENBF".to_vec(),
+        };
+        assert_eq!(Openpgpkey::parse(&key.to_rdata()), Some(key.clone()));
+        assert_eq!(key.to_string(), "bVFFTkJG");
+        assert_eq!(Openpgpkey::parse(&[]), None);
+    }
+}
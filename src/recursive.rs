@@ -0,0 +1,391 @@
+use crate::cache::{Answer, Cache};
+use crate::full_resolver::{weakest, Resolver};
+use crate::message::{Message, Resource};
+use crate::server::{Handler, Request};
+use crate::validator::{self, Security};
+
+/** 1 つの問い合わせでたどる CNAME の数の上限 */
+const MAX_CNAME_CHAIN: usize = 8;
+
+/** クライアントの再帰問い合わせに resolver で答えるフルリゾルバ。答えは TTL の間キャッシュする */
+pub struct Recursor {
+    resolver: Resolver,
+    cache: Cache,
+}
+
+impl Recursor {
+    pub fn new(resolver: Resolver) -> Self {
+        Self {
+            resolver,
+            cache: Cache::new(),
+        }
+    }
+
+    /**
+     * qname の qtype の答え。CNAME は qtype のレコードに行き着くまでたどり、たどったレコードを Answer の先頭に残す。
+     * 解決できないか、DNSSEC の検証に失敗したら None
+     */
+    pub fn resolve(&self, qname: &str, qtype: u16) -> Option<Answer> {
+        let now = validator::now();
+        if let Some(answer) = self.cache.get(qname, qtype, now) {
+            println!("{:?} (type {:?}) をキャッシュから答えます", qname, qtype);
+            return Some(answer);
+        }
+        let answer = self.follow_cnames(qname, qtype)?;
+        self.cache.insert(qname, qtype, &answer, now);
+        Some(answer)
+    }
+
+    fn follow_cnames(&self, qname: &str, qtype: u16) -> Option<Answer> {
+        let mut answer = self.lookup(qname, qtype)?;
+        let mut name = qname.trim_end_matches('.').to_ascii_lowercase();
+        for _ in 0..=MAX_CNAME_CHAIN {
+            // CNAME そのものと ANY の問い合わせではたどらない
+            if answer.rcode != 0
+                || qtype == 5
+                || qtype == 255
+                || answer
+                    .answers
+                    .iter()
+                    .any(|r| r.rr_type == qtype && r.name.eq_ignore_ascii_case(&name))
+            {
+                return Some(answer);
+            }
+            name = match answer
+                .answers
+                .iter()
+                .find(|r| r.rr_type == 5 && r.name.eq_ignore_ascii_case(&name))
+            {
+                Some(cname) => cname.cname.to_ascii_lowercase(),
+                None => return Some(answer),
+            };
+            // 応答に別名の先のレコードがあれば、そのままたどる
+            if answer
+                .answers
+                .iter()
+                .any(|r| r.name.eq_ignore_ascii_case(&name))
+            {
+                continue;
+            }
+            println!("{:?} の別名 {:?} を問い合わせます", qname, name);
+            let next = self.lookup(&name, qtype)?;
+            answer = Answer {
+                rcode: next.rcode,
+                answers: [answer.answers, next.answers].concat(),
+                authorities: next.authorities,
+                security: weakest(answer.security, next.security),
+            };
+        }
+
+        println!("CNAME の連鎖が長すぎるか、ループしています: {:?}", qname);
+        None
+    }
+
+    /** キャッシュになければ resolver で問い合わせる (CNAME はたどらない) */
+    fn lookup(&self, qname: &str, qtype: u16) -> Option<Answer> {
+        if let Some(answer) = self.cache.get(qname, qtype, validator::now()) {
+            return Some(answer);
+        }
+        let (response, security) = self.resolver.lookup_validated(qname, qtype)?;
+        if security == Security::Bogus {
+            println!("{:?} の応答は DNSSEC の検証に失敗しました", qname);
+            return None;
+        }
+        Some(Answer {
+            rcode: response.header.rcode(),
+            answers: response.answers.clone(),
+            authorities: response.authorities.clone(),
+            security,
+        })
+    }
+}
+
+impl Handler for Recursor {
+    fn handle(&self, request: &Request) -> Message {
+        let question = &request.question;
+        // IN クラス以外とゾーン転送 (IXFR, AXFR) は断る
+        if question.qclass != 1 || question.qtype == 251 || question.qtype == 252 {
+            return request.response(false, true, 5);
+        }
+        // 答えは NOERROR か NXDOMAIN のものだけ。ほかの RCODE はこちらの失敗として SERVFAIL にする
+        let answer = match self.resolve(&question.qname_dec, question.qtype) {
+            Some(answer) if matches!(answer.rcode, 0 | 3) => answer,
+            _ => {
+                println!("{} を解決できませんでした。SERVFAIL を返します", question);
+                return request.response(false, true, 2);
+            }
+        };
+
+        let mut response = request.response(false, true, answer.rcode);
+        // DO ビットのないクライアントには、問われていない DNSSEC のレコードを返さない (RFC 4035 Section 3.2.1)
+        let dnssec_ok = request.edns.is_some_and(|edns| edns.dnssec_ok);
+        let visible = |r: &Resource| {
+            dnssec_ok || r.rr_type == question.qtype || !matches!(r.rr_type, 46 | 47 | 50)
+        };
+        response.answers = answer.answers.into_iter().filter(visible).collect();
+        response.authorities = answer.authorities.into_iter().filter(visible).collect();
+        // 検証済みの答えには、DO か AD を立てて問い合わせたクライアントに AD を返す (RFC 6840 Section 5.7)
+        if answer.security == Security::Secure && (dnssec_ok || request.header.z() & 0b010 != 0) {
+            response.header.flags |= 0x0020;
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Recursor;
+    use crate::authoritative::{Authority, Zone};
+    use crate::config::{Mode, ResolverConfig};
+    use crate::full_resolver::{testing::with_roots, Resolver};
+    use crate::message::{Header, Message, Question, Resource};
+    use crate::server::testing::request;
+    use crate::server::{spawn, Handler, Request};
+    use crate::zone_file;
+    use std::net::{SocketAddr, UdpSocket};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const EXAMPLE_COM: &str = "@ 3600 IN SOA ns1 hostmaster 1 7200 3600 1209600 300
+@ 3600 IN NS ns1
+ns1 3600 IN A 192.0.2.53
+web 3600 IN A 192.0.2.1
+alias 3600 IN CNAME web
+www 3600 IN CNAME www.example.net.
+loop 3600 IN CNAME loop.example.net.
+";
+
+    const EXAMPLE_NET: &str = "@ 3600 IN SOA ns1 hostmaster 1 7200 3600 1209600 300
+@ 3600 IN NS ns1
+ns1 3600 IN A 198.51.100.53
+www 600 IN A 198.51.100.1
+loop 3600 IN CNAME loop.example.com.
+";
+
+    const ROOT: &str =
+        "@ 86400 IN SOA a.root-servers.net. nstld.verisign-grs.com. 1 1800 900 604800 86400
+@ 518400 IN NS a.root-servers.net.
+";
+
+    /** example.com と example.net の権威サーバに RA を付けて、上位リゾルバのふりをする */
+    struct Upstream {
+        authority: Authority,
+        queries: Arc<AtomicUsize>,
+    }
+
+    impl Handler for Upstream {
+        fn handle(&self, request: &Request) -> Message {
+            self.queries.fetch_add(1, Ordering::SeqCst);
+            let mut response = self.authority.handle(request);
+            response.header.flags |= 0x0080;
+            response
+        }
+    }
+
+    /** 権威サーバとして答え、受け取った問い合わせを数える */
+    struct Counting {
+        authority: Authority,
+        queries: Arc<AtomicUsize>,
+    }
+
+    impl Handler for Counting {
+        fn handle(&self, request: &Request) -> Message {
+            self.queries.fetch_add(1, Ordering::SeqCst);
+            self.authority.handle(request)
+        }
+    }
+
+    fn zone(origin: &str, text: &str) -> Zone {
+        Zone::new(origin, zone_file::parse(text, origin).unwrap()).unwrap()
+    }
+
+    /** 上位リゾルバに転送する Recursor と、上位リゾルバが受け取った問い合わせの数 */
+    fn recursor() -> (Recursor, Arc<AtomicUsize>) {
+        let queries = Arc::new(AtomicUsize::new(0));
+        let upstream = Upstream {
+            authority: Authority::new(vec![
+                zone("example.com", EXAMPLE_COM),
+                zone("example.net", EXAMPLE_NET),
+            ]),
+            queries: Arc::clone(&queries),
+        };
+        let address = spawn("127.0.0.1:0".parse().unwrap(), upstream).unwrap();
+        (forwarding_to(address), queries)
+    }
+
+    fn forwarding_to(address: SocketAddr) -> Recursor {
+        Recursor::new(Resolver::with_config(ResolverConfig {
+            timeout: Duration::from_millis(300),
+            attempts: 1,
+            ..ResolverConfig::forward(&[address])
+        }))
+    }
+
+    /** ルートと example.com, example.net の権威サーバを 1 台で兼ね、それを反復問い合わせでたどる Recursor */
+    fn iterative() -> (Recursor, Arc<AtomicUsize>) {
+        let queries = Arc::new(AtomicUsize::new(0));
+        let root = Counting {
+            authority: Authority::new(vec![
+                zone("", ROOT),
+                zone("example.com", EXAMPLE_COM),
+                zone("example.net", EXAMPLE_NET),
+            ]),
+            queries: Arc::clone(&queries),
+        };
+        let address = spawn("127.0.0.1:0".parse().unwrap(), root).unwrap();
+        let config = ResolverConfig {
+            mode: Mode::Iterative,
+            timeout: Duration::from_millis(300),
+            attempts: 1,
+            ..ResolverConfig::new()
+        };
+        (Recursor::new(with_roots(config, &[address])), queries)
+    }
+
+    fn text(records: &[Resource]) -> Vec<String> {
+        records.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn answers_follow_cnames_and_are_cached() {
+        let (recursor, queries) = recursor();
+
+        let response = recursor.handle(&request("WWW.example.com", 1));
+        assert_eq!(response.header.id, 7);
+        assert_eq!(response.header.qr(), 1);
+        assert_eq!(response.header.aa(), 0);
+        assert_eq!(response.header.rd(), 1);
+        assert_eq!(response.header.ra(), 1);
+        assert_eq!(response.header.rcode(), 0);
+        assert_eq!(response.question.qname_dec, "WWW.example.com");
+        assert_eq!(
+            text(&response.answers),
+            vec![
+                "www.example.com. 3600 IN CNAME www.example.net.",
+                "www.example.net. 600 IN A 198.51.100.1"
+            ]
+        );
+        // 別名の先を改めて問い合わせた
+        assert_eq!(queries.load(Ordering::SeqCst), 2);
+
+        let response = recursor.handle(&request("www.example.com", 1));
+        assert_eq!(response.answers.len(), 2);
+        assert!(response.answers.iter().all(|r| r.ttl <= 600));
+        assert_eq!(queries.load(Ordering::SeqCst), 2);
+
+        // 同じゾーンの中の CNAME は上位リゾルバがたどっている
+        let response = recursor.handle(&request("alias.example.com", 1));
+        assert_eq!(response.answers.len(), 2);
+        assert_eq!(queries.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn iterative_answers_follow_cnames_and_are_cached() {
+        let (recursor, queries) = iterative();
+
+        let response = recursor.handle(&request("www.example.com", 1));
+        assert_eq!(response.header.aa(), 0);
+        assert_eq!(response.header.ra(), 1);
+        assert_eq!(response.header.rcode(), 0);
+        assert_eq!(
+            text(&response.answers),
+            vec![
+                "www.example.com. 3600 IN CNAME www.example.net.",
+                "www.example.net. 600 IN A 198.51.100.1"
+            ]
+        );
+        // ルートとして設定したサーバに、別名の先も改めて問い合わせた
+        assert_eq!(queries.load(Ordering::SeqCst), 2);
+
+        let response = recursor.handle(&request("www.example.com", 1));
+        assert_eq!(response.answers.len(), 2);
+        assert_eq!(queries.load(Ordering::SeqCst), 2);
+
+        let response = recursor.handle(&request("nothing.example.net", 1));
+        assert_eq!(response.header.rcode(), 3);
+        assert_eq!(response.authorities[0].rr_type, 6);
+        assert_eq!(response.authorities[0].name, "example.net");
+    }
+
+    #[test]
+    fn negative_answers_are_passed_on_and_cached() {
+        let (recursor, queries) = recursor();
+        for _ in 0..2 {
+            let response = recursor.handle(&request("nothing.example.com", 1));
+            assert_eq!(response.header.rcode(), 3);
+            assert_eq!(response.header.ra(), 1);
+            assert!(response.answers.is_empty());
+            assert_eq!(response.authorities[0].rr_type, 6);
+        }
+        assert_eq!(queries.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn failures_become_servfail() {
+        let (recursor, _) = recursor();
+        // 上位リゾルバが REFUSED を返す名前と、CNAME のループ
+        for qname in ["www.example.org", "loop.example.com"] {
+            let response = recursor.handle(&request(qname, 1));
+            assert_eq!(response.header.id, 7);
+            assert_eq!(response.header.ra(), 1);
+            assert_eq!(response.header.rcode(), 2);
+            assert_eq!(response.question.qname_dec, qname);
+            assert!(response.answers.is_empty());
+        }
+
+        // 反復問い合わせで、権威を持たないサーバが REFUSED を返す名前
+        let lame = spawn(
+            "127.0.0.1:0".parse().unwrap(),
+            Authority::new(vec![zone("example.com", EXAMPLE_COM)]),
+        )
+        .unwrap();
+        let config = ResolverConfig {
+            mode: Mode::Iterative,
+            ..ResolverConfig::new()
+        };
+        let recursor = Recursor::new(with_roots(config, &[lame]));
+        let response = recursor.handle(&request("www.example.org", 1));
+        assert_eq!(response.header.rcode(), 2);
+        assert!(response.answers.is_empty());
+
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recursor = forwarding_to(silent.local_addr().unwrap());
+        assert_eq!(
+            recursor
+                .handle(&request("www.example.com", 1))
+                .header
+                .rcode(),
+            2
+        );
+
+        let mut chaos = request("version.bind", 16);
+        chaos.question.qclass = 3;
+        assert_eq!(recursor.handle(&chaos).header.rcode(), 5);
+    }
+
+    #[test]
+    fn serves_clients_over_udp() {
+        let (recursor, _) = recursor();
+        let address = spawn("127.0.0.1:0".parse().unwrap(), recursor).unwrap();
+
+        let query = Message::new(
+            Header::create(0x1234, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0),
+            Question::new("web.example.com", 1, 1).unwrap(),
+        );
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        socket.send_to(&query.to_bytes(), address).unwrap();
+        let mut buf = [0; 512];
+        let (length, _) = socket.recv_from(&mut buf).unwrap();
+        let response = Message::parse(&buf[..length]).unwrap();
+        assert_eq!(response.header.id, 0x1234);
+        assert_eq!(response.header.ra(), 1);
+        assert_eq!(
+            text(&response.answers),
+            vec!["web.example.com. 3600 IN A 192.0.2.1"]
+        );
+    }
+}
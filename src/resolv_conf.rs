@@ -0,0 +1,161 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+pub const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+
+/** glibc と同じく nameserver は先頭の 3 つまで */
+const MAXNS: usize = 3;
+
+/** resolv.conf(5) の内容。省略された項目は glibc の既定値になる */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvConf {
+    pub nameservers: Vec<IpAddr>,
+    pub search: Vec<String>,
+    pub ndots: usize,
+    pub timeout: Duration,
+    pub attempts: usize,
+    pub rotate: bool,
+    pub edns0: bool,
+}
+
+impl Default for ResolvConf {
+    fn default() -> Self {
+        Self {
+            nameservers: Vec::new(),
+            search: Vec::new(),
+            ndots: 1,
+            timeout: Duration::from_secs(5),
+            attempts: 2,
+            rotate: false,
+            edns0: false,
+        }
+    }
+}
+
+impl ResolvConf {
+    pub fn parse(text: &str) -> Self {
+        let mut conf = Self::default();
+
+        for line in text.lines() {
+            // glibc と同じく、'#' か ';' で始まる行だけがコメント (行の途中にあっても値の一部)
+            if line.trim_start().starts_with(['#', ';']) {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let keyword = match words.next() {
+                Some(keyword) => keyword,
+                None => continue,
+            };
+
+            match keyword {
+                "nameserver" => {
+                    // IP アドレスとして読めない行は数えずに読み飛ばす
+                    if let Some(Ok(address)) = words.next().map(str::parse::<IpAddr>) {
+                        if conf.nameservers.len() < MAXNS {
+                            conf.nameservers.push(address);
+                        }
+                    }
+                }
+                // domain と search は後に書かれたほうが優先される
+                "domain" => {
+                    conf.search = words.next().map(normalize).into_iter().collect();
+                }
+                "search" => {
+                    conf.search = words.map(normalize).collect();
+                }
+                "options" => {
+                    for option in words {
+                        conf.apply_option(option);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        conf
+    }
+
+    fn apply_option(&mut self, option: &str) {
+        let (name, value) = match option.split_once(':') {
+            Some((name, value)) => (name, value.parse::<usize>().ok()),
+            None => (option, None),
+        };
+
+        // 上限値は glibc に合わせる
+        match (name, value) {
+            ("ndots", Some(ndots)) => self.ndots = ndots.min(15),
+            ("timeout", Some(timeout)) => {
+                self.timeout = Duration::from_secs(timeout.clamp(1, 30) as u64)
+            }
+            ("attempts", Some(attempts)) => self.attempts = attempts.clamp(1, 5),
+            ("rotate", _) => self.rotate = true,
+            ("edns0", _) => self.edns0 = true,
+            _ => {}
+        }
+    }
+}
+
+fn normalize(domain: &str) -> String {
+    domain.trim_end_matches('.').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ResolvConf;
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    #[test]
+    fn parse_resolv_conf() {
+        let conf = ResolvConf::parse(
+            "# generated by NetworkManager\n\
+             domain old.example\n\
+             search corp.example. example.com\n\
+             nameserver 192.168.12.1\n\
+             nameserver ns.corp.example\n\
+             nameserver 2001:db8::53 ; secondary\n\
+             nameserver 192.0.2.53\n\
+             nameserver 192.0.2.54\n\
+             options ndots:2 timeout:3 attempts:9 rotate edns0 unknown\n",
+        );
+
+        assert_eq!(
+            conf.nameservers,
+            ["192.168.12.1", "2001:db8::53", "192.0.2.53"]
+                .iter()
+                .map(|s| s.parse::<IpAddr>().unwrap())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(conf.search, vec!["corp.example", "example.com"]);
+        assert_eq!(conf.ndots, 2);
+        assert_eq!(conf.timeout, Duration::from_secs(3));
+        assert_eq!(conf.attempts, 5);
+        assert!(conf.rotate);
+        assert!(conf.edns0);
+    }
+
+    #[test]
+    fn parse_empty_resolv_conf() {
+        assert_eq!(ResolvConf::parse(""), ResolvConf::default());
+        assert_eq!(
+            ResolvConf::parse("search a.example\ndomain b.example\n").search,
+            vec!["b.example"]
+        );
+    }
+
+    #[test]
+    fn comments_only_at_line_start() {
+        let conf = ResolvConf::parse(
+            "  ; nameserver 192.0.2.1\n\
+             \t# nameserver 192.0.2.2\n\
+             nameserver 192.0.2.3#main\n\
+             nameserver 192.0.2.4\n\
+             search corp#1.example b;c.example\n",
+        );
+        assert_eq!(
+            conf.nameservers,
+            vec!["192.0.2.4".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(conf.search, vec!["corp#1.example", "b;c.example"]);
+    }
+}
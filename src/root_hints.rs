@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+
+/** IANA が配布している named.root */
+const NAMED_ROOT: &str = include_str!("named.root");
+
+/** ルートヒント (named.root 形式) に書かれたルートサーバの NS 名とアドレス */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootHints {
+    /** NS 名 (小文字・末尾のドットなし) とそのアドレス。ファイルに書かれた順 */
+    pub servers: Vec<(String, Vec<IpAddr>)>,
+}
+
+impl RootHints {
+    pub fn parse(text: &str) -> Self {
+        let mut names: Vec<String> = Vec::new();
+        let mut addresses: HashMap<String, Vec<IpAddr>> = HashMap::new();
+
+        for line in text.lines() {
+            let line = match line.find(';') {
+                Some(index) => &line[..index],
+                None => line,
+            };
+            let mut words = line.split_whitespace();
+            let owner = match words.next() {
+                Some(owner) => normalize(owner),
+                None => continue,
+            };
+            // TTL と CLASS は省略されていることがある
+            let mut words = words.skip_while(|word| {
+                word.chars().all(|c| c.is_ascii_digit()) || word.eq_ignore_ascii_case("IN")
+            });
+            let (rr_type, rdata) = match (words.next(), words.next()) {
+                (Some(rr_type), Some(rdata)) => (rr_type.to_ascii_uppercase(), rdata),
+                _ => continue,
+            };
+
+            match rr_type.as_str() {
+                "NS" if owner.is_empty() => {
+                    let name = normalize(rdata);
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+                "A" | "AAAA" => {
+                    if let Ok(address) = rdata.parse::<IpAddr>() {
+                        addresses.entry(owner).or_default().push(address);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let servers = names
+            .into_iter()
+            .map(|name| {
+                let addresses = addresses.remove(&name).unwrap_or_default();
+                (name, addresses)
+            })
+            .collect();
+        Self { servers }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    /** すべてのルートサーバのアドレスを NS の順に返す */
+    pub fn addresses(&self) -> Vec<IpAddr> {
+        self.servers
+            .iter()
+            .flat_map(|(_, addresses)| addresses.iter().copied())
+            .collect()
+    }
+}
+
+impl Default for RootHints {
+    fn default() -> Self {
+        Self::parse(NAMED_ROOT)
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RootHints;
+    use std::net::IpAddr;
+
+    #[test]
+    fn builtin_root_hints() {
+        let hints = RootHints::default();
+        assert_eq!(hints.servers.len(), 13);
+        assert_eq!(hints.servers[0].0, "a.root-servers.net");
+        assert_eq!(
+            hints.servers[0].1,
+            vec![
+                "198.41.0.4".parse::<IpAddr>().unwrap(),
+                "2001:503:ba3e::2:30".parse::<IpAddr>().unwrap()
+            ]
+        );
+        assert_eq!(hints.addresses().len(), 26);
+    }
+
+    #[test]
+    fn custom_root_hints() {
+        let hints = RootHints::parse(
+            "; lab root\n\
+             ns1.lab.example. 3600 IN A 10.0.0.53\n\
+             . 3600 IN NS ns1.lab.example.\n\
+             .      NS ns2.lab.example.\n\
+             ns2.lab.example.   AAAA fd00::53\n\
+             nyamikan.net. 3600 NS ignored.example.\n",
+        );
+        assert_eq!(
+            hints.servers,
+            vec![
+                (
+                    "ns1.lab.example".to_string(),
+                    vec!["10.0.0.53".parse::<IpAddr>().unwrap()]
+                ),
+                (
+                    "ns2.lab.example".to_string(),
+                    vec!["fd00::53".parse::<IpAddr>().unwrap()]
+                ),
+            ]
+        );
+    }
+}
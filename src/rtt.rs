@@ -0,0 +1,151 @@
+use rand::Rng;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/** 新しい応答時間を平滑化 RTT に反映する割合 (BIND と同じく 3/10) */
+const SAMPLE_WEIGHT: f64 = 0.3;
+
+/** 選ばれなかったサーバの平滑化 RTT を縮める割合。タイムアウトしたサーバにもいずれ再挑戦する */
+const DECAY: f64 = 0.98;
+
+/** タイムアウト時の平滑化 RTT の下限と上限 */
+const TIMEOUT_PENALTY: Duration = Duration::from_secs(1);
+const MAX_SRTT: Duration = Duration::from_secs(10);
+
+/** 最速でないサーバをあえて選ぶ確率 */
+const EXPLORATION: f64 = 0.05;
+
+/** 問い合わせ先ごとの平滑化 RTT。リゾルバ全体で共有する */
+pub struct RttTable {
+    srtt: Mutex<HashMap<SocketAddr, Duration>>,
+    exploration: f64,
+}
+
+impl RttTable {
+    pub fn new() -> Self {
+        Self::with_exploration(EXPLORATION)
+    }
+
+    pub fn with_exploration(exploration: f64) -> Self {
+        Self {
+            srtt: Mutex::new(HashMap::new()),
+            exploration,
+        }
+    }
+
+    /** 候補の中から平滑化 RTT が最も小さいサーバを選ぶ (ときどき他のサーバも試す) */
+    pub fn select(&self, candidates: &[SocketAddr]) -> Option<SocketAddr> {
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let mut rng = rand::thread_rng();
+        let mut srtt = self.srtt.lock().unwrap();
+        // まだ問い合わせたことのないサーバは、小さな乱数の RTT から始めて早めに試す
+        for candidate in candidates {
+            srtt.entry(*candidate)
+                .or_insert_with(|| Duration::from_millis(rng.gen_range(1..32)));
+        }
+
+        let selected = if rng.gen_bool(self.exploration) {
+            candidates[rng.gen_range(0..candidates.len())]
+        } else {
+            candidates
+                .iter()
+                .copied()
+                .min_by_key(|candidate| srtt[candidate])
+                .unwrap()
+        };
+
+        for candidate in candidates.iter().filter(|c| **c != selected) {
+            if let Some(value) = srtt.get_mut(candidate) {
+                *value = value.mul_f64(DECAY);
+            }
+        }
+
+        Some(selected)
+    }
+
+    /** 応答が得られたときの RTT を反映する */
+    pub fn record(&self, server: SocketAddr, rtt: Duration) {
+        let mut srtt = self.srtt.lock().unwrap();
+        let value = srtt.entry(server).or_insert(rtt);
+        *value = value.mul_f64(1.0 - SAMPLE_WEIGHT) + rtt.mul_f64(SAMPLE_WEIGHT);
+    }
+
+    /** タイムアウトしたサーバの平滑化 RTT を倍にして、しばらく選ばれにくくする */
+    pub fn penalize(&self, server: SocketAddr) {
+        let mut srtt = self.srtt.lock().unwrap();
+        let value = srtt.entry(server).or_insert(TIMEOUT_PENALTY);
+        *value = (*value * 2).clamp(TIMEOUT_PENALTY, MAX_SRTT);
+    }
+
+    pub fn srtt(&self, server: SocketAddr) -> Option<Duration> {
+        self.srtt.lock().unwrap().get(&server).copied()
+    }
+}
+
+impl Default for RttTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RttTable;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    const FIRST: &str = "192.0.2.1:53";
+    const SECOND: &str = "192.0.2.2:53";
+
+    fn server(address: &str) -> SocketAddr {
+        address.parse().unwrap()
+    }
+
+    fn servers() -> Vec<SocketAddr> {
+        vec![server(FIRST), server(SECOND)]
+    }
+
+    #[test]
+    fn select_fastest_server() {
+        let table = RttTable::with_exploration(0.0);
+        table.record(server(FIRST), Duration::from_millis(50));
+        table.record(server(SECOND), Duration::from_millis(10));
+
+        assert_eq!(table.select(&servers()), Some(server(SECOND)));
+        assert_eq!(table.select(&[]), None);
+
+        table.record(server(SECOND), Duration::from_millis(110));
+        assert_eq!(table.srtt(server(SECOND)), Some(Duration::from_millis(40)));
+    }
+
+    #[test]
+    fn timed_out_server_is_retried_later() {
+        let table = RttTable::with_exploration(0.0);
+        table.record(server(FIRST), Duration::from_millis(50));
+        table.record(server(SECOND), Duration::from_millis(10));
+        table.penalize(server(SECOND));
+
+        assert_eq!(table.select(&servers()), Some(server(FIRST)));
+        assert_eq!(
+            table.srtt(server(SECOND)),
+            Some(Duration::from_secs(1).mul_f64(0.98))
+        );
+
+        // 選ばれないあいだに平滑化 RTT が縮み、いずれ再び選ばれる
+        let retried = (0..500).any(|_| table.select(&servers()) == Some(server(SECOND)));
+        assert!(retried);
+    }
+
+    #[test]
+    fn unknown_servers_are_tried() {
+        let table = RttTable::with_exploration(0.0);
+        table.record(server(FIRST), Duration::from_millis(100));
+
+        assert_eq!(table.select(&servers()), Some(server(SECOND)));
+    }
+}
@@ -0,0 +1,541 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::message::{read_name, read_u16, Edns, Header, Message, Question};
+
+/** 応答の OPT レコードで広告する UDP ペイロードサイズ。UDP の応答もこの大きさまでにとどめる */
+pub const EDNS_UDP_PAYLOAD_SIZE: u16 = 1232;
+
+/** EDNS0 を使わないクライアントへの UDP の応答の上限 (RFC 1035 Section 4.2.1) */
+const UDP_PAYLOAD_SIZE: usize = 512;
+
+/** UDP と TCP のそれぞれで、同時に動かす応答のスレッドの上限 */
+const MAX_HANDLER_THREADS: usize = 256;
+
+/** 問い合わせのない TCP の接続を閉じるまでの時間 (RFC 7766 Section 6.2.3) */
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/** クライアントからの問い合わせ */
+pub struct Request {
+    pub header: Header,
+    pub question: Question,
+    pub edns: Option<Edns>,
+    /** 問い合わせてきたクライアントのアドレス */
+    pub client: SocketAddr,
+}
+
+impl Request {
+    /** 質問部がちょうど 1 つの問い合わせを読む。壊れたメッセージは None */
+    pub fn parse(buf: &[u8], client: SocketAddr) -> Option<Self> {
+        let header = Header::parse(buf.get(..12)?.try_into().ok()?);
+        if header.qd_count != 1 {
+            return None;
+        }
+        let (qname, position) = read_name(buf, buf, 12)?;
+        let question = Question::new(
+            &qname,
+            read_u16(buf, position)?,
+            read_u16(buf, position + 2)?,
+        )?;
+
+        // 付加情報部の OPT レコードを探す。ほかのレコードは読み飛ばす
+        let mut position = position + 4;
+        let mut edns = None;
+        let before_additionals = usize::from(header.an_count) + usize::from(header.ns_count);
+        let records = before_additionals + usize::from(header.ar_count);
+        for index in 0..records {
+            let (name, next) = read_name(buf, buf, position)?;
+            let rr_type = read_u16(buf, next)?;
+            let rdlength = read_u16(buf, next + 8)?;
+            position = next + 10 + usize::from(rdlength);
+            if position > buf.len() {
+                return None;
+            }
+            if rr_type == 41 {
+                // OPT はルートの名前で、付加情報部に 1 つだけ (RFC 6891 Section 6.1.1)
+                if index < before_additionals || !name.is_empty() || edns.is_some() {
+                    return None;
+                }
+                edns = Some(Edns {
+                    udp_payload_size: read_u16(buf, next + 2)?,
+                    dnssec_ok: read_u16(buf, next + 6)? & 0x8000 != 0,
+                });
+            }
+        }
+
+        Some(Self {
+            header,
+            question,
+            edns,
+            client,
+        })
+    }
+
+    /**
+     * この問い合わせへの応答の雛形。ID, OPCODE, RD, CD と質問部を写す。
+     * 問い合わせに EDNS0 があれば、応答にも OPT レコードを付けて DO ビットを返す (RFC 3225)
+     */
+    pub fn response(&self, aa: bool, ra: bool, rcode: u8) -> Message {
+        let mut response = Message::new(
+            Header::create(
+                self.header.id,
+                0b1,
+                self.header.opcode(),
+                aa.into(),
+                0b0,
+                self.header.rd(),
+                ra.into(),
+                self.header.z() & 0b001,
+                rcode,
+                0x0001,
+                0x0000,
+                0x0000,
+                0x0000,
+            ),
+            self.question.clone(),
+        );
+        response.edns = self.edns.map(|edns| Edns {
+            dnssec_ok: edns.dnssec_ok,
+            ..Edns::new(EDNS_UDP_PAYLOAD_SIZE)
+        });
+        response
+    }
+
+    /** UDP で返せる応答の大きさ */
+    fn udp_limit(&self) -> usize {
+        match self.edns {
+            Some(edns) => usize::from(edns.udp_payload_size)
+                .clamp(UDP_PAYLOAD_SIZE, EDNS_UDP_PAYLOAD_SIZE.into()),
+            None => UDP_PAYLOAD_SIZE,
+        }
+    }
+}
+
+/** 問い合わせに答えるもの */
+pub trait Handler: Send + Sync + 'static {
+    /** 応答を作る。ヘッダの各セクションの件数は送る前に設定されるので、設定しなくてよい */
+    fn handle(&self, request: &Request) -> Message;
+
+    /** client からのメッセージを受け付けるか。false なら何も返さずに捨てる */
+    fn accepts(&self, _client: SocketAddr) -> bool {
+        true
+    }
+}
+
+/**
+ * 受け取ったメッセージへの応答をワイヤ形式で返す。handler が受け付けないクライアント、ヘッダが読めないもの、
+ * 応答 (QR=1) には答えない。UDP で大きすぎる応答は TC ビットを立てて、質問部だけにする
+ */
+pub fn respond(
+    handler: &impl Handler,
+    query: &[u8],
+    client: SocketAddr,
+    tcp: bool,
+) -> Option<Vec<u8>> {
+    if !handler.accepts(client) {
+        println!("{:?} からのメッセージを捨てます", client);
+        return None;
+    }
+    let header = Header::parse(query.get(..12)?.try_into().ok()?);
+    if header.qr() == 1 {
+        return None;
+    }
+    let request = match Request::parse(query, client) {
+        Some(request) => request,
+        None => {
+            println!("{:?} からの問い合わせを解釈できません", client);
+            return Some(format_error(header).to_bytes());
+        }
+    };
+    println!(
+        "{:?} から問い合わせを受け取りました: {}",
+        client, request.question
+    );
+
+    // 標準の問い合わせ (OPCODE 0) にだけ答える
+    let mut response = if request.header.opcode() == 0 {
+        handler.handle(&request)
+    } else {
+        request.response(false, false, 4)
+    };
+    set_counts(&mut response);
+    let bytes = response.to_bytes();
+    if tcp || bytes.len() <= request.udp_limit() {
+        return Some(bytes);
+    }
+
+    response.header.flags |= 0x0200;
+    response.answers.clear();
+    response.authorities.clear();
+    response.additionals.clear();
+    set_counts(&mut response);
+    Some(response.to_bytes())
+}
+
+/** UDP と TCP の同じポートで待ち受け、それぞれのスレッドで応答する。実際に待ち受けるアドレスを返す */
+pub fn spawn(address: SocketAddr, handler: impl Handler) -> io::Result<SocketAddr> {
+    let socket = UdpSocket::bind(address)?;
+    // ポート 0 が指定されたときも、TCP は UDP と同じポートにする
+    let address = socket.local_addr()?;
+    let listener = TcpListener::bind(address)?;
+    let handler = Arc::new(handler);
+
+    let udp_handler = Arc::clone(&handler);
+    thread::spawn(move || serve_udp(socket, udp_handler));
+    thread::spawn(move || serve_tcp(listener, handler));
+    println!("{:?} で待ち受けます", address);
+    Ok(address)
+}
+
+/**
+ * 問い合わせごとにスレッドを立てて応答する (フルリゾルバの応答は待たされることがある)。
+ * スレッドが上限まで動いているときの問い合わせは捨てる
+ */
+pub fn serve_udp<H: Handler>(socket: UdpSocket, handler: Arc<H>) {
+    let socket = Arc::new(socket);
+    let running = Arc::new(AtomicUsize::new(0));
+    let mut buf = [0; 65535];
+    loop {
+        let (length, client) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) => {
+                println!("受信に失敗しました: {:?}", e);
+                continue;
+            }
+        };
+        let query = buf[..length].to_vec();
+        let socket = Arc::clone(&socket);
+        let handler = Arc::clone(&handler);
+        let spawned = spawn_limited(&running, MAX_HANDLER_THREADS, move || {
+            if let Some(response) = respond(handler.as_ref(), &query, client, false) {
+                if let Err(e) = socket.send_to(&response, client) {
+                    println!("{:?} への送信に失敗しました: {:?}", client, e);
+                }
+            }
+        });
+        if !spawned {
+            println!(
+                "応答のスレッドを立てられないので、{:?} からの問い合わせを捨てます",
+                client
+            );
+        }
+    }
+}
+
+/** 接続ごとにスレッドを立てる。スレッドが上限まで動いているときの接続はすぐに閉じる */
+pub fn serve_tcp<H: Handler>(listener: TcpListener, handler: Arc<H>) {
+    let running = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let handler = Arc::clone(&handler);
+                let spawned = spawn_limited(&running, MAX_HANDLER_THREADS, move || {
+                    if let Err(e) = serve_connection(stream, handler.as_ref()) {
+                        println!("TCP の接続を閉じます: {:?}", e);
+                    }
+                });
+                if !spawned {
+                    println!("応答のスレッドを立てられないので、TCP の接続を閉じます");
+                }
+            }
+            Err(e) => println!("TCP の接続を受け付けられませんでした: {:?}", e),
+        }
+    }
+}
+
+/**
+ * running で数えているスレッドが limit 未満なら、f を新しいスレッドで実行する。
+ * 上限に達しているか、スレッドを立てられなかったら false
+ */
+fn spawn_limited(
+    running: &Arc<AtomicUsize>,
+    limit: usize,
+    f: impl FnOnce() + Send + 'static,
+) -> bool {
+    if running.fetch_add(1, Ordering::SeqCst) >= limit {
+        running.fetch_sub(1, Ordering::SeqCst);
+        return false;
+    }
+    // f が panic しても数を戻す
+    let finished = Finished(Arc::clone(running));
+    let spawned = thread::Builder::new().spawn(move || {
+        let _finished = finished;
+        f();
+    });
+    match spawned {
+        Ok(_) => true,
+        Err(e) => {
+            println!("スレッドを立てられませんでした: {:?}", e);
+            false
+        }
+    }
+}
+
+/** 落とされたときに、動いているスレッドの数を 1 つ減らす */
+struct Finished(Arc<AtomicUsize>);
+
+impl Drop for Finished {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/** 2 バイトの長さが前に付いたメッセージ (RFC 1035 Section 4.2.2) を、接続が閉じられるまで順に処理する */
+fn serve_connection(mut stream: TcpStream, handler: &impl Handler) -> io::Result<()> {
+    stream.set_read_timeout(Some(TCP_IDLE_TIMEOUT))?;
+    let client = stream.peer_addr()?;
+    loop {
+        let mut length = [0; 2];
+        match stream.read_exact(&mut length) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let mut query = vec![0; usize::from(u16::from_be_bytes(length))];
+        stream.read_exact(&mut query)?;
+        if let Some(response) = respond(handler, &query, client, true) {
+            let mut framed = (response.len() as u16).to_be_bytes().to_vec();
+            framed.extend(response);
+            stream.write_all(&framed)?;
+        }
+    }
+}
+
+/** 質問部を読めなかった問い合わせへの FORMERR。質問部は付けない */
+fn format_error(query: Header) -> Message {
+    Message::new(
+        Header::create(
+            query.id,
+            0b1,
+            query.opcode(),
+            0b0,
+            0b0,
+            query.rd(),
+            0b0,
+            0b000,
+            0b0001,
+            0x0000,
+            0x0000,
+            0x0000,
+            0x0000,
+        ),
+        Question::default(),
+    )
+}
+
+fn set_counts(message: &mut Message) {
+    message.header.an_count = message.answers.len() as u16;
+    message.header.ns_count = message.authorities.len() as u16;
+    message.header.ar_count =
+        (message.additionals.len() + usize::from(message.edns.is_some())) as u16;
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::Request;
+    use crate::message::{Header, Question};
+
+    /** 192.0.2.100 からの、ID 7 で RD=1 の IN クラスの問い合わせ */
+    pub fn request(qname: &str, qtype: u16) -> Request {
+        Request {
+            header: Header::create(7, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0),
+            question: Question::new(qname, qtype, 1).unwrap(),
+            edns: None,
+            client: "192.0.2.100:5353".parse().unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{respond, spawn, spawn_limited, Handler, Request};
+    use crate::message::{Edns, Header, Message, Question};
+    use crate::zone_file::parse_record;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream, UdpSocket};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::Duration;
+
+    /** 質問の名前の最初のラベルの数だけ TXT を返す */
+    struct Txt;
+
+    impl Handler for Txt {
+        fn handle(&self, request: &Request) -> Message {
+            let qname = &request.question.qname_dec;
+            let count: usize = qname.split('.').next().unwrap().parse().unwrap();
+            let mut response = request.response(true, false, 0);
+            for i in 0..count {
+                let text = format!("{}. 60 IN TXT \"{:040}\"", qname, i);
+                response.answers.push(parse_record(&text).unwrap());
+            }
+            response
+        }
+    }
+
+    fn client() -> SocketAddr {
+        "192.0.2.1:5353".parse().unwrap()
+    }
+
+    fn query(opcode: u8, qname: &str, edns: Option<Edns>) -> Vec<u8> {
+        let mut message = Message::new(
+            Header::create(
+                0xbeef,
+                0,
+                opcode,
+                0,
+                0,
+                1,
+                0,
+                0b001,
+                0,
+                1,
+                0,
+                0,
+                edns.is_some().into(),
+            ),
+            Question::new(qname, 16, 1).unwrap(),
+        );
+        message.edns = edns;
+        message.to_bytes()
+    }
+
+    fn respond_to(query: &[u8], tcp: bool) -> Message {
+        Message::parse(&respond(&Txt, query, client(), tcp).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn handler_threads_are_limited() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let (release, released) = mpsc::channel::<()>();
+        assert!(spawn_limited(&running, 1, move || {
+            released.recv().unwrap();
+        }));
+        assert!(!spawn_limited(&running, 1, || {}));
+        assert_eq!(running.load(Ordering::SeqCst), 1);
+
+        release.send(()).unwrap();
+        while running.load(Ordering::SeqCst) > 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        // panic したスレッドも数えなくなる
+        assert!(spawn_limited(&running, 1, || panic!("handler failed")));
+        while running.load(Ordering::SeqCst) > 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(spawn_limited(&running, 1, || {}));
+    }
+
+    #[test]
+    fn responses_echo_the_query() {
+        let response = respond_to(&query(0, "1.Example", None), false);
+        assert_eq!(response.header.id, 0xbeef);
+        assert_eq!(response.header.qr(), 1);
+        assert_eq!(response.header.aa(), 1);
+        assert_eq!(response.header.rd(), 1);
+        assert_eq!(response.header.z(), 0b001);
+        assert_eq!(response.header.qd_count, 1);
+        assert_eq!(response.header.an_count, 1);
+        assert_eq!(response.question.qname_dec, "1.Example");
+        assert_eq!(response.edns, None);
+
+        let edns = Edns {
+            udp_payload_size: 4096,
+            dnssec_ok: true,
+        };
+        let response = respond_to(&query(0, "1.example", Some(edns)), false);
+        assert_eq!(
+            response.edns,
+            Some(Edns {
+                udp_payload_size: 1232,
+                dnssec_ok: true
+            })
+        );
+    }
+
+    #[test]
+    fn large_responses_are_truncated_over_udp() {
+        let response = respond_to(&query(0, "10.example", None), false);
+        assert_eq!(response.header.tc(), 1);
+        assert_eq!(response.header.qd_count, 1);
+        assert!(response.answers.is_empty());
+
+        // EDNS0 で大きな UDP の応答を受け取れるクライアントには、そのまま返す
+        let edns = Some(Edns::new(4096));
+        let response = respond_to(&query(0, "10.example", edns), false);
+        assert_eq!(response.header.tc(), 0);
+        assert_eq!(response.answers.len(), 10);
+        // ただし応答は EDNS_UDP_PAYLOAD_SIZE までにとどめる
+        let response = respond_to(&query(0, "30.example", edns), false);
+        assert_eq!(response.header.tc(), 1);
+
+        let response = respond_to(&query(0, "30.example", None), true);
+        assert_eq!(response.header.tc(), 0);
+        assert_eq!(response.answers.len(), 30);
+    }
+
+    #[test]
+    fn malformed_queries() {
+        let valid = query(0, "1.example", None);
+        assert!(respond(&Txt, &valid[..11], client(), false).is_none());
+        // 応答には応答しない
+        let mut response = valid.clone();
+        response[2] |= 0x80;
+        assert!(respond(&Txt, &response, client(), false).is_none());
+
+        // 質問部が途中で切れている
+        let response = respond_to(&valid[..valid.len() - 1], false);
+        assert_eq!(response.header.id, 0xbeef);
+        assert_eq!(response.header.rcode(), 1);
+        assert_eq!(response.header.qd_count, 0);
+        // 質問部が 2 つある
+        let mut two_questions = valid.clone();
+        two_questions[5] = 2;
+        assert_eq!(respond_to(&two_questions, false).header.rcode(), 1);
+
+        // STATUS などの OPCODE には NOTIMP
+        let response = respond_to(&query(2, "1.example", None), false);
+        assert_eq!(response.header.rcode(), 4);
+        assert_eq!(response.header.opcode(), 2);
+        assert!(response.answers.is_empty());
+    }
+
+    #[test]
+    fn serves_udp_and_tcp_on_the_same_port() {
+        let address = spawn("127.0.0.1:0".parse().unwrap(), Txt).unwrap();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        socket
+            .send_to(&query(0, "2.example", None), address)
+            .unwrap();
+        let mut buf = [0; 512];
+        let (length, _) = socket.recv_from(&mut buf).unwrap();
+        assert_eq!(Message::parse(&buf[..length]).unwrap().answers.len(), 2);
+
+        // 1 つの接続で続けて問い合わせられる
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        for count in [30, 3] {
+            let query = query(0, &format!("{}.example", count), None);
+            let mut framed = (query.len() as u16).to_be_bytes().to_vec();
+            framed.extend(query);
+            stream.write_all(&framed).unwrap();
+
+            let mut length = [0; 2];
+            stream.read_exact(&mut length).unwrap();
+            let mut response = vec![0; usize::from(u16::from_be_bytes(length))];
+            stream.read_exact(&mut response).unwrap();
+            assert_eq!(Message::parse(&response).unwrap().answers.len(), count);
+        }
+    }
+}
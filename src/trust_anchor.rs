@@ -0,0 +1,485 @@
+use data_encoding::{BASE64, HEXUPPER_PERMISSIVE};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::dnssec::{Dnskey, Ds};
+use crate::message::{presentation_name, Resource};
+use crate::validator;
+
+/** ルートゾーンの KSK-2017 と KSK-2024 (IANA の root-anchors.xml) */
+const ROOT_ANCHORS: &str = "\
+. IN DS 20326 8 2 E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D
+. IN DS 38696 8 2 683D2D0ACB8C9B712A1948B27F741219298D0A450D612C483AF444A4C0FB2B16
+";
+
+/** 新しい鍵を信頼するまで待つ時間 (RFC 5011 Section 2.4.1 の Add Hold-Down Time) */
+const ADD_HOLD_DOWN: u32 = 30 * 86400;
+
+/** 失効した鍵の記録を消すまでの時間 (RFC 5011 Section 2.4.2 の Remove Hold-Down Time) */
+const REMOVE_HOLD_DOWN: u32 = 30 * 86400;
+
+/** RFC 5011 Section 4 の鍵の状態 (Start と Removed は記録しない) */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyState {
+    /** 初めて見つけてから Add Hold-Down Time を待っている */
+    AddPend,
+    Valid,
+    /** 信頼しているが、DNSKEY の RRset から消えている */
+    Missing,
+    /** REVOKE ビットを立てた自身の署名で失効した */
+    Revoked,
+}
+
+impl KeyState {
+    fn name(&self) -> &'static str {
+        match self {
+            KeyState::AddPend => "ADDPEND",
+            KeyState::Valid => "VALID",
+            KeyState::Missing => "MISSING",
+            KeyState::Revoked => "REVOKED",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [
+            KeyState::AddPend,
+            KeyState::Valid,
+            KeyState::Missing,
+            KeyState::Revoked,
+        ]
+        .into_iter()
+        .find(|state| state.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/** RFC 5011 で管理している鍵 */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManagedKey {
+    pub dnskey: Dnskey,
+    pub state: KeyState,
+    /** 今の状態になった時刻 (1970 年からの秒) */
+    pub since: u32,
+}
+
+/** DNSSEC の検証の起点となるゾーンと、その鍵 */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrustAnchor {
+    /** 小文字・末尾のドットなし (ルートは空文字列) */
+    pub zone: String,
+    /** 設定された DS。指している鍵を見つけたら keys に移す */
+    pub ds: Vec<Ds>,
+    pub keys: Vec<ManagedKey>,
+}
+
+impl TrustAnchor {
+    /**
+     * DS と DNSKEY をマスターファイル形式で並べたもの (unbound の trust-anchor-file と同じ) を読む。TTL と CLASS は省略できる。
+     * DNSKEY の行末のコメントに "state=VALID since=1700000000" のように RFC 5011 の状態を書ける (省略すると VALID)
+     */
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut zone: Option<String> = None;
+        let mut ds = Vec::new();
+        let mut keys = Vec::new();
+
+        for line in text.lines() {
+            let (line, comment) = line.split_once(';').unwrap_or((line, ""));
+            let mut words = line.split_whitespace();
+            let owner = match words.next() {
+                Some(owner) => owner.trim_end_matches('.').to_ascii_lowercase(),
+                None => continue,
+            };
+            let mut words = words.skip_while(|word| {
+                word.chars().all(|c| c.is_ascii_digit()) || word.eq_ignore_ascii_case("IN")
+            });
+            let rr_type = words.next()?.to_ascii_uppercase();
+            if rr_type != "DS" && rr_type != "DNSKEY" {
+                continue;
+            }
+            // すべてのレコードは同じゾーンのものでなければならない
+            if *zone.get_or_insert_with(|| owner.clone()) != owner {
+                return None;
+            }
+
+            if rr_type == "DS" {
+                ds.push(Ds {
+                    key_tag: words.next()?.parse().ok()?,
+                    algorithm: words.next()?.parse().ok()?,
+                    digest_type: words.next()?.parse().ok()?,
+                    // ダイジェストは空白で区切られていることがある
+                    digest: HEXUPPER_PERMISSIVE
+                        .decode(words.collect::<String>().as_bytes())
+                        .ok()?,
+                });
+                continue;
+            }
+
+            let dnskey = Dnskey {
+                flags: words.next()?.parse().ok()?,
+                protocol: words.next()?.parse().ok()?,
+                algorithm: words.next()?.parse().ok()?,
+                public_key: BASE64.decode(words.collect::<String>().as_bytes()).ok()?,
+            };
+            let mut key = ManagedKey {
+                dnskey,
+                state: KeyState::Valid,
+                since: 0,
+            };
+            for (name, value) in comment.split_whitespace().filter_map(|w| w.split_once('=')) {
+                match name {
+                    "state" => key.state = KeyState::from_name(value)?,
+                    "since" => key.since = value.parse().ok()?,
+                    _ => {}
+                }
+            }
+            keys.push(key);
+        }
+
+        Some(Self {
+            zone: zone?,
+            ds,
+            keys,
+        })
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "トラストアンカーを読み込めませんでした",
+            )
+        })
+    }
+
+    /** parse で読める形式で書き出す */
+    pub fn to_state(&self) -> String {
+        let owner = presentation_name(&self.zone);
+        let mut text = "; RFC 5011 で管理しているトラストアンカー\n".to_string();
+        for ds in &self.ds {
+            text.push_str(&format!("{} IN DS {}\n", owner, ds));
+        }
+        for key in &self.keys {
+            text.push_str(&format!(
+                "{} IN DNSKEY {} ; state={} since={}\n",
+                owner,
+                key.dnskey,
+                key.state.name(),
+                key.since
+            ));
+        }
+        text
+    }
+
+    /** 書きかけのファイルが残らないよう、一時ファイルに書いてから置き換える */
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, self.to_state())?;
+        fs::rename(&temporary, path)
+    }
+
+    /** 検証の起点として信頼する鍵を指す DS (設定された DS と、VALID か MISSING の鍵のもの) */
+    pub fn trusted_ds(&self) -> Vec<Ds> {
+        let managed = self
+            .keys
+            .iter()
+            .filter(|key| matches!(key.state, KeyState::Valid | KeyState::Missing))
+            .filter_map(|key| {
+                Some(Ds {
+                    key_tag: key.dnskey.key_tag(),
+                    algorithm: key.dnskey.algorithm,
+                    digest_type: 2,
+                    digest: validator::ds_digest(&self.zone, &key.dnskey, 2)?,
+                })
+            });
+        self.ds.iter().cloned().chain(managed).collect()
+    }
+
+    /**
+     * 信頼している鍵で検証済みの DNSKEY の RRset (answers) を見て、RFC 5011 に従って鍵の状態を進める。
+     * 状態が変わったら true を返す
+     */
+    pub fn update(&mut self, answers: &[Resource], now: u32) -> bool {
+        let rrsets = validator::rrsets(answers);
+        let rrset = match rrsets
+            .iter()
+            .find(|rrset| rrset.rr_type == 48 && rrset.name == self.zone)
+        {
+            Some(rrset) => rrset,
+            None => return false,
+        };
+        let before = (self.ds.clone(), self.keys.clone());
+        let dnskeys: Vec<&Dnskey> = rrset
+            .records
+            .iter()
+            .filter_map(|record| record.dnskey.as_ref())
+            .filter(|dnskey| dnskey.is_zone_key() && dnskey.is_sep())
+            .collect();
+
+        // 設定された DS が指す鍵は、そのまま信頼する鍵として管理を始める
+        for dnskey in &dnskeys {
+            if let Some(index) = self
+                .ds
+                .iter()
+                .position(|ds| validator::ds_matches(&self.zone, dnskey, ds))
+            {
+                self.ds.remove(index);
+                if self.find(dnskey).is_none() {
+                    self.keys.push(ManagedKey {
+                        dnskey: (*dnskey).clone(),
+                        state: KeyState::Valid,
+                        since: now,
+                    });
+                }
+            }
+        }
+
+        for dnskey in &dnskeys {
+            if dnskey.is_revoked() {
+                // 失効は、REVOKE ビットを立てた鍵自身の署名があるときだけ認める
+                if let Some(index) = self.find(dnskey) {
+                    let key = &mut self.keys[index];
+                    if key.state != KeyState::Revoked
+                        && validator::verify_revocation(rrset, dnskey, now)
+                    {
+                        println!(
+                            "トラストアンカーの鍵 {} が失効しました",
+                            key.dnskey.key_tag()
+                        );
+                        *key = ManagedKey {
+                            dnskey: (*dnskey).clone(),
+                            state: KeyState::Revoked,
+                            since: now,
+                        };
+                    }
+                }
+                continue;
+            }
+
+            match self.find(dnskey) {
+                None => {
+                    println!(
+                        "トラストアンカーの新しい鍵 {} を見つけました",
+                        dnskey.key_tag()
+                    );
+                    self.keys.push(ManagedKey {
+                        dnskey: (*dnskey).clone(),
+                        state: KeyState::AddPend,
+                        since: now,
+                    });
+                }
+                Some(index) => {
+                    let key = &mut self.keys[index];
+                    let promote = match key.state {
+                        KeyState::AddPend => now.saturating_sub(key.since) >= ADD_HOLD_DOWN,
+                        KeyState::Missing => true,
+                        KeyState::Valid | KeyState::Revoked => false,
+                    };
+                    if promote {
+                        println!("トラストアンカーの鍵 {} を信頼します", dnskey.key_tag());
+                        key.state = KeyState::Valid;
+                        key.since = now;
+                    }
+                }
+            }
+        }
+
+        // RRset から消えた鍵
+        let present = |key: &ManagedKey| dnskeys.iter().any(|dnskey| same_key(&key.dnskey, dnskey));
+        self.keys.retain_mut(|key| {
+            if present(key) {
+                return true;
+            }
+            match key.state {
+                KeyState::AddPend => false,
+                KeyState::Valid => {
+                    key.state = KeyState::Missing;
+                    key.since = now;
+                    true
+                }
+                KeyState::Missing => true,
+                KeyState::Revoked => now.saturating_sub(key.since) < REMOVE_HOLD_DOWN,
+            }
+        });
+
+        (self.ds.clone(), self.keys.clone()) != before
+    }
+
+    fn find(&self, dnskey: &Dnskey) -> Option<usize> {
+        self.keys
+            .iter()
+            .position(|key| same_key(&key.dnskey, dnskey))
+    }
+}
+
+impl Default for TrustAnchor {
+    fn default() -> Self {
+        Self::parse(ROOT_ANCHORS).unwrap()
+    }
+}
+
+/** REVOKE ビットの有無を除いて同じ鍵か */
+fn same_key(a: &Dnskey, b: &Dnskey) -> bool {
+    a.flags | 0x0080 == b.flags | 0x0080
+        && a.protocol == b.protocol
+        && a.algorithm == b.algorithm
+        && a.public_key == b.public_key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyState, TrustAnchor, ADD_HOLD_DOWN, REMOVE_HOLD_DOWN};
+    use crate::message::Resource;
+    use crate::validator::testing::SigningKey;
+    use crate::validator::{self, now};
+
+    #[test]
+    fn builtin_root_anchor() {
+        let anchor = TrustAnchor::default();
+        assert_eq!(anchor.zone, "");
+        assert_eq!(
+            anchor.ds.iter().map(|ds| ds.key_tag).collect::<Vec<u16>>(),
+            vec![20326, 38696]
+        );
+        assert_eq!(anchor.ds[0].digest.len(), 32);
+    }
+
+    #[test]
+    fn custom_trust_anchor() {
+        let anchor = TrustAnchor::parse(
+            "; lab anchor\n\
+             Lab. 3600 IN DS 60485 5 1 2BB183AF5F22588179A53B0A 98631FAD1A292118\n\
+             lab. DS 60485 5 2 d4b7d520e7bb5f0f67674a0cceb1e3e0614b93c4f9e99b8383f6a1e4469da50a\n",
+        )
+        .unwrap();
+        assert_eq!(anchor.zone, "lab");
+        assert_eq!(anchor.ds.len(), 2);
+        assert_eq!(anchor.ds[0].digest.len(), 20);
+        assert_eq!(anchor.ds[1].digest_type, 2);
+
+        assert_eq!(TrustAnchor::parse("a. DS 1 8 2 00\nb. DS 1 8 2 00\n"), None);
+        assert_eq!(TrustAnchor::parse(""), None);
+    }
+
+    /** signers のすべてで署名した、keys からなる DNSKEY の RRset */
+    fn dnskey_rrset(keys: &[&SigningKey], signers: &[&SigningKey]) -> Vec<Resource> {
+        let mut records: Vec<Resource> = keys.iter().map(|key| key.dnskey_record()).collect();
+        let signatures: Vec<Resource> = signers.iter().map(|key| key.sign(&records)).collect();
+        records.extend(signatures);
+        records
+    }
+
+    fn states(anchor: &TrustAnchor) -> Vec<(u16, KeyState)> {
+        anchor
+            .keys
+            .iter()
+            .map(|key| (key.dnskey.key_tag(), key.state))
+            .collect()
+    }
+
+    #[test]
+    fn rfc5011_rollover() {
+        let mut old = SigningKey::ed25519("", 1);
+        let new = SigningKey::ed25519("", 2);
+        let mut anchor = TrustAnchor {
+            zone: "".to_string(),
+            ds: vec![old.ds()],
+            keys: Vec::new(),
+        };
+        // 失効の署名を検証する時点が現在時刻になるようにする
+        let start = now() - ADD_HOLD_DOWN;
+
+        // 設定された DS の鍵は信頼し、新しい鍵は保留する
+        assert!(anchor.update(&dnskey_rrset(&[&old, &new], &[&old]), start));
+        assert!(anchor.ds.is_empty());
+        assert_eq!(
+            states(&anchor),
+            vec![
+                (old.dnskey.key_tag(), KeyState::Valid),
+                (new.dnskey.key_tag(), KeyState::AddPend)
+            ]
+        );
+        assert_eq!(anchor.trusted_ds(), vec![old.ds()]);
+
+        // Add Hold-Down Time が過ぎるまでは信頼しない
+        let later = start + ADD_HOLD_DOWN / 2;
+        assert!(!anchor.update(&dnskey_rrset(&[&old, &new], &[&old]), later));
+        let later = start + ADD_HOLD_DOWN;
+        assert!(anchor.update(&dnskey_rrset(&[&old, &new], &[&old]), later));
+        assert_eq!(anchor.trusted_ds(), vec![old.ds(), new.ds()]);
+
+        // 古い鍵を失効させる
+        let old_tag = old.dnskey.key_tag();
+        old.revoke();
+        anchor.update(&dnskey_rrset(&[&old, &new], &[&old, &new]), later);
+        assert_eq!(
+            states(&anchor),
+            vec![
+                (old.dnskey.key_tag(), KeyState::Revoked),
+                (new.dnskey.key_tag(), KeyState::Valid)
+            ]
+        );
+        assert_eq!(anchor.trusted_ds(), vec![new.ds()]);
+        assert_ne!(old.dnskey.key_tag(), old_tag);
+
+        // 失効した鍵の記録は Remove Hold-Down Time の後に消える
+        anchor.update(&dnskey_rrset(&[&new], &[&new]), later + 1);
+        assert_eq!(anchor.keys.len(), 2);
+        anchor.update(&dnskey_rrset(&[&new], &[&new]), later + REMOVE_HOLD_DOWN);
+        assert_eq!(
+            states(&anchor),
+            vec![(new.dnskey.key_tag(), KeyState::Valid)]
+        );
+    }
+
+    #[test]
+    fn rfc5011_ignores_unsigned_revocation_and_tracks_missing_keys() {
+        let mut old = SigningKey::ed25519("", 1);
+        let other = SigningKey::ed25519("", 3);
+        let mut anchor = TrustAnchor {
+            zone: "".to_string(),
+            ds: vec![old.ds(), other.ds()],
+            keys: Vec::new(),
+        };
+        let start = now();
+        anchor.update(&dnskey_rrset(&[&old, &other], &[&old]), start);
+
+        // RRset から消えた鍵も、失効するまでは信頼し続ける
+        anchor.update(&dnskey_rrset(&[&other], &[&other]), start);
+        assert_eq!(anchor.keys[0].state, KeyState::Missing);
+        assert_eq!(anchor.trusted_ds().len(), 2);
+
+        // 失効した鍵自身の署名がなければ、REVOKE ビットを信じない
+        old.revoke();
+        let records = dnskey_rrset(&[&old, &other], &[&other]);
+        let rrsets = validator::rrsets(&records);
+        assert!(!validator::verify_revocation(
+            &rrsets[0],
+            &old.dnskey,
+            start
+        ));
+        anchor.update(&records, start);
+        assert_eq!(anchor.keys[0].state, KeyState::Missing);
+    }
+
+    #[test]
+    fn state_file_roundtrip() {
+        let key = SigningKey::ed25519("lab", 1);
+        let mut anchor = TrustAnchor {
+            zone: "lab".to_string(),
+            ds: vec![SigningKey::ed25519("lab", 2).ds()],
+            keys: Vec::new(),
+        };
+        anchor.update(&dnskey_rrset(&[&key], &[&key]), 1700000000);
+        assert_eq!(anchor.keys[0].state, KeyState::AddPend);
+
+        let text = anchor.to_state();
+        assert!(text.contains("lab. IN DNSKEY 257 3 15 "));
+        assert!(text.contains("; state=ADDPEND since=1700000000"));
+        assert_eq!(TrustAnchor::parse(&text), Some(anchor.clone()));
+
+        let path = std::env::temp_dir().join(format!("trust-anchor-{}", std::process::id()));
+        anchor.save(&path).unwrap();
+        assert_eq!(TrustAnchor::load(&path).unwrap(), anchor);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,507 @@
+use ring::digest;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::dnssec::{Dnskey, Ds, Rrsig};
+use crate::message::{encode_name, escape_label, is_subdomain, name_labels, Resource};
+
+/** DNSSEC の検証結果 (RFC 4033 Section 5) */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Security {
+    /** トラストアンカーから署名の連鎖をたどって検証できた */
+    Secure,
+    /** 途中の委任に DS がないことがわかり、署名されていない */
+    Insecure,
+    /** 署名されているはずなのに検証できなかった */
+    Bogus,
+    /** 検証していないか、トラストアンカーから判断できなかった */
+    Indeterminate,
+}
+
+/** 所有者名 (小文字) とタイプが同じレコードの集まりと、それを対象とする RRSIG */
+#[derive(Debug)]
+pub struct RrSet<'a> {
+    pub name: String,
+    pub rr_type: u16,
+    pub records: Vec<&'a Resource>,
+    pub rrsigs: Vec<&'a Rrsig>,
+}
+
+/** セクションのレコードを RRset にまとめる。RRSIG は対象の RRset に振り分ける */
+pub fn rrsets(resources: &[Resource]) -> Vec<RrSet<'_>> {
+    let mut rrsets: Vec<RrSet> = Vec::new();
+    for resource in resources.iter().filter(|r| r.rr_type != 46) {
+        let name = resource.name.to_ascii_lowercase();
+        match rrsets
+            .iter_mut()
+            .find(|rrset| rrset.name == name && rrset.rr_type == resource.rr_type)
+        {
+            Some(rrset) => rrset.records.push(resource),
+            None => rrsets.push(RrSet {
+                name,
+                rr_type: resource.rr_type,
+                records: vec![resource],
+                rrsigs: Vec::new(),
+            }),
+        }
+    }
+
+    for resource in resources {
+        let rrsig = match &resource.rrsig {
+            Some(rrsig) => rrsig,
+            None => continue,
+        };
+        if let Some(rrset) = rrsets.iter_mut().find(|rrset| {
+            rrset.name.eq_ignore_ascii_case(&resource.name) && rrset.rr_type == rrsig.type_covered
+        }) {
+            rrset.rrsigs.push(rrsig);
+        }
+    }
+    rrsets
+}
+
+/** 現在時刻 (1970 年からの秒) */
+pub fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as u32)
+        .unwrap_or(0)
+}
+
+/** 検証できる署名アルゴリズム (RSA/SHA-256, ECDSA P-256/SHA-256, ECDSA P-384/SHA-384, Ed25519) */
+pub fn is_supported_algorithm(algorithm: u8) -> bool {
+    matches!(algorithm, 8 | 13 | 14 | 15)
+}
+
+/** DS のアルゴリズムとダイジェストの種類がどちらも検証できるものか */
+pub fn is_supported_ds(ds: &Ds) -> bool {
+    is_supported_algorithm(ds.algorithm) && matches!(ds.digest_type, 1 | 2 | 4)
+}
+
+/** ゾーン zone の dnskey から DS のダイジェストを計算する (RFC 4034 Section 5.1.4) */
+pub fn ds_digest(zone: &str, dnskey: &Dnskey, digest_type: u8) -> Option<Vec<u8>> {
+    let algorithm = match digest_type {
+        1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        2 => &digest::SHA256,
+        4 => &digest::SHA384,
+        _ => return None,
+    };
+    let mut data = encode_name(&zone.to_ascii_lowercase())?;
+    data.extend(dnskey.to_rdata());
+    Some(digest::digest(algorithm, &data).as_ref().to_vec())
+}
+
+/** ds がゾーン zone の dnskey を指しているか */
+pub fn ds_matches(zone: &str, dnskey: &Dnskey, ds: &Ds) -> bool {
+    ds.key_tag == dnskey.key_tag()
+        && ds.algorithm == dnskey.algorithm
+        && ds_digest(zone, dnskey, ds.digest_type).as_ref() == Some(&ds.digest)
+}
+
+/** rrset を rrsig と dnskey で検証する (RFC 4035 Section 5.3)。失効した鍵は使わない */
+pub fn verify_rrset(rrset: &RrSet, rrsig: &Rrsig, dnskey: &Dnskey, now: u32) -> bool {
+    !dnskey.is_revoked() && verify_signed_by(rrset, rrsig, dnskey, now)
+}
+
+/** REVOKE ビットを立てた dnskey 自身が、DNSKEY の rrset に署名しているか (RFC 5011 Section 2.1) */
+pub fn verify_revocation(rrset: &RrSet, dnskey: &Dnskey, now: u32) -> bool {
+    rrset.rr_type == 48
+        && dnskey.is_revoked()
+        && rrset
+            .rrsigs
+            .iter()
+            .any(|rrsig| verify_signed_by(rrset, rrsig, dnskey, now))
+}
+
+fn verify_signed_by(rrset: &RrSet, rrsig: &Rrsig, dnskey: &Dnskey, now: u32) -> bool {
+    if rrsig.type_covered != rrset.rr_type
+        || rrsig.algorithm != dnskey.algorithm
+        || rrsig.key_tag != dnskey.key_tag()
+        || dnskey.protocol != 3
+        || !dnskey.is_zone_key()
+    {
+        return false;
+    }
+    // 署名者は所有者名と同じか、その祖先でなければならない
+    let signer = rrsig.signer_name.to_ascii_lowercase();
+    if !is_subdomain(&rrset.name, &signer) {
+        return false;
+    }
+    // 有効期間は通し番号の算術 (RFC 1982) で比べる
+    if (now.wrapping_sub(rrsig.inception) as i32) < 0
+        || (rrsig.expiration.wrapping_sub(now) as i32) < 0
+    {
+        return false;
+    }
+
+    match signed_data(rrset, rrsig) {
+        Some(data) => verify_signature(dnskey, &data, &rrsig.signature),
+        None => false,
+    }
+}
+
+/** rrset のいずれかの RRSIG が、ゾーン signer の keys のいずれかで検証できるか */
+pub fn verify_with_keys(rrset: &RrSet, keys: &[Dnskey], signer: &str, now: u32) -> bool {
+    rrset
+        .rrsigs
+        .iter()
+        .filter(|rrsig| rrsig.signer_name.eq_ignore_ascii_case(signer))
+        .any(|rrsig| {
+            keys.iter()
+                .any(|dnskey| verify_rrset(rrset, rrsig, dnskey, now))
+        })
+}
+
+/** ゾーン zone の DNSKEY の応答を ds で認証する。DS が指す鍵で署名されていれば、その中の鍵を返す */
+pub fn validate_dnskeys(
+    zone: &str,
+    answers: &[Resource],
+    ds: &[Ds],
+    now: u32,
+) -> Option<Vec<Dnskey>> {
+    let zone = zone.to_ascii_lowercase();
+    let rrsets = rrsets(answers);
+    let rrset = rrsets
+        .iter()
+        .find(|rrset| rrset.rr_type == 48 && rrset.name == zone)?;
+    let keys: Vec<Dnskey> = rrset
+        .records
+        .iter()
+        .filter_map(|record| record.dnskey.clone())
+        .collect();
+
+    let trusted: Vec<Dnskey> = keys
+        .iter()
+        .filter(|dnskey| ds.iter().any(|ds| ds_matches(&zone, dnskey, ds)))
+        .cloned()
+        .collect();
+    if verify_with_keys(rrset, &trusted, &zone, now) {
+        Some(keys)
+    } else {
+        None
+    }
+}
+
+/** 署名の対象となるデータ: RRSIG の RDATA (署名を除く) に、正規形のレコードを正規の順に並べたもの */
+fn signed_data(rrset: &RrSet, rrsig: &Rrsig) -> Option<Vec<u8>> {
+    let labels = name_labels(&rrset.name);
+    let labels = match labels.first() {
+        Some(first) if first == b"*" => &labels[1..],
+        _ => &labels[..],
+    };
+    let signed_labels = usize::from(rrsig.labels);
+    // ワイルドカードから合成されたレコードは、ワイルドカードの名前で署名されている
+    let owner = match signed_labels.cmp(&labels.len()) {
+        std::cmp::Ordering::Greater => return None,
+        std::cmp::Ordering::Equal => rrset.name.clone(),
+        std::cmp::Ordering::Less => ["*".to_string()]
+            .into_iter()
+            .chain(
+                labels[labels.len() - signed_labels..]
+                    .iter()
+                    .map(|label| escape_label(label)),
+            )
+            .collect::<Vec<String>>()
+            .join("."),
+    };
+
+    let mut records: Vec<(Vec<u8>, &Resource)> = rrset
+        .records
+        .iter()
+        .map(|record| (record.canonical_rdata(), *record))
+        .collect();
+    records.sort_by(|a, b| a.0.cmp(&b.0));
+    records.dedup_by(|a, b| a.0 == b.0);
+
+    let mut data = rrsig.to_rdata_without_signature();
+    for (_, record) in records {
+        let record = Resource {
+            name: owner.clone(),
+            ..record.clone()
+        };
+        data.extend(record.to_canonical_byte(rrsig.original_ttl));
+    }
+    Some(data)
+}
+
+fn verify_signature(dnskey: &Dnskey, data: &[u8], signature: &[u8]) -> bool {
+    let key = &dnskey.public_key;
+    match dnskey.algorithm {
+        8 => {
+            // 公開鍵の形式は RFC 3110 Section 2 (指数の長さ, 指数, 法)
+            let (exponent_length, position) = match key.first() {
+                Some(0) if key.len() > 3 => (usize::from(key[1]) * 256 + usize::from(key[2]), 3),
+                Some(length) => (usize::from(*length), 1),
+                None => return false,
+            };
+            if key.len() <= position + exponent_length {
+                return false;
+            }
+            let components = RsaPublicKeyComponents {
+                n: &key[position + exponent_length..],
+                e: &key[position..position + exponent_length],
+            };
+            // 1024 ビットの ZSK がまだ使われているので、短い鍵も受け付ける
+            components
+                .verify(
+                    &signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY,
+                    data,
+                    signature,
+                )
+                .is_ok()
+        }
+        13 | 14 => {
+            let algorithm = if dnskey.algorithm == 13 {
+                &signature::ECDSA_P256_SHA256_FIXED
+            } else {
+                &signature::ECDSA_P384_SHA384_FIXED
+            };
+            // DNSKEY には圧縮されていない点の X と Y だけが入っている (RFC 6605 Section 4)
+            let mut point = vec![0x04];
+            point.extend(key);
+            UnparsedPublicKey::new(algorithm, point)
+                .verify(data, signature)
+                .is_ok()
+        }
+        15 => UnparsedPublicKey::new(&signature::ED25519, key)
+            .verify(data, signature)
+            .is_ok(),
+        _ => false,
+    }
+}
+
+/** テスト用に RRset へ署名する */
+#[cfg(test)]
+pub(crate) mod testing {
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, Ed25519KeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+
+    use super::ds_digest;
+    use crate::dnssec::{Dnskey, Ds, Rrsig};
+    use crate::message::{encode_name, label_count, Resource};
+
+    pub enum Signer {
+        Ed25519(Ed25519KeyPair),
+        EcdsaP256(EcdsaKeyPair),
+    }
+
+    /** ゾーンの署名鍵 (KSK と ZSK を兼ねる) */
+    pub struct SigningKey {
+        pub zone: String,
+        pub dnskey: Dnskey,
+        signer: Signer,
+    }
+
+    impl SigningKey {
+        /** seed から Ed25519 の鍵を作る */
+        pub fn ed25519(zone: &str, seed: u8) -> Self {
+            let pair = Ed25519KeyPair::from_seed_unchecked(&[seed; 32]).unwrap();
+            let public_key = pair.public_key().as_ref().to_vec();
+            Self::new(zone, 15, &public_key, Signer::Ed25519(pair))
+        }
+
+        pub fn ecdsa_p256(zone: &str) -> Self {
+            let rng = SystemRandom::new();
+            let pkcs8 =
+                EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+            let pair =
+                EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+                    .unwrap();
+            // 先頭の 0x04 (非圧縮) を除く
+            let public_key = pair.public_key().as_ref()[1..].to_vec();
+            Self::new(zone, 13, &public_key, Signer::EcdsaP256(pair))
+        }
+
+        fn new(zone: &str, algorithm: u8, public_key: &[u8], signer: Signer) -> Self {
+            Self {
+                zone: zone.to_string(),
+                dnskey: Dnskey {
+                    flags: 257,
+                    protocol: 3,
+                    algorithm,
+                    public_key: public_key.to_vec(),
+                },
+                signer,
+            }
+        }
+
+        /** REVOKE ビットを立てる (キータグも変わる) */
+        pub fn revoke(&mut self) {
+            self.dnskey.flags |= 0x0080;
+        }
+
+        pub fn ds(&self) -> Ds {
+            Ds {
+                key_tag: self.dnskey.key_tag(),
+                algorithm: self.dnskey.algorithm,
+                digest_type: 2,
+                digest: ds_digest(&self.zone, &self.dnskey, 2).unwrap(),
+            }
+        }
+
+        /** DNSKEY レコード */
+        pub fn dnskey_record(&self) -> Resource {
+            resource(&self.zone, 48, &self.dnskey.to_rdata())
+        }
+
+        /** records (同じ RRset) に対する RRSIG レコード */
+        pub fn sign(&self, records: &[Resource]) -> Resource {
+            let owner = &records[0].name;
+            let mut rrsig = Rrsig {
+                type_covered: records[0].rr_type,
+                algorithm: self.dnskey.algorithm,
+                labels: label_count(owner) as u8,
+                original_ttl: records[0].ttl,
+                expiration: super::now() + 86400,
+                inception: super::now() - 3600,
+                key_tag: self.dnskey.key_tag(),
+                signer_name: self.zone.clone(),
+                signature: Vec::new(),
+            };
+            if owner.starts_with("*.") {
+                rrsig.labels -= 1;
+            }
+            let rrset = super::rrsets(records);
+            let data = super::signed_data(&rrset[0], &rrsig).unwrap();
+            rrsig.signature = match &self.signer {
+                Signer::Ed25519(pair) => pair.sign(&data).as_ref().to_vec(),
+                Signer::EcdsaP256(pair) => pair
+                    .sign(&SystemRandom::new(), &data)
+                    .unwrap()
+                    .as_ref()
+                    .to_vec(),
+            };
+            resource(owner, 46, &rrsig.to_rdata())
+        }
+    }
+
+    /** ワイヤ形式から解析したレコード (TTL は 3600) */
+    pub fn resource(name: &str, rr_type: u16, rdata: &[u8]) -> Resource {
+        let mut bytes = encode_name(name).unwrap();
+        bytes.extend(rr_type.to_be_bytes());
+        bytes.extend(1u16.to_be_bytes());
+        bytes.extend(3600u32.to_be_bytes());
+        bytes.extend((rdata.len() as u16).to_be_bytes());
+        bytes.extend(rdata);
+        Resource::parse(&bytes, &bytes, 1).unwrap().remove(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::{resource, SigningKey};
+    use super::{ds_matches, rrsets, validate_dnskeys, verify_rrset, verify_with_keys};
+    use crate::dnssec::{Dnskey, Ds, Rrsig};
+    use data_encoding::BASE64;
+
+    #[test]
+    fn rsa_sha256_signature() {
+        // 別の実装 (Python の cryptography) で署名した 1024 ビット RSA の例
+        let dnskey = Dnskey {
+            flags: 256,
+            protocol: 3,
+            algorithm: 8,
+            public_key: BASE64.decode(b"AwEAAcvxZXfxsrNtJ94phpVSNbgKoSoK3Uoiv24KaJJ691XmrULmSGYD+UNCdVC56U4ILRxn0YrafFueuBvhtpDgAEnemy0BDIPmcjI3au2TTtimQYiXnKS/60A8tsxfcSya5DuxsWFIeratQhN3nHcVUaDHL/KHVeMTnU5sA7JIa+XH").unwrap(),
+        };
+        assert_eq!(dnskey.key_tag(), 13533);
+        let rrsig = Rrsig {
+            type_covered: 1,
+            algorithm: 8,
+            labels: 2,
+            original_ttl: 3600,
+            expiration: 2000000000,
+            inception: 1600000000,
+            key_tag: 13533,
+            signer_name: "example".to_string(),
+            signature: BASE64.decode(b"HcN7S42AirjUE61KDaB/ILTNdqrL8RXhyZF8CS0Y1sOeuuiOspYGEfMetI7+cpP5/PlHv/NSQsHpo6lb/Zk3Nal0+X0YM8w0+eAVSxEGdxx+yImSIK+tftumKis7jZjDYRXsYK0C9HZoM2HGi1IWhDgu3su3MpiBOkT7pGyzHW8=").unwrap(),
+        };
+        let records = vec![resource("WWW.Example", 1, &[192, 0, 2, 1])];
+        let rrset = &rrsets(&records)[0];
+
+        assert!(verify_rrset(rrset, &rrsig, &dnskey, 1700000000));
+        // 有効期間の外
+        assert!(!verify_rrset(rrset, &rrsig, &dnskey, 2100000000));
+        // 改ざんされたデータ
+        let tampered = vec![resource("www.example", 1, &[192, 0, 2, 2])];
+        assert!(!verify_rrset(
+            &rrsets(&tampered)[0],
+            &rrsig,
+            &dnskey,
+            1700000000
+        ));
+    }
+
+    #[test]
+    fn ecdsa_and_ed25519_signatures() {
+        for key in [
+            SigningKey::ecdsa_p256("example"),
+            SigningKey::ed25519("example", 7),
+        ] {
+            let mut records = vec![
+                resource("www.example", 1, &[192, 0, 2, 2]),
+                resource("www.example", 1, &[192, 0, 2, 1]),
+            ];
+            records.push(key.sign(&records));
+            let rrsets = rrsets(&records);
+            assert_eq!(rrsets.len(), 1);
+            assert_eq!(rrsets[0].rrsigs.len(), 1);
+            assert!(verify_with_keys(
+                &rrsets[0],
+                std::slice::from_ref(&key.dnskey),
+                "example",
+                super::now()
+            ));
+            // 署名者が違うゾーンの鍵では検証しない
+            assert!(!verify_with_keys(
+                &rrsets[0],
+                std::slice::from_ref(&key.dnskey),
+                "other",
+                super::now()
+            ));
+        }
+    }
+
+    #[test]
+    fn wildcard_expansion() {
+        let key = SigningKey::ed25519("example", 1);
+        let wildcard = vec![resource("*.example", 1, &[192, 0, 2, 1])];
+        let rrsig = key.sign(&wildcard);
+
+        // *.example の署名で host.sub.example への応答を検証する
+        let mut records = vec![resource("host.sub.example", 1, &[192, 0, 2, 1])];
+        records.push(resource("host.sub.example", 46, &rrsig.rdata));
+        assert!(verify_with_keys(
+            &rrsets(&records)[0],
+            std::slice::from_ref(&key.dnskey),
+            "example",
+            super::now()
+        ));
+    }
+
+    #[test]
+    fn dnskey_authenticated_by_ds() {
+        let key = SigningKey::ed25519("lab", 2);
+        let mut answers = vec![key.dnskey_record()];
+        answers.push(key.sign(&answers));
+
+        assert!(ds_matches("LAB", &key.dnskey, &key.ds()));
+        assert_eq!(
+            validate_dnskeys("lab", &answers, &[key.ds()], super::now()),
+            Some(vec![key.dnskey.clone()])
+        );
+
+        let other = SigningKey::ed25519("lab", 3);
+        assert_eq!(
+            validate_dnskeys("lab", &answers, &[other.ds()], super::now()),
+            None
+        );
+        let wrong_digest = Ds {
+            digest: vec![0; 32],
+            ..key.ds()
+        };
+        assert_eq!(
+            validate_dnskeys("lab", &answers, &[wrong_digest], super::now()),
+            None
+        );
+    }
+}
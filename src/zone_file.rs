@@ -0,0 +1,1036 @@
+use data_encoding::{BASE32HEX_NOPAD, BASE64, HEXUPPER_PERMISSIVE};
+use std::fs;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::dnssec::{encode_type_bitmaps, parse_time};
+use crate::message::{
+    class_from_name, encode_name, escape_label, read_name, type_from_name, Resource,
+};
+use crate::rdata::{svc_param_key_from_name, write_character_string, SvcParam, Svcb};
+
+/** $INCLUDE を入れ子にできる深さ。自分自身を読み込むようなループを止める */
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/**
+ * マスターファイル (RFC 1035 Section 5) を読み、レコードを書かれた順に返す。
+ * origin は最初の $ORIGIN (末尾のドットはあってもなくてもよい)。$INCLUDE のパスはカレントディレクトリから探す
+ */
+pub fn parse(text: &str, origin: &str) -> Option<Vec<Resource>> {
+    let mut parser = Parser::new(origin, PathBuf::from("."));
+    parser.parse(text, 0)?;
+    Some(parser.records)
+}
+
+/** path のマスターファイルを読む。$INCLUDE のパスは path のあるディレクトリから探す */
+pub fn load(path: &Path, origin: &str) -> io::Result<Vec<Resource>> {
+    let text = fs::read_to_string(path)?;
+    let directory = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+    let mut parser = Parser::new(origin, directory);
+    match parser.parse(&text, 0) {
+        Some(()) => Ok(parser.records),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} を解釈できません", path.display()),
+        )),
+    }
+}
+
+/** Resource の Display の表記 (絶対名で書かれた 1 つのレコード) を読む。TTL は省略できない */
+pub fn parse_record(text: &str) -> Option<Resource> {
+    let lines = tokenize(text)?;
+    match lines.as_slice() {
+        [line] if !line.indented && !line.tokens[0].text.starts_with('$') => {
+            Parser::new("", PathBuf::from(".")).record(line)
+        }
+        _ => None,
+    }
+}
+
+/** 空白で区切られた 1 つの項目。エスケープ (\X, \DDD) は解釈せずに残し、引用符は取り除く */
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+/** 括弧でつながった行をまとめた、1 つのエントリ */
+#[derive(Debug)]
+struct Line {
+    /** 最初の物理行の行番号 (1 から) */
+    number: usize,
+    /** 空白から始まる行は、直前のレコードと同じ所有者名を使う */
+    indented: bool,
+    tokens: Vec<Token>,
+}
+
+/** コメントと括弧を取り除き、エントリごとの項目に分ける */
+fn tokenize(text: &str) -> Option<Vec<Line>> {
+    let mut lines = Vec::new();
+    let mut number = 1;
+    let mut line = Line {
+        number,
+        indented: false,
+        tokens: Vec::new(),
+    };
+    let mut token: Option<Token> = None;
+    let mut depth = 0;
+    let mut line_start = true;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if line_start {
+            line.indented = c == ' ' || c == '\t';
+            line_start = false;
+        }
+        match c {
+            '\\' => {
+                let current = token.get_or_insert_with(|| Token {
+                    text: String::new(),
+                    quoted: false,
+                });
+                current.text.push(c);
+                current.text.push(chars.next()?);
+            }
+            '"' => {
+                let current = token.get_or_insert_with(|| Token {
+                    text: String::new(),
+                    quoted: true,
+                });
+                // 閉じる引用符までは空白や括弧、セミコロンもそのまま値に含める
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => {
+                            current.text.push('\\');
+                            current.text.push(chars.next()?);
+                        }
+                        Some(c) => {
+                            if c == '\n' {
+                                number += 1;
+                            }
+                            current.text.push(c);
+                        }
+                        None => {
+                            println!("{} 行目の引用符が閉じられていません", number);
+                            return None;
+                        }
+                    }
+                }
+            }
+            ';' => while chars.next_if(|c| *c != '\n').is_some() {},
+            '(' | ')' | ' ' | '\t' | '\r' | '\n' => {
+                line.tokens.extend(token.take());
+                match c {
+                    '(' => depth += 1,
+                    ')' if depth == 0 => {
+                        println!("{} 行目に対応しない閉じ括弧があります", number);
+                        return None;
+                    }
+                    ')' => depth -= 1,
+                    '\n' => {
+                        number += 1;
+                        // 括弧の中の改行はエントリの区切りにならない
+                        if depth == 0 {
+                            if !line.tokens.is_empty() {
+                                lines.push(line);
+                            }
+                            line = Line {
+                                number,
+                                indented: false,
+                                tokens: Vec::new(),
+                            };
+                            line_start = true;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            c => token
+                .get_or_insert_with(|| Token {
+                    text: String::new(),
+                    quoted: false,
+                })
+                .text
+                .push(c),
+        }
+    }
+
+    if depth > 0 {
+        println!("{} 行目からの括弧が閉じられていません", line.number);
+        return None;
+    }
+    line.tokens.extend(token);
+    if !line.tokens.is_empty() {
+        lines.push(line);
+    }
+    Some(lines)
+}
+
+/** ディレクティブと省略された項目の状態を持ちながら、エントリを順に読む */
+struct Parser {
+    /** 小文字にはしない・末尾のドットなし (ルートは空文字列) */
+    origin: String,
+    /** $TTL (RFC 2308) */
+    default_ttl: Option<u32>,
+    /** 直前のレコードの TTL と CLASS と所有者名 */
+    last_ttl: Option<u32>,
+    last_class: u16,
+    last_owner: Option<String>,
+    /** $INCLUDE の相対パスの起点 */
+    directory: PathBuf,
+    records: Vec<Resource>,
+}
+
+impl Parser {
+    fn new(origin: &str, directory: PathBuf) -> Self {
+        Self {
+            origin: origin.trim_end_matches('.').to_string(),
+            default_ttl: None,
+            last_ttl: None,
+            last_class: 1,
+            last_owner: None,
+            directory,
+            records: Vec::new(),
+        }
+    }
+
+    fn parse(&mut self, text: &str, depth: usize) -> Option<()> {
+        for line in tokenize(text)? {
+            let result = match line.tokens[0].text.as_str() {
+                directive if directive.starts_with('$') && !line.indented => {
+                    self.directive(&line.tokens, depth)
+                }
+                _ => self.record(&line).map(|record| self.records.push(record)),
+            };
+            if result.is_none() {
+                println!("{} 行目を解釈できません: {:?}", line.number, line.tokens);
+                return None;
+            }
+        }
+        Some(())
+    }
+
+    fn directive(&mut self, tokens: &[Token], depth: usize) -> Option<()> {
+        let mut fields = Fields::new(&tokens[1..]);
+        match tokens[0].text.to_ascii_uppercase().as_str() {
+            "$ORIGIN" => self.origin = fields.name(&self.origin)?,
+            "$TTL" => self.default_ttl = Some(parse_ttl(fields.text()?)?),
+            "$INCLUDE" => {
+                if depth >= MAX_INCLUDE_DEPTH {
+                    println!("$INCLUDE の入れ子が深すぎます");
+                    return None;
+                }
+                let path = self.directory.join(fields.text()?);
+                // 読み込んだファイルの中の $ORIGIN は、読み終えたら元に戻る
+                let origin = match fields.remaining() {
+                    0 => self.origin.clone(),
+                    _ => fields.name(&self.origin)?,
+                };
+                let text = match fs::read_to_string(&path) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        println!("{} を読めません: {:?}", path.display(), e);
+                        return None;
+                    }
+                };
+                let mut included = Parser {
+                    origin,
+                    default_ttl: self.default_ttl,
+                    last_ttl: self.last_ttl,
+                    last_class: self.last_class,
+                    last_owner: self.last_owner.clone(),
+                    directory: path.parent().unwrap_or(Path::new(".")).to_path_buf(),
+                    records: Vec::new(),
+                };
+                included.parse(&text, depth + 1)?;
+                self.records.append(&mut included.records);
+            }
+            _ => return None,
+        }
+        fields.end()
+    }
+
+    /** [<owner>] [<TTL>] [<class>] <type> <RDATA> (TTL と CLASS は順不同) */
+    fn record(&mut self, line: &Line) -> Option<Resource> {
+        let mut fields = Fields::new(&line.tokens);
+        let owner = if line.indented {
+            self.last_owner.clone()?
+        } else {
+            fields.name(&self.origin)?
+        };
+
+        let mut ttl = None;
+        let mut class = None;
+        let rr_type = loop {
+            let text = fields.text()?;
+            if ttl.is_none() && text.starts_with(|c: char| c.is_ascii_digit()) {
+                ttl = Some(parse_ttl(text)?);
+            } else if let (None, Some(value)) = (class, class_from_name(text)) {
+                class = Some(value);
+            } else {
+                break type_from_name(text)?;
+            }
+        };
+        let class = class.unwrap_or(self.last_class);
+
+        let rdata = if fields.peek().is_some_and(|t| t.text == "\\#" && !t.quoted) {
+            fields.text()?;
+            generic_rdata(rr_type, &mut fields)?
+        } else {
+            rdata(rr_type, &mut fields, &self.origin)?
+        };
+        fields.end()?;
+        if rdata.len() > usize::from(u16::MAX) {
+            return None;
+        }
+
+        // TTL を省略したら $TTL、それもなければ直前のレコードの TTL を使う
+        let ttl = match ttl.or(self.default_ttl).or(self.last_ttl) {
+            Some(ttl) => ttl,
+            // 最初の SOA で TTL がなければ、MINIMUM を使う (BIND と同じ)
+            None if rr_type == 6 => u32::from_be_bytes(rdata[rdata.len() - 4..].try_into().ok()?),
+            None => {
+                println!("TTL がわかりません ($TTL がありません)");
+                return None;
+            }
+        };
+        self.last_ttl = Some(ttl);
+        self.last_class = class;
+        self.last_owner = Some(owner.clone());
+        resource(&owner, rr_type, class, ttl, &rdata)
+    }
+}
+
+/** エントリの項目を先頭から順に読む */
+struct Fields<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn new(tokens: &'a [Token]) -> Self {
+        Self {
+            tokens,
+            position: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.peek()?;
+        self.position += 1;
+        Some(token)
+    }
+
+    fn remaining(&self) -> usize {
+        self.tokens.len() - self.position
+    }
+
+    /** 引用符で囲まれていない項目 */
+    fn text(&mut self) -> Option<&'a str> {
+        let token = self.next()?;
+        if token.quoted {
+            return None;
+        }
+        Some(&token.text)
+    }
+
+    fn number<T: FromStr>(&mut self) -> Option<T> {
+        self.text()?.parse().ok()
+    }
+
+    fn name(&mut self, origin: &str) -> Option<String> {
+        absolute_name(self.text()?, origin)
+    }
+
+    /** <character-string> (引用符はあってもなくてもよい) */
+    fn character_string(&mut self) -> Option<Vec<u8>> {
+        let string = decode(&self.next()?.text)?;
+        if string.len() > 255 {
+            return None;
+        }
+        Some(string)
+    }
+
+    /** 残りの項目をつなげたもの (空白で区切られた Base64 や 16 進数) */
+    fn rest(&mut self) -> Option<String> {
+        let mut rest = String::new();
+        while self.remaining() > 0 {
+            rest.push_str(self.text()?);
+        }
+        Some(rest)
+    }
+
+    fn end(&self) -> Option<()> {
+        if self.remaining() > 0 {
+            return None;
+        }
+        Some(())
+    }
+}
+
+/** タイプごとの表記から RDATA を組み立てる */
+fn rdata(rr_type: u16, fields: &mut Fields, origin: &str) -> Option<Vec<u8>> {
+    let mut rdata = Vec::new();
+    match rr_type {
+        1 => rdata.extend(fields.number::<Ipv4Addr>()?.octets()),
+        28 => rdata.extend(fields.number::<Ipv6Addr>()?.octets()),
+        2 | 5 | 12 => rdata.extend(encode_name(&fields.name(origin)?)?),
+        6 => {
+            rdata.extend(encode_name(&fields.name(origin)?)?);
+            rdata.extend(encode_name(&fields.name(origin)?)?);
+            rdata.extend(fields.number::<u32>()?.to_be_bytes());
+            // REFRESH, RETRY, EXPIRE, MINIMUM は 1h のような単位つきでも書ける
+            for _ in 0..4 {
+                rdata.extend(parse_ttl(fields.text()?)?.to_be_bytes());
+            }
+        }
+        13 => {
+            write_character_string(&mut rdata, &fields.character_string()?);
+            write_character_string(&mut rdata, &fields.character_string()?);
+        }
+        15 => {
+            rdata.extend(fields.number::<u16>()?.to_be_bytes());
+            rdata.extend(encode_name(&fields.name(origin)?)?);
+        }
+        16 => {
+            write_character_string(&mut rdata, &fields.character_string()?);
+            while fields.remaining() > 0 {
+                write_character_string(&mut rdata, &fields.character_string()?);
+            }
+        }
+        33 => {
+            for _ in 0..3 {
+                rdata.extend(fields.number::<u16>()?.to_be_bytes());
+            }
+            rdata.extend(encode_name(&fields.name(origin)?)?);
+        }
+        35 => {
+            rdata.extend(fields.number::<u16>()?.to_be_bytes());
+            rdata.extend(fields.number::<u16>()?.to_be_bytes());
+            for _ in 0..3 {
+                write_character_string(&mut rdata, &fields.character_string()?);
+            }
+            rdata.extend(encode_name(&fields.name(origin)?)?);
+        }
+        43 => {
+            rdata.extend(fields.number::<u16>()?.to_be_bytes());
+            rdata.push(fields.number()?);
+            rdata.push(fields.number()?);
+            rdata.extend(hex(&fields.rest()?)?);
+        }
+        44 => {
+            rdata.push(fields.number()?);
+            rdata.push(fields.number()?);
+            rdata.extend(hex(&fields.rest()?)?);
+        }
+        46 => {
+            rdata.extend(type_from_name(fields.text()?)?.to_be_bytes());
+            rdata.push(fields.number()?);
+            rdata.push(fields.number()?);
+            rdata.extend(fields.number::<u32>()?.to_be_bytes());
+            rdata.extend(parse_time(fields.text()?)?.to_be_bytes());
+            rdata.extend(parse_time(fields.text()?)?.to_be_bytes());
+            rdata.extend(fields.number::<u16>()?.to_be_bytes());
+            rdata.extend(encode_name(&fields.name(origin)?)?);
+            rdata.extend(BASE64.decode(fields.rest()?.as_bytes()).ok()?);
+        }
+        47 => {
+            rdata.extend(encode_name(&fields.name(origin)?)?);
+            rdata.extend(encode_type_bitmaps(&types(fields)?));
+        }
+        48 => {
+            rdata.extend(fields.number::<u16>()?.to_be_bytes());
+            rdata.push(fields.number()?);
+            rdata.push(fields.number()?);
+            rdata.extend(BASE64.decode(fields.rest()?.as_bytes()).ok()?);
+        }
+        50 | 51 => {
+            rdata.push(fields.number()?);
+            rdata.push(fields.number()?);
+            rdata.extend(fields.number::<u16>()?.to_be_bytes());
+            let salt = match fields.text()? {
+                "-" => Vec::new(),
+                salt => hex(salt)?,
+            };
+            write_character_string(&mut rdata, &salt);
+            if rr_type == 50 {
+                let hash = BASE32HEX_NOPAD
+                    .decode(fields.text()?.to_ascii_uppercase().as_bytes())
+                    .ok()?;
+                write_character_string(&mut rdata, &hash);
+                rdata.extend(encode_type_bitmaps(&types(fields)?));
+            }
+        }
+        52 => {
+            rdata.push(fields.number()?);
+            rdata.push(fields.number()?);
+            rdata.push(fields.number()?);
+            rdata.extend(hex(&fields.rest()?)?);
+        }
+        61 => rdata.extend(BASE64.decode(fields.rest()?.as_bytes()).ok()?),
+        64 | 65 => {
+            let priority = fields.number()?;
+            let target = fields.name(origin)?;
+            let mut params: Vec<SvcParam> = Vec::new();
+            while fields.remaining() > 0 {
+                let param = svc_param(&fields.next()?.text)?;
+                if params.iter().any(|p| p.key() == param.key()) {
+                    return None;
+                }
+                params.push(param);
+            }
+            rdata.extend(
+                Svcb {
+                    priority,
+                    target,
+                    params,
+                }
+                .to_rdata(),
+            );
+        }
+        257 => {
+            rdata.push(fields.number()?);
+            let tag = fields.text()?;
+            if tag.is_empty() || tag.len() > 255 || !tag.chars().all(|c| c.is_ascii_alphanumeric())
+            {
+                return None;
+            }
+            rdata.push(tag.len() as u8);
+            rdata.extend(tag.as_bytes());
+            rdata.extend(decode(&fields.next()?.text)?);
+        }
+        _ => {
+            println!("タイプ {} の表記は \\# の形式で書いてください", rr_type);
+            return None;
+        }
+    }
+    Some(rdata)
+}
+
+/** RFC 3597 Section 5 の \# <長さ> <16 進数> */
+fn generic_rdata(rr_type: u16, fields: &mut Fields) -> Option<Vec<u8>> {
+    let length: usize = fields.number()?;
+    let rdata = hex(&fields.rest()?)?;
+    if rdata.len() != length {
+        return None;
+    }
+    // 知っているタイプは、その形式として正しいものだけを受け付ける (名前は圧縮されていないこと)
+    let expected_length = match rr_type {
+        1 => 4,
+        28 => 16,
+        2 | 5 | 12 => read_name(&[], &rdata, 0)?.1,
+        15 => read_name(&[], &rdata, 2)?.1,
+        6 => read_name(&[], &rdata, read_name(&[], &rdata, 0)?.1)?.1 + 20,
+        _ => rdata.len(),
+    };
+    if expected_length != rdata.len() {
+        return None;
+    }
+    Some(rdata)
+}
+
+/** NSEC と NSEC3 の残りの項目のタイプ */
+fn types(fields: &mut Fields) -> Option<Vec<u16>> {
+    let mut types = Vec::new();
+    while fields.remaining() > 0 {
+        types.push(type_from_name(fields.text()?)?);
+    }
+    Some(types)
+}
+
+/** SVCB の key=value (RFC 9460 Appendix A)。値の引用符は字句解析で取り除かれている */
+fn svc_param(text: &str) -> Option<SvcParam> {
+    let (key, value) = match text.split_once('=') {
+        Some((key, value)) => (key, Some(decode(value)?)),
+        None => (text, None),
+    };
+    let key = svc_param_key_from_name(key)?;
+    let list = |value: &[u8]| -> Option<Vec<String>> {
+        Some(
+            std::str::from_utf8(value)
+                .ok()?
+                .split(',')
+                .map(str::to_string)
+                .collect(),
+        )
+    };
+
+    let mut wire = Vec::new();
+    match (key, value) {
+        (2, None) => {}
+        (0, Some(value)) => {
+            for name in list(&value)? {
+                wire.extend(svc_param_key_from_name(&name)?.to_be_bytes());
+            }
+        }
+        (1, Some(value)) => {
+            for id in split_value_list(&value) {
+                if id.len() > 255 {
+                    return None;
+                }
+                write_character_string(&mut wire, &id);
+            }
+        }
+        (3, Some(value)) => wire.extend(
+            std::str::from_utf8(&value)
+                .ok()?
+                .parse::<u16>()
+                .ok()?
+                .to_be_bytes(),
+        ),
+        (4, Some(value)) => {
+            for address in list(&value)? {
+                wire.extend(address.parse::<Ipv4Addr>().ok()?.octets());
+            }
+        }
+        (5, Some(value)) => wire.extend(BASE64.decode(&value).ok()?),
+        (6, Some(value)) => {
+            for address in list(&value)? {
+                wire.extend(address.parse::<Ipv6Addr>().ok()?.octets());
+            }
+        }
+        (0..=6, _) => return None,
+        (_, value) => wire.extend(value.unwrap_or_default()),
+    }
+    SvcParam::parse(key, &wire)
+}
+
+/** カンマ区切りの値。値の中の , と \ は \ でエスケープされている */
+fn split_value_list(value: &[u8]) -> Vec<Vec<u8>> {
+    let mut items = vec![Vec::new()];
+    let mut bytes = value.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'\\' => items.last_mut().unwrap().extend(bytes.next()),
+            b',' => items.push(Vec::new()),
+            _ => items.last_mut().unwrap().push(byte),
+        }
+    }
+    items
+}
+
+/** \X と \DDD のエスケープを解釈したバイト列 */
+fn decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => chars.next()?,
+            c => {
+                bytes.extend(c.to_string().as_bytes());
+                continue;
+            }
+        };
+        match c.to_digit(10) {
+            Some(hundreds) => {
+                let tens = chars.next()?.to_digit(10)?;
+                let ones = chars.next()?.to_digit(10)?;
+                bytes.push(u8::try_from(hundreds * 100 + tens * 10 + ones).ok()?);
+            }
+            None => bytes.extend(c.to_string().as_bytes()),
+        }
+    }
+    Some(bytes)
+}
+
+/**
+ * 名前の表記を絶対名にする。"@" は origin、末尾がドットでなければ origin からの相対名。
+ * ラベルの中のドットや表示できないバイトは、message::escape_label のエスケープで名前の文字列に残す
+ */
+fn absolute_name(text: &str, origin: &str) -> Option<String> {
+    if text == "@" {
+        return Some(origin.to_string());
+    }
+    if text == "." {
+        return Some(String::new());
+    }
+
+    // エスケープされていないドットで区切る
+    let mut raw_labels = vec![String::new()];
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let label = raw_labels.last_mut().unwrap();
+                label.push(c);
+                label.push(chars.next()?);
+            }
+            '.' => raw_labels.push(String::new()),
+            c => raw_labels.last_mut().unwrap().push(c),
+        }
+    }
+    let absolute = raw_labels.len() > 1 && raw_labels.last().unwrap().is_empty();
+    if absolute {
+        raw_labels.pop();
+    }
+
+    let mut labels = Vec::new();
+    for raw_label in raw_labels {
+        let label = decode(&raw_label)?;
+        if label.is_empty() || label.len() > 63 {
+            return None;
+        }
+        labels.push(escape_label(&label));
+    }
+
+    let mut name = labels.join(".");
+    if !absolute && !origin.is_empty() {
+        name = format!("{}.{}", name, origin);
+    }
+    encode_name(&name)?;
+    Some(name)
+}
+
+/** TTL の表記。秒数のほか、BIND と同じく 1h30m のような単位 (w, d, h, m, s) も使える */
+fn parse_ttl(text: &str) -> Option<u32> {
+    if let Ok(ttl) = text.parse() {
+        return Some(ttl);
+    }
+    let mut total: u64 = 0;
+    let mut value: Option<u64> = None;
+    for c in text.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            value = Some(value.unwrap_or(0).checked_mul(10)? + u64::from(digit));
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            'w' => 604800,
+            'd' => 86400,
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        total = total.checked_add(value.take()?.checked_mul(unit)?)?;
+    }
+    if value.is_some() {
+        return None;
+    }
+    u32::try_from(total).ok()
+}
+
+fn hex(text: &str) -> Option<Vec<u8>> {
+    HEXUPPER_PERMISSIVE.decode(text.as_bytes()).ok()
+}
+
+/** ワイヤ形式に書き出して読み直し、タイプ別のフィールドも埋めたレコードにする */
+fn resource(owner: &str, rr_type: u16, class: u16, ttl: u32, rdata: &[u8]) -> Option<Resource> {
+    let mut bytes = encode_name(owner)?;
+    bytes.extend(rr_type.to_be_bytes());
+    bytes.extend(class.to_be_bytes());
+    bytes.extend(ttl.to_be_bytes());
+    bytes.extend((rdata.len() as u16).to_be_bytes());
+    bytes.extend(rdata);
+    Resource::parse(&bytes, &bytes, 1)?.pop()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{absolute_name, load, parse, parse_record, parse_ttl};
+    use crate::message::type_name;
+    use crate::rdata::SvcParam;
+    use std::fs;
+    use std::net::IpAddr;
+
+    /** message が扱えるすべてのタイプのレコード ($ORIGIN は example) */
+    const ALL_TYPES: &str = "$TTL 300\n\
+        4.3.2.1.in-addr.arpa. PTR host\n\
+        host HINFO \"x86-64\" Linux\n\
+        _sip._tcp SRV 10 60 5060 sip\n\
+        sip NAPTR 100 10 \"S\" \"SIP+D2T\" \"\" _sip._tcp\n\
+        @ CAA 128 issue \"ca.example.net; account=1\"\n\
+        host SSHFP 4 2 123456789abcdef67890123456789abcdef67890123456789abcdef123456789\n\
+        _443._tcp TLSA 3 1 1 ( 0C72AC70B745AC19998811B131D662C9\n\
+                               AC69DBDBE7CB23E5B514B56664C5D3D6 )\n\
+        key OPENPGPKEY AQID\n\
+        @ HTTPS 1 . alpn=\"h2,h3\" port=8443 ipv4hint=192.0.2.1,192.0.2.2 key65000=\"a b\"\n\
+        alias SVCB 0 svc.example.net.\n\
+        @ DS 60485 5 1 2BB183AF5F22588179A53B0A 98631FAD1A292118\n\
+        @ DNSKEY 256 3 5 AQOeiiR0GOMYkDshWoSKz9Xz fwJr1AYtsmx3TGkJaNXVbfi/\n\
+        @ RRSIG A 5 2 86400 20040509183619 1081539377 2642 example. oJB1W6WNGv+ldvQ3WDG0MQkg\n\
+        @ NSEC host.example. A NS SOA MX RRSIG NSEC DNSKEY TYPE1234\n\
+        2vptu5timamqttgl4luu9kg21e0aor3s NSEC3 1 1 12 AABBCCDD 2T7B4G4VSA5SMI47K61MV5BV1A22BOJR MX DNSKEY NS SOA NSEC3PARAM RRSIG\n\
+        @ NSEC3PARAM 1 0 0 -\n\
+        raw TYPE65280 \\# 3 ABCDEF\n\
+        raw2 A \\# 4 C0000201\n";
+
+    #[test]
+    fn rfc1035_style_zone() {
+        let records = parse(
+            "$ORIGIN Example.COM.\n\
+             $TTL 1h\n\
+             @   IN  SOA ns1 hostmaster.example.com. (\n\
+                     2024010101 ; serial\n\
+                     7200       ; refresh\n\
+                     1h 2w      ; retry, expire\n\
+                     300 )      ; minimum\n\
+             \tNS  ns1\n\
+             \t86400 MX 10 mail\n\
+             ns1 A 192.0.2.53\n\
+             \tIN 60 AAAA 2001:db8::53\n\
+             www CNAME @\n\
+             txt TXT \"v=spf1 -all\" two\\ words \"say \\\"hi\\\"\\059\" \\255\n\
+             $ORIGIN sub\n\
+             host A 192.0.2.1\n\
+             abs.example.net. A 192.0.2.2\n",
+            ".",
+        )
+        .unwrap();
+
+        let summary: Vec<(String, String, u32)> = records
+            .iter()
+            .map(|r| (r.name.clone(), type_name(r.rr_type), r.ttl))
+            .collect();
+        let expected = [
+            ("Example.COM", "SOA", 3600),
+            ("Example.COM", "NS", 3600),
+            ("Example.COM", "MX", 86400),
+            ("ns1.Example.COM", "A", 3600),
+            ("ns1.Example.COM", "AAAA", 60),
+            ("www.Example.COM", "CNAME", 3600),
+            ("txt.Example.COM", "TXT", 3600),
+            ("host.sub.Example.COM", "A", 3600),
+            ("abs.example.net", "A", 3600),
+        ];
+        assert_eq!(
+            summary,
+            expected
+                .iter()
+                .map(|(name, rr_type, ttl)| (name.to_string(), rr_type.to_string(), *ttl))
+                .collect::<Vec<_>>()
+        );
+
+        let soa = &records[0];
+        assert_eq!(
+            (soa.mname.as_str(), soa.rname.as_str()),
+            ("ns1.Example.COM", "hostmaster.example.com")
+        );
+        assert_eq!(
+            (soa.serial, soa.refresh, soa.retry, soa.expire, soa.minimum),
+            (2024010101, 7200, 3600, 1209600, 300)
+        );
+        assert_eq!(records[1].nsdname, "ns1.Example.COM");
+        assert_eq!(
+            (records[2].preference, records[2].exchange.as_str()),
+            (10, "mail.Example.COM")
+        );
+        assert_eq!(
+            records[4].address,
+            Some("2001:db8::53".parse::<IpAddr>().unwrap())
+        );
+        assert_eq!(records[5].cname, "Example.COM");
+        assert_eq!(
+            records[6].txt.as_ref().unwrap().strings,
+            vec![
+                b"v=spf1 -all".to_vec(),
+                b"two words".to_vec(),
+                b"say \"hi\";".to_vec(),
+                vec![255]
+            ]
+        );
+    }
+
+    #[test]
+    fn every_supported_type() {
+        let records = parse(ALL_TYPES, "example").unwrap();
+        assert_eq!(records.len(), 18);
+        let by_type = |rr_type: u16| records.iter().find(|r| r.rr_type == rr_type).unwrap();
+
+        assert_eq!(by_type(12).name, "4.3.2.1.in-addr.arpa");
+        assert_eq!(by_type(12).ptrdname, "host.example");
+        assert_eq!(by_type(13).hinfo.as_ref().unwrap().os, b"Linux");
+        let srv = by_type(33).srv.as_ref().unwrap();
+        assert_eq!((srv.port, srv.target.as_str()), (5060, "sip.example"));
+        let naptr = by_type(35).naptr.as_ref().unwrap();
+        assert_eq!(
+            (
+                naptr.services.as_slice(),
+                naptr.regexp.as_slice(),
+                naptr.replacement.as_str()
+            ),
+            (&b"SIP+D2T"[..], &b""[..], "_sip._tcp.example")
+        );
+        let caa = by_type(257).caa.as_ref().unwrap();
+        assert!(caa.is_critical());
+        assert_eq!(caa.value, b"ca.example.net; account=1");
+        assert_eq!(by_type(44).sshfp.as_ref().unwrap().fingerprint.len(), 32);
+        assert_eq!(by_type(52).tlsa.as_ref().unwrap().data.len(), 32);
+        assert_eq!(
+            by_type(61).openpgpkey.as_ref().unwrap().public_key,
+            vec![1, 2, 3]
+        );
+
+        let https = by_type(65).svcb.as_ref().unwrap();
+        assert_eq!(https.target, "");
+        assert_eq!(
+            https.params,
+            vec![
+                SvcParam::Alpn(vec![b"h2".to_vec(), b"h3".to_vec()]),
+                SvcParam::Port(8443),
+                SvcParam::Ipv4Hint(vec![
+                    "192.0.2.1".parse().unwrap(),
+                    "192.0.2.2".parse().unwrap()
+                ]),
+                SvcParam::Unknown(65000, b"a b".to_vec()),
+            ]
+        );
+        assert!(by_type(64).svcb.as_ref().unwrap().is_alias());
+
+        assert_eq!(by_type(43).ds.as_ref().unwrap().digest.len(), 20);
+        assert_eq!(by_type(48).dnskey.as_ref().unwrap().flags, 256);
+        let rrsig = by_type(46).rrsig.as_ref().unwrap();
+        assert_eq!(
+            (
+                rrsig.type_covered,
+                rrsig.expiration,
+                rrsig.inception,
+                rrsig.signer_name.as_str()
+            ),
+            (1, 1084127779, 1081539377, "example")
+        );
+        assert_eq!(
+            by_type(47).nsec.as_ref().unwrap().types,
+            vec![1, 2, 6, 15, 46, 47, 48, 1234]
+        );
+        let nsec3 = by_type(50).nsec3.as_ref().unwrap();
+        assert_eq!(
+            (nsec3.iterations, nsec3.salt.as_slice()),
+            (12, &[0xAA, 0xBB, 0xCC, 0xDD][..])
+        );
+        assert_eq!(nsec3.next_hashed_owner.len(), 20);
+        assert!(by_type(51).nsec3param.as_ref().unwrap().salt.is_empty());
+        assert_eq!(by_type(65280).rdata, vec![0xAB, 0xCD, 0xEF]);
+        assert_eq!(
+            records[17].address,
+            Some("192.0.2.1".parse::<IpAddr>().unwrap())
+        );
+    }
+
+    #[test]
+    fn records_round_trip_through_presentation_format() {
+        let mut records = parse(ALL_TYPES, "example").unwrap();
+        records.extend(
+            parse(
+                "$TTL 0\n\
+                 a\\(b\\032c CH TXT \"\" \"tab\\009\"\n\
+                 . CLASS42 TYPE7 \\# 0\n\
+                 @ SOA . . 1 2 3 4 5\n\
+                 a\\.b\\\\c\\195\\169 CH CNAME w\\.x\n",
+                "example",
+            )
+            .unwrap(),
+        );
+
+        for record in &records {
+            let text = record.to_string();
+            let parsed = parse_record(&text).unwrap_or_else(|| panic!("{}", text));
+            assert_eq!(parsed.to_byte(), record.to_byte(), "{}", text);
+            assert_eq!(parsed.to_string(), text);
+        }
+        assert_eq!(records[17].to_string(), "raw2.example. 300 IN A 192.0.2.1");
+        assert_eq!(
+            records[18].to_string(),
+            "a\\(b\\032c.example. 0 CH TXT \"\" \"tab\\009\""
+        );
+        assert_eq!(records[19].to_string(), ". 0 CLASS42 TYPE7 \\# 0");
+        // ラベルの中のドット、'\'、ASCII 以外のバイトはエスケープして書き戻す
+        assert_eq!(
+            records[21].to_string(),
+            "a\\.b\\\\c\\195\\169.example. 0 CH CNAME w\\.x.example."
+        );
+        assert_eq!(&records[21].to_byte()[..8], b"\x07a.b\\c\xC3\xA9");
+
+        // TTL の省略、ディレクティブ、複数のレコードは受け付けない
+        assert!(parse_record("www IN A 192.0.2.1").is_none());
+        assert!(parse_record("$TTL 60").is_none());
+        assert!(parse_record("a. 60 A 192.0.2.1\nb. 60 A 192.0.2.2").is_none());
+    }
+
+    #[test]
+    fn include_switches_origin_temporarily() {
+        let dir = std::env::temp_dir().join(format!("resolver-zone-{}", std::process::id()));
+        fs::create_dir_all(dir.join("hosts")).unwrap();
+        fs::write(
+            dir.join("example.zone"),
+            "$TTL 60\n\
+             $INCLUDE hosts/lab.inc lab\n\
+             www A 192.0.2.1\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("hosts/lab.inc"),
+            "db A 192.0.2.10\n\
+             $ORIGIN other.example.\n\
+             x A 192.0.2.11\n",
+        )
+        .unwrap();
+
+        let names: Vec<String> = load(&dir.join("example.zone"), "example.")
+            .unwrap()
+            .into_iter()
+            .map(|r| r.name)
+            .collect();
+        assert_eq!(
+            names,
+            vec!["db.lab.example", "x.other.example", "www.example"]
+        );
+
+        fs::write(dir.join("loop.zone"), "$INCLUDE loop.zone\n").unwrap();
+        assert!(load(&dir.join("loop.zone"), "example").is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn malformed_entries_are_rejected() {
+        let rejected = [
+            // TTL が決まらない
+            "www A 192.0.2.1\n",
+            "$TTL 60\nwww A 192.0.2.1 extra\n",
+            "$TTL 60\nwww A ( 192.0.2.1\n",
+            "$TTL 60\nwww A 192.0.2.1 )\n",
+            "$TTL 60\nwww TXT \"unterminated\n",
+            // 直前のレコードがないのに所有者名を省略している
+            "$TTL 60\n A 192.0.2.1\n",
+            // 表記を知らないタイプ
+            "$TTL 60\nwww TYPE65280 abc\n",
+            "$TTL 60\nwww A \\# 3 C00002\n",
+            "$TTL 60\nwww CNAME \\# 2 C00C\n",
+            "$TTL 60\nwww HTTPS 1 . port=x\n",
+            "$TTL 60\nwww HTTPS 1 . alpn=h2 alpn=h3\n",
+            "$TTL 60\nwww TXT \\256\n",
+            "$TTL 60\n$GENERATE 1-2 host$ A 192.0.2.$\n",
+        ];
+        for text in rejected {
+            assert!(parse(text, "example").is_none(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn names_and_ttls() {
+        assert_eq!(absolute_name("@", "example").unwrap(), "example");
+        assert_eq!(absolute_name(".", "example").unwrap(), "");
+        assert_eq!(absolute_name("www", "").unwrap(), "www");
+        assert_eq!(absolute_name("w\\119w", "example").unwrap(), "www.example");
+        assert_eq!(absolute_name("a\\.b", "example").unwrap(), "a\\.b.example");
+        assert_eq!(absolute_name("caf\\195\\169", "").unwrap(), "caf\\195\\169");
+        assert_eq!(absolute_name("café", "").unwrap(), "caf\\195\\169");
+        assert_eq!(absolute_name("a\\032b\\\\", "").unwrap(), "a\\032b\\\\");
+        assert_eq!(absolute_name("a..b", "example"), None);
+        assert_eq!(absolute_name(&"a".repeat(64), "example"), None);
+
+        assert_eq!(parse_ttl("3600"), Some(3600));
+        assert_eq!(parse_ttl("1w2d3h4m5s"), Some(788645));
+        assert_eq!(parse_ttl("1H"), Some(3600));
+        assert_eq!(parse_ttl("10x"), None);
+        assert_eq!(parse_ttl("h"), None);
+        assert_eq!(parse_ttl("5000000000"), None);
+    }
+}